@@ -0,0 +1,16 @@
+//! Runs the instruction sequences made by the fuzzer on both the cycle-level
+//! core and the reference model of `tests/reference`, see
+//! `tests/differential.rs` for the offline version.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../tests/reference/mod.rs"]
+mod reference;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(msg) = reference::compare(data) {
+        panic!("{msg}");
+    }
+});
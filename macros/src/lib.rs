@@ -0,0 +1,193 @@
+//! Compile-time 6502 assembler for embedding test programs.
+//!
+//! See [`asm6502!`] for details.
+
+use fast6502::asm::Assembler;
+use proc_macro::{Delimiter, Group, Literal, Punct, Spacing, TokenStream, TokenTree};
+
+/// Assembles 6502 source at compile time into a `[u8; N]` array.
+///
+/// Takes a string literal with the source, using the syntax of the
+/// `fast6502::asm` module, optionally preceded by `illegal,` to enable
+/// illegal opcodes. The array holds every assembled byte from the lowest
+/// address to the highest, with any gaps between `.org` segments filled with
+/// zero. Assembly errors are reported as compile errors.
+///
+/// ```
+/// use fast6502_macros::asm6502;
+///
+/// const PROGRAM: [u8; 5] = asm6502!(
+///     "
+///     lda #$42
+///     sta $10
+///     brk
+///     "
+/// );
+/// assert_eq!(PROGRAM, [0xa9, 0x42, 0x85, 0x10, 0x00]);
+/// ```
+///
+/// Illegal opcodes need the `illegal,` option, and raw strings work too:
+///
+/// ```
+/// use fast6502_macros::asm6502;
+///
+/// const PROGRAM: [u8; 3] = asm6502!(illegal, r#"
+///     lax $10 ; "load A and X"
+///     nop
+/// "#);
+/// assert_eq!(PROGRAM, [0xa7, 0x10, 0xea]);
+/// ```
+///
+/// Unknown options are rejected:
+///
+/// ```compile_fail
+/// use fast6502_macros::asm6502;
+///
+/// const PROGRAM: [u8; 1] = asm6502!(undocumented, "nop");
+/// ```
+///
+/// And so is source that does not assemble:
+///
+/// ```compile_fail
+/// use fast6502_macros::asm6502;
+///
+/// const PROGRAM: [u8; 2] = asm6502!("lda ($10");
+/// ```
+#[proc_macro]
+pub fn asm6502(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(bytes) => bytes,
+        Err(msg) => compile_error(&msg),
+    }
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter().peekable();
+    let mut assembler = Assembler::new();
+
+    if let Some(TokenTree::Ident(ident)) = tokens.peek() {
+        if ident.to_string() != "illegal" {
+            return Err(format!("unknown option `{ident}`"));
+        }
+        assembler.set_illegal(true);
+        tokens.next();
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ',' => {}
+            _ => return Err("expected `,` after `illegal`".to_string()),
+        }
+    }
+
+    let src = match tokens.next() {
+        Some(TokenTree::Literal(lit)) => unquote(&lit.to_string())?,
+        _ => return Err("expected a string literal with the assembly source".to_string()),
+    };
+    if tokens.next().is_some() {
+        return Err("unexpected tokens after the assembly source".to_string());
+    }
+
+    let program = assembler.assemble(&src).map_err(|e| e.to_string())?;
+    let (_, image) = program.to_image();
+
+    let mut bytes = TokenStream::new();
+    for byte in image {
+        bytes.extend([
+            TokenTree::Literal(Literal::u8_suffixed(byte)),
+            TokenTree::Punct(Punct::new(',', Spacing::Alone)),
+        ]);
+    }
+    Ok(TokenTree::Group(Group::new(Delimiter::Bracket, bytes)).into())
+}
+
+/// Returns the contents of a string literal as written in the source.
+fn unquote(lit: &str) -> Result<String, String> {
+    if let Some(raw) = lit.strip_prefix('r') {
+        let hashes = raw.len() - raw.trim_start_matches('#').len();
+        return raw[hashes..raw.len() - hashes]
+            .strip_prefix('"')
+            .and_then(|s| s.strip_suffix('"'))
+            .map(str::to_string)
+            .ok_or_else(|| "expected a string literal".to_string());
+    }
+
+    let Some(body) = lit.strip_prefix('"').and_then(|s| s.strip_suffix('"')) else {
+        return Err("expected a string literal".to_string());
+    };
+
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('0') => out.push('\0'),
+            Some(c @ ('\\' | '\'' | '"')) => out.push(c),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16).map_err(|e| e.to_string())?;
+                out.push(char::from(byte));
+            }
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                let code = u32::from_str_radix(&code, 16).map_err(|e| e.to_string())?;
+                out.push(char::from_u32(code).ok_or("invalid unicode escape")?);
+            }
+            Some('\n') => {
+                // Line continuation, skip leading whitespace on the next line.
+                let rest: String = chars.collect();
+                out.push_str(&unquote(&format!("\"{}\"", rest.trim_start()))?);
+                break;
+            }
+            _ => return Err("invalid escape in string literal".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("::core::compile_error!({msg:?})")
+        .parse()
+        .expect("compile_error invocation should parse")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes() {
+        assert_eq!(unquote(r#""lda #1""#).unwrap(), "lda #1");
+        assert_eq!(unquote(r#""\n\r\t\0\\\'\"""#).unwrap(), "\n\r\t\0\\'\"");
+        assert_eq!(unquote(r#""\x41\x7e""#).unwrap(), "A~");
+        assert_eq!(unquote(r#""\u{41}\u{263A}""#).unwrap(), "A\u{263a}");
+    }
+
+    #[test]
+    fn line_continuation() {
+        assert_eq!(
+            unquote("\"nop\\\n      brk\\\n\trts\"").unwrap(),
+            "nopbrkrts"
+        );
+    }
+
+    #[test]
+    fn raw_strings() {
+        assert_eq!(unquote(r#"r"a\n""#).unwrap(), "a\\n");
+        assert_eq!(unquote(r##"r#"say "hi""#"##).unwrap(), r#"say "hi""#);
+        assert_eq!(unquote(r###"r##"a"#b"##"###).unwrap(), r##"a"#b"##);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(unquote("42").is_err());
+        assert!(unquote("'a'").is_err());
+        assert!(unquote(r#""\q""#).is_err());
+        assert!(unquote(r#""\xZZ""#).is_err());
+        assert!(unquote(r#""\u{D800}""#).is_err());
+        assert!(unquote(r#""\u{110000}""#).is_err());
+    }
+}
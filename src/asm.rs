@@ -0,0 +1,1332 @@
+//! Two-pass assembler for 6502 assembly.
+//!
+//! Meant for writing test programs and small patches without having to
+//! resort to raw bytes. The instruction set is taken from [`OPCODE_INFO`](crate::OPCODE_INFO) so
+//! the assembler always agrees with the emulator about which opcode is which.
+//!
+//! # Syntax
+//!
+//! Every line holds at most one statement, optionally preceded by a label and
+//! followed by a comment starting with `;`. Mnemonics, directives and the
+//! register names are case-insensitive while symbols are not.
+//!
+//! ```text
+//! count = $10             ; Constant definition.
+//!         .org $0200      ; Set the address of the following code.
+//! start:  ldx #count      ; Global label.
+//! @loop:  lda table-1,x   ; Local label, scoped to the last global label.
+//!         sta ($20),y
+//!         dex
+//!         bne @loop
+//!         jmp (vector)
+//! table:  .byte 1, 2, "text", <start, >start
+//! vector: .word start, * + 2
+//! ```
+//!
+//! Operands use the standard syntax, i.e. `#value`, `addr`, `addr,X`,
+//! `addr,Y`, `(addr)`, `(addr,X)`, `(addr),Y` and `A`. Zero page addressing
+//! is chosen automatically when the address is known to fit in the first
+//! pass, otherwise absolute addressing is used.
+//!
+//! Expressions support decimal, `$hex`, `%binary` and `'c'` character
+//! numbers, symbols, `*` for the address of the current statement, the unary
+//! operators `-`, `~`, `<` (low byte) and `>` (high byte), the binary
+//! operators `*`, `/`, `%`, `+`, `-`, `<<`, `>>`, `&`, `^` and `|` with the
+//! usual precedence, and parentheses. An operand completely enclosed in
+//! parentheses is taken as indirect addressing.
+//!
+//! Constants may refer to symbols defined later on, in which case `*` is the
+//! address of the constant definition. Local labels belong to the global
+//! label before them, which includes one on the same line.
+//!
+//! The directives are `.org`, `.byte` (or `.db`) and `.word` (or `.dw`).
+//! Illegal opcodes can be used after enabling them with
+//! [`Assembler::set_illegal`]. Only the NMOS 6502 instruction set is
+//! available, the 65C02 additions are not since the emulator does not model
+//! that processor.
+//!
+//! Requires feature `alloc`.
+
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+
+use crate::{memory::Memory, AddressingMode, Mnemonic, Opcode};
+
+extern crate alloc;
+
+/// An error found while assembling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// The line the error was found on, starting at 1.
+    pub line: usize,
+    /// What went wrong.
+    pub kind: ErrorKind,
+}
+
+/// The kind of an [`Error`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The line could not be parsed.
+    Syntax(String),
+    /// Not a known mnemonic or directive.
+    UnknownMnemonic(String),
+    /// The instruction does not support the addressing mode.
+    InvalidAddressingMode(Mnemonic),
+    /// The instruction is only available as an illegal opcode, which has not
+    /// been enabled.
+    IllegalOpcode(Mnemonic),
+    /// A symbol is used but never defined.
+    UndefinedSymbol(String),
+    /// A symbol is defined more than once.
+    DuplicateSymbol(String),
+    /// A value does not fit where it is used.
+    ValueOutOfRange(i64),
+    /// A branch target is too far away.
+    BranchOutOfRange(i64),
+    /// The program does not fit inside the address space.
+    AddressOverflow,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ErrorKind::Syntax(msg) => write!(f, "syntax error, {msg}"),
+            ErrorKind::UnknownMnemonic(name) => write!(f, "unknown mnemonic `{name}`"),
+            ErrorKind::InvalidAddressingMode(m) => {
+                write!(f, "addressing mode not supported by {m}")
+            }
+            ErrorKind::IllegalOpcode(m) => write!(f, "{m} is an illegal opcode"),
+            ErrorKind::UndefinedSymbol(name) => write!(f, "undefined symbol `{name}`"),
+            ErrorKind::DuplicateSymbol(name) => write!(f, "symbol `{name}` already defined"),
+            ErrorKind::ValueOutOfRange(v) => write!(f, "value {v} out of range"),
+            ErrorKind::BranchOutOfRange(v) => write!(f, "branch offset {v} out of range"),
+            ErrorKind::AddressOverflow => write!(f, "program passes the end of memory"),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// A contiguous block of assembled bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    /// The address of the first byte.
+    pub origin: u16,
+    /// The assembled bytes.
+    pub data: Vec<u8>,
+}
+
+/// The result of assembling a source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+    segments: Vec<Segment>,
+    symbols: BTreeMap<String, u16>,
+}
+
+impl Program {
+    /// Returns the assembled segments, in source order. Each `.org`
+    /// directive starts a new segment.
+    #[inline]
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns every symbol defined by the source. Local labels are named
+    /// `global@local`.
+    #[inline]
+    pub fn symbols(&self) -> &BTreeMap<String, u16> {
+        &self.symbols
+    }
+
+    /// Returns the value of the symbol `name`.
+    #[inline]
+    pub fn symbol(&self, name: &str) -> Option<u16> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Writes every segment to memory using [`set`].
+    ///
+    /// [`set`]: Memory::set
+    pub fn write_to<M: Memory + ?Sized>(&self, mem: &mut M) {
+        for segment in &self.segments {
+            mem.set_slice(segment.origin, &segment.data);
+        }
+    }
+
+    /// Returns the segments flattened into a single image starting at the
+    /// lowest assembled address, with any gaps filled with zero. Returns the
+    /// address of the first byte together with the image.
+    pub fn to_image(&self) -> (u16, Vec<u8>) {
+        let used = self.segments.iter().filter(|s| !s.data.is_empty());
+        let Some(start) = used.clone().map(|s| s.origin).min() else {
+            return (0, Vec::new());
+        };
+        let end = used
+            .map(|s| usize::from(s.origin) + s.data.len())
+            .max()
+            .unwrap_or(0);
+
+        let mut image = alloc::vec![0; end - usize::from(start)];
+        for segment in &self.segments {
+            let offset = usize::from(segment.origin - start);
+            image[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        (start, image)
+    }
+}
+
+/// The assembler, holding the options and predefined symbols.
+#[derive(Debug, Clone, Default)]
+pub struct Assembler {
+    illegal: bool,
+    symbols: BTreeMap<String, u16>,
+}
+
+impl Assembler {
+    /// Returns a new assembler with illegal opcodes disabled.
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    /// Returns whether illegal opcodes are accepted.
+    #[inline]
+    pub fn illegal(&self) -> bool {
+        self.illegal
+    }
+
+    /// Sets whether illegal opcodes are accepted.
+    #[inline]
+    pub fn set_illegal(&mut self, illegal: bool) {
+        self.illegal = illegal;
+    }
+
+    /// Defines a symbol available to every source assembled.
+    pub fn define(&mut self, name: &str, value: u16) {
+        self.symbols.insert(name.to_string(), value);
+    }
+
+    /// Assembles `src` into a [`Program`].
+    pub fn assemble(&self, src: &str) -> Result<Program, Error> {
+        let stmts = parse(src)?;
+
+        let mut symbols: BTreeMap<String, i64> = self
+            .symbols
+            .iter()
+            .map(|(name, &v)| (name.clone(), i64::from(v)))
+            .collect();
+        let sizes = self.first_pass(&stmts, &mut symbols)?;
+        self.second_pass(&stmts, &sizes, &symbols)
+    }
+
+    /// Assembles `src` and writes the result to memory using [`set`].
+    ///
+    /// [`set`]: Memory::set
+    pub fn assemble_into<M: Memory + ?Sized>(
+        &self,
+        src: &str,
+        mem: &mut M,
+    ) -> Result<Program, Error> {
+        let program = self.assemble(src)?;
+        program.write_to(mem);
+        Ok(program)
+    }
+
+    /// Assigns an address to every label and decides the encoding of every
+    /// instruction, returning the chosen opcodes.
+    fn first_pass(
+        &self,
+        stmts: &[Line],
+        symbols: &mut BTreeMap<String, i64>,
+    ) -> Result<Vec<Option<Opcode>>, Error> {
+        let mut pc: i64 = 0;
+        let mut opcodes = Vec::with_capacity(stmts.len());
+        let mut deferred = Vec::new();
+
+        for line in stmts {
+            let err = |kind| Error {
+                line: line.number,
+                kind,
+            };
+
+            if let Some(label) = &line.label {
+                define(symbols, &deferred, label, pc).map_err(err)?;
+            }
+
+            let mut opcode = None;
+            match &line.stmt {
+                Stmt::None => {}
+                Stmt::Equ(name, expr) => match expr.eval(symbols, pc) {
+                    Ok(value) => define(symbols, &deferred, name, value).map_err(err)?,
+                    Err(_) => {
+                        unique(symbols, &deferred, name).map_err(err)?;
+                        deferred.push(Deferred {
+                            line: line.number,
+                            name,
+                            expr,
+                            pc,
+                        });
+                    }
+                },
+                Stmt::Org(expr) => {
+                    pc = expr.eval(symbols, pc).map_err(err)?;
+                    if !(0..=0xffff).contains(&pc) {
+                        return Err(err(ErrorKind::ValueOutOfRange(pc)));
+                    }
+                }
+                Stmt::Bytes(items) => {
+                    pc += items.iter().map(|item| item.len() as i64).sum::<i64>();
+                }
+                Stmt::Words(exprs) => pc += 2 * exprs.len() as i64,
+                Stmt::Instr(mnemonic, operand) => {
+                    let op = self.select(*mnemonic, operand, symbols, pc).map_err(err)?;
+                    pc += i64::from(op.info().len);
+                    opcode = Some(op);
+                }
+            }
+
+            if pc > 0x10000 {
+                return Err(err(ErrorKind::AddressOverflow));
+            }
+            opcodes.push(opcode);
+        }
+
+        // Resolve constants referring to symbols defined later on. The order
+        // does not matter since every label is known by now, and the names
+        // were already checked to be unique.
+        while !deferred.is_empty() {
+            let before = deferred.len();
+            let mut error = None;
+            deferred.retain(|constant| match constant.expr.eval(symbols, constant.pc) {
+                Ok(value) => {
+                    symbols.insert(constant.name.to_string(), value);
+                    false
+                }
+                Err(kind) => {
+                    error.get_or_insert(Error {
+                        line: constant.line,
+                        kind,
+                    });
+                    true
+                }
+            });
+
+            if deferred.len() == before {
+                return Err(error.expect("unresolved constant should have an error"));
+            }
+        }
+
+        Ok(opcodes)
+    }
+
+    /// Emits the bytes of every statement.
+    fn second_pass(
+        &self,
+        stmts: &[Line],
+        opcodes: &[Option<Opcode>],
+        symbols: &BTreeMap<String, i64>,
+    ) -> Result<Program, Error> {
+        let mut pc: i64 = 0;
+        let mut segments = Vec::new();
+        let mut current = Segment {
+            origin: 0,
+            data: Vec::new(),
+        };
+
+        for (line, opcode) in stmts.iter().zip(opcodes) {
+            let err = |kind| Error {
+                line: line.number,
+                kind,
+            };
+            let eval = |expr: &Expr| expr.eval(symbols, pc).map_err(err);
+
+            let start = current.data.len();
+            match &line.stmt {
+                Stmt::None | Stmt::Equ(..) => {}
+                Stmt::Org(expr) => {
+                    pc = eval(expr)?;
+                    let next = Segment {
+                        origin: pc as u16,
+                        data: Vec::new(),
+                    };
+                    segments.push(core::mem::replace(&mut current, next));
+                    continue;
+                }
+                Stmt::Bytes(items) => {
+                    for item in items {
+                        match item {
+                            Item::Expr(expr) => current.data.push(byte(eval(expr)?).map_err(err)?),
+                            Item::Str(s) => current.data.extend_from_slice(s.as_bytes()),
+                        }
+                    }
+                }
+                Stmt::Words(exprs) => {
+                    for expr in exprs {
+                        let word = word(eval(expr)?).map_err(err)?;
+                        current.data.extend_from_slice(&word.to_le_bytes());
+                    }
+                }
+                Stmt::Instr(_, operand) => {
+                    let opcode = opcode.expect("instruction should have an opcode");
+                    current.data.push(opcode.into());
+                    let value = match operand.expr() {
+                        Some(expr) => eval(expr)?,
+                        None => 0,
+                    };
+                    match opcode.mode() {
+                        AddressingMode::Implied | AddressingMode::Accumulator => {}
+                        AddressingMode::Relative => {
+                            let offset = value - (pc + 2);
+                            if !(-128..=127).contains(&offset) {
+                                return Err(err(ErrorKind::BranchOutOfRange(offset)));
+                            }
+                            current.data.push(offset as u8);
+                        }
+                        mode if mode.operand_len() == 1 => {
+                            let value = match mode {
+                                AddressingMode::Immediate => byte(value),
+                                _ => zero_page(value),
+                            };
+                            current.data.push(value.map_err(err)?);
+                        }
+                        _ => {
+                            let word = word(value).map_err(err)?;
+                            current.data.extend_from_slice(&word.to_le_bytes());
+                        }
+                    }
+                }
+            }
+            pc += (current.data.len() - start) as i64;
+        }
+        segments.push(current);
+        segments.retain(|s| !s.data.is_empty());
+
+        let symbols = symbols
+            .iter()
+            .map(|(name, &value)| (name.clone(), value as u16))
+            .collect();
+        Ok(Program { segments, symbols })
+    }
+
+    /// Chooses the opcode for an instruction, preferring zero page addressing
+    /// when the address is known and small enough.
+    fn select(
+        &self,
+        mnemonic: Mnemonic,
+        operand: &Operand,
+        symbols: &BTreeMap<String, i64>,
+        pc: i64,
+    ) -> Result<Opcode, ErrorKind> {
+        use AddressingMode::*;
+
+        let small = operand
+            .expr()
+            .and_then(|expr| expr.eval(symbols, pc).ok())
+            .is_some_and(|value| (0..=0xff).contains(&value));
+        let candidates: &[AddressingMode] = match operand {
+            Operand::None => &[Implied, Accumulator],
+            Operand::Acc => &[Accumulator],
+            Operand::Imm(_) => &[Immediate],
+            Operand::Addr(_, Index::None) if small => &[ZeroPage, Absolute, Relative],
+            Operand::Addr(_, Index::None) => &[Absolute, Relative, ZeroPage],
+            Operand::Addr(_, Index::X) if small => &[ZeroPageX, AbsoluteX],
+            Operand::Addr(_, Index::X) => &[AbsoluteX, ZeroPageX],
+            Operand::Addr(_, Index::Y) if small => &[ZeroPageY, AbsoluteY],
+            Operand::Addr(_, Index::Y) => &[AbsoluteY, ZeroPageY],
+            Operand::Ind(_) => &[Indirect],
+            Operand::IndX(_) => &[IndirectX],
+            Operand::IndY(_) => &[IndirectY],
+        };
+
+        let mut illegal_only = false;
+        for &mode in candidates {
+            let matching = |op: &Opcode| op.mnemonic() == mnemonic && op.mode() == mode;
+            let legal = Opcode::iter().find(|op| matching(op) && !op.is_illegal());
+            match (legal, Opcode::iter().find(matching)) {
+                (Some(op), _) => return Ok(op),
+                (None, Some(op)) if self.illegal => return Ok(op),
+                (None, Some(_)) => illegal_only = true,
+                (None, None) => {}
+            }
+        }
+
+        if illegal_only {
+            Err(ErrorKind::IllegalOpcode(mnemonic))
+        } else {
+            Err(ErrorKind::InvalidAddressingMode(mnemonic))
+        }
+    }
+}
+
+/// A constant whose value depends on symbols defined further down.
+struct Deferred<'a> {
+    line: usize,
+    name: &'a str,
+    expr: &'a Expr,
+    /// The address of the definition, used for `*`.
+    pc: i64,
+}
+
+/// Checks that `name` is neither defined nor waiting to be defined.
+fn unique(
+    symbols: &BTreeMap<String, i64>,
+    deferred: &[Deferred],
+    name: &str,
+) -> Result<(), ErrorKind> {
+    if symbols.contains_key(name) || deferred.iter().any(|d| d.name == name) {
+        return Err(ErrorKind::DuplicateSymbol(name.to_string()));
+    }
+    Ok(())
+}
+
+fn define(
+    symbols: &mut BTreeMap<String, i64>,
+    deferred: &[Deferred],
+    name: &str,
+    value: i64,
+) -> Result<(), ErrorKind> {
+    unique(symbols, deferred, name)?;
+    symbols.insert(name.to_string(), value);
+    Ok(())
+}
+
+fn byte(value: i64) -> Result<u8, ErrorKind> {
+    match value {
+        -0x80..=0xff => Ok(value as u8),
+        _ => Err(ErrorKind::ValueOutOfRange(value)),
+    }
+}
+
+fn zero_page(value: i64) -> Result<u8, ErrorKind> {
+    match value {
+        0..=0xff => Ok(value as u8),
+        _ => Err(ErrorKind::ValueOutOfRange(value)),
+    }
+}
+
+fn word(value: i64) -> Result<u16, ErrorKind> {
+    match value {
+        -0x8000..=0xffff => Ok(value as u16),
+        _ => Err(ErrorKind::ValueOutOfRange(value)),
+    }
+}
+
+/// A parsed source line.
+#[derive(Debug)]
+struct Line {
+    number: usize,
+    label: Option<String>,
+    stmt: Stmt,
+}
+
+#[derive(Debug)]
+enum Stmt {
+    None,
+    Equ(String, Expr),
+    Org(Expr),
+    Bytes(Vec<Item>),
+    Words(Vec<Expr>),
+    Instr(Mnemonic, Operand),
+}
+
+#[derive(Debug)]
+enum Item {
+    Expr(Expr),
+    Str(String),
+}
+
+impl Item {
+    fn len(&self) -> usize {
+        match self {
+            Item::Expr(_) => 1,
+            Item::Str(s) => s.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Index {
+    None,
+    X,
+    Y,
+}
+
+#[derive(Debug)]
+enum Operand {
+    None,
+    Acc,
+    Imm(Expr),
+    Addr(Expr, Index),
+    Ind(Expr),
+    IndX(Expr),
+    IndY(Expr),
+}
+
+impl Operand {
+    fn expr(&self) -> Option<&Expr> {
+        match self {
+            Operand::None | Operand::Acc => None,
+            Operand::Imm(e)
+            | Operand::Addr(e, _)
+            | Operand::Ind(e)
+            | Operand::IndX(e)
+            | Operand::IndY(e) => Some(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UnOp {
+    Neg,
+    Not,
+    Lo,
+    Hi,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BinOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    And,
+    Xor,
+    Or,
+}
+
+impl BinOp {
+    /// Binding strength, higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Mul | BinOp::Div | BinOp::Rem => 5,
+            BinOp::Add | BinOp::Sub => 4,
+            BinOp::Shl | BinOp::Shr => 3,
+            BinOp::And => 2,
+            BinOp::Xor => 1,
+            BinOp::Or => 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Sym(String),
+    Pc,
+    Unary(UnOp, Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, symbols: &BTreeMap<String, i64>, pc: i64) -> Result<i64, ErrorKind> {
+        Ok(match self {
+            Expr::Num(n) => *n,
+            Expr::Pc => pc,
+            Expr::Sym(name) => *symbols
+                .get(name)
+                .ok_or_else(|| ErrorKind::UndefinedSymbol(name.clone()))?,
+            Expr::Unary(op, e) => {
+                let v = e.eval(symbols, pc)?;
+                match op {
+                    UnOp::Neg => v.wrapping_neg(),
+                    UnOp::Not => !v,
+                    UnOp::Lo => v & 0xff,
+                    UnOp::Hi => (v >> 8) & 0xff,
+                }
+            }
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.eval(symbols, pc)?, r.eval(symbols, pc)?);
+                match op {
+                    BinOp::Mul => l.wrapping_mul(r),
+                    BinOp::Div | BinOp::Rem if r == 0 => {
+                        return Err(ErrorKind::Syntax("division by zero".to_string()))
+                    }
+                    BinOp::Div => l.wrapping_div(r),
+                    BinOp::Rem => l.wrapping_rem(r),
+                    BinOp::Add => l.wrapping_add(r),
+                    BinOp::Sub => l.wrapping_sub(r),
+                    BinOp::Shl => l.wrapping_shl(r as u32),
+                    BinOp::Shr => l.wrapping_shr(r as u32),
+                    BinOp::And => l & r,
+                    BinOp::Xor => l ^ r,
+                    BinOp::Or => l | r,
+                }
+            }
+        })
+    }
+}
+
+/// Parses the whole source, qualifying local labels with the global label
+/// they belong to.
+fn parse(src: &str) -> Result<Vec<Line>, Error> {
+    let mut lines = Vec::new();
+    let mut scope = String::new();
+
+    for (i, text) in src.lines().enumerate() {
+        let number = i + 1;
+        let mut parser = Parser {
+            src: text.as_bytes(),
+            pos: 0,
+            scope: &mut scope,
+        };
+        let line = parser.line(number).map_err(|msg| Error {
+            line: number,
+            kind: msg,
+        })?;
+        lines.push(line);
+    }
+
+    Ok(lines)
+}
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    /// The last global label, updated as soon as a new one is parsed so the
+    /// rest of its line already uses the new scope.
+    scope: &'a mut String,
+}
+
+impl<'a> Parser<'a> {
+    fn syntax<T>(&self, msg: &str) -> Result<T, ErrorKind> {
+        Err(ErrorKind::Syntax(msg.to_string()))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.get(self.pos).copied()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_ws();
+        matches!(self.peek(), None | Some(b';'))
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_ws();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), ErrorKind> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(ErrorKind::Syntax(alloc::format!(
+                "expected `{}`",
+                c as char
+            )))
+        }
+    }
+
+    /// Parses an identifier, including a leading `.` for directives or `@`
+    /// for local labels.
+    fn ident(&mut self) -> Option<&'a str> {
+        self.skip_ws();
+        let start = self.pos;
+        if let Some(b'.' | b'@') = self.peek() {
+            self.pos += 1;
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'_' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        let src = self.src;
+        let ident = &src[start..self.pos];
+        match ident.first() {
+            Some(c) if !c.is_ascii_digit() && ident != b"." && ident != b"@" => {
+                Some(core::str::from_utf8(ident).expect("identifier should be ascii"))
+            }
+            _ => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    /// Returns the full name of a symbol, qualifying local labels.
+    fn qualify(&self, name: &str) -> String {
+        match name.strip_prefix('@') {
+            Some(_) => alloc::format!("{}{}", self.scope, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn line(&mut self, number: usize) -> Result<Line, ErrorKind> {
+        let mut label = None;
+        let mut stmt = Stmt::None;
+
+        let start = self.pos;
+        if let Some(name) = self.ident() {
+            let name = self.qualify(name);
+            if self.eat(b':') {
+                if !name.contains('@') {
+                    self.scope.clone_from(&name);
+                }
+                label = Some(name);
+            } else if self.eat(b'=') {
+                stmt = Stmt::Equ(name, self.expr()?);
+            } else {
+                self.pos = start;
+            }
+        }
+
+        if matches!(stmt, Stmt::None) && !self.at_end() {
+            stmt = self.stmt()?;
+        }
+
+        if !self.at_end() {
+            return self.syntax("unexpected trailing characters");
+        }
+
+        Ok(Line {
+            number,
+            label,
+            stmt,
+        })
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, ErrorKind> {
+        let Some(name) = self.ident() else {
+            return self.syntax("expected mnemonic or directive");
+        };
+        let name = name.to_ascii_lowercase();
+
+        match name.as_str() {
+            ".org" => return Ok(Stmt::Org(self.expr()?)),
+            ".byte" | ".db" => {
+                let mut items = Vec::new();
+                loop {
+                    self.skip_ws();
+                    if self.peek() == Some(b'"') {
+                        items.push(Item::Str(self.string()?));
+                    } else {
+                        items.push(Item::Expr(self.expr()?));
+                    }
+                    if !self.eat(b',') {
+                        break;
+                    }
+                }
+                return Ok(Stmt::Bytes(items));
+            }
+            ".word" | ".dw" => {
+                let mut exprs = alloc::vec![self.expr()?];
+                while self.eat(b',') {
+                    exprs.push(self.expr()?);
+                }
+                return Ok(Stmt::Words(exprs));
+            }
+            _ => {}
+        }
+
+        let Some(mnemonic) = Opcode::iter()
+            .map(Opcode::mnemonic)
+            .find(|m| m.as_str().eq_ignore_ascii_case(&name))
+        else {
+            return Err(ErrorKind::UnknownMnemonic(name));
+        };
+
+        Ok(Stmt::Instr(mnemonic, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Operand, ErrorKind> {
+        if self.at_end() {
+            return Ok(Operand::None);
+        }
+
+        if self.eat(b'#') {
+            return Ok(Operand::Imm(self.expr()?));
+        }
+
+        let start = self.pos;
+        if let Some(name) = self.ident() {
+            if name.eq_ignore_ascii_case("a") && self.at_end() {
+                return Ok(Operand::Acc);
+            }
+            self.pos = start;
+        }
+
+        // An operand completely enclosed in parentheses is indirect, while
+        // something like `(a+1)*2` is just an expression.
+        self.skip_ws();
+        if self.peek() == Some(b'(') {
+            let start = self.pos;
+            if let Ok(expr) = self.expr() {
+                let enclosed = self.enclosed(start);
+                if self.at_end() {
+                    return Ok(if enclosed {
+                        Operand::Ind(expr)
+                    } else {
+                        Operand::Addr(expr, Index::None)
+                    });
+                }
+                if !enclosed {
+                    return self.indexed(expr);
+                }
+                self.expect(b',')?;
+                self.index_reg(b'y')?;
+                return Ok(Operand::IndY(expr));
+            }
+
+            self.pos = start;
+            self.expect(b'(')?;
+            let expr = self.expr()?;
+            self.expect(b',')?;
+            self.index_reg(b'x')?;
+            self.expect(b')')?;
+            return Ok(Operand::IndX(expr));
+        }
+
+        let expr = self.expr()?;
+        self.indexed(expr)
+    }
+
+    /// Returns whether the text from `start` up to the current position is
+    /// wrapped in a single pair of parentheses.
+    fn enclosed(&self, start: usize) -> bool {
+        let text = &self.src[start..self.pos];
+        let text = text.trim_ascii();
+        if text.first() != Some(&b'(') || text.last() != Some(&b')') {
+            return false;
+        }
+
+        let mut depth = 0;
+        for (i, &c) in text.iter().enumerate() {
+            match c {
+                b'(' => depth += 1,
+                b')' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 && i != text.len() - 1 {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn indexed(&mut self, expr: Expr) -> Result<Operand, ErrorKind> {
+        if !self.eat(b',') {
+            return Ok(Operand::Addr(expr, Index::None));
+        }
+
+        match self.ident().map(str::to_ascii_lowercase).as_deref() {
+            Some("x") => Ok(Operand::Addr(expr, Index::X)),
+            Some("y") => Ok(Operand::Addr(expr, Index::Y)),
+            _ => self.syntax("expected index register X or Y"),
+        }
+    }
+
+    fn index_reg(&mut self, reg: u8) -> Result<(), ErrorKind> {
+        match self.ident() {
+            Some(name) if name.as_bytes().eq_ignore_ascii_case(&[reg]) => Ok(()),
+            _ => Err(ErrorKind::Syntax(alloc::format!(
+                "expected index register {}",
+                reg.to_ascii_uppercase() as char
+            ))),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ErrorKind> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c != b'"') {
+            self.pos += 1;
+        }
+        let s = core::str::from_utf8(&self.src[start..self.pos])
+            .map_err(|_| ErrorKind::Syntax("invalid string".to_string()))?
+            .to_string();
+        self.expect(b'"')?;
+        Ok(s)
+    }
+
+    fn expr(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(0)
+    }
+
+    /// Parses a binary expression using precedence climbing.
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, ErrorKind> {
+        let mut lhs = self.unary()?;
+        while let Some((op, len)) = self.bin_op() {
+            if op.precedence() < min_precedence {
+                break;
+            }
+            self.pos += len;
+            let rhs = self.binary(op.precedence() + 1)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn bin_op(&mut self) -> Option<(BinOp, usize)> {
+        self.skip_ws();
+        let rest = &self.src[self.pos..];
+        Some(match rest {
+            [b'<', b'<', ..] => (BinOp::Shl, 2),
+            [b'>', b'>', ..] => (BinOp::Shr, 2),
+            [b'*', ..] => (BinOp::Mul, 1),
+            [b'/', ..] => (BinOp::Div, 1),
+            [b'%', ..] => (BinOp::Rem, 1),
+            [b'+', ..] => (BinOp::Add, 1),
+            [b'-', ..] => (BinOp::Sub, 1),
+            [b'&', ..] => (BinOp::And, 1),
+            [b'^', ..] => (BinOp::Xor, 1),
+            [b'|', ..] => (BinOp::Or, 1),
+            _ => return None,
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ErrorKind> {
+        self.skip_ws();
+        let op = match self.peek() {
+            Some(b'-') => UnOp::Neg,
+            Some(b'~') => UnOp::Not,
+            Some(b'<') => UnOp::Lo,
+            Some(b'>') => UnOp::Hi,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        Ok(Expr::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ErrorKind> {
+        self.skip_ws();
+        match self.peek() {
+            Some(b'(') => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect(b')')?;
+                Ok(expr)
+            }
+            Some(b'*') => {
+                self.pos += 1;
+                Ok(Expr::Pc)
+            }
+            Some(b'$') => {
+                self.pos += 1;
+                self.number(16)
+            }
+            Some(b'%') => {
+                self.pos += 1;
+                self.number(2)
+            }
+            Some(b'\'') => {
+                let c = *self
+                    .src
+                    .get(self.pos + 1)
+                    .ok_or(ErrorKind::Syntax("unterminated character".to_string()))?;
+                self.pos += 2;
+                self.expect(b'\'')?;
+                Ok(Expr::Num(i64::from(c)))
+            }
+            Some(c) if c.is_ascii_digit() => self.number(10),
+            _ => match self.ident() {
+                Some(name) if !name.starts_with('.') => Ok(Expr::Sym(self.qualify(name))),
+                _ => self.syntax("expected expression"),
+            },
+        }
+    }
+
+    fn number(&mut self, radix: u32) -> Result<Expr, ErrorKind> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        let digits =
+            core::str::from_utf8(&self.src[start..self.pos]).expect("digits should be ascii");
+        match i64::from_str_radix(digits, radix) {
+            Ok(n) if n <= 0xffff_ffff => Ok(Expr::Num(n)),
+            _ => self.syntax("invalid number"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Assembles `src` returning the bytes of the single segment.
+    fn bytes(src: &str) -> Vec<u8> {
+        let program = Assembler::new().assemble(src).unwrap();
+        match program.segments() {
+            [] => Vec::new(),
+            [segment] => segment.data.clone(),
+            segments => panic!("expected one segment, got {segments:?}"),
+        }
+    }
+
+    /// Assembles `src` expecting it to fail, returning the error.
+    fn error(src: &str) -> Error {
+        Assembler::new().assemble(src).unwrap_err()
+    }
+
+    #[test]
+    fn addressing_modes() {
+        let cases: &[(&str, &[u8])] = &[
+            ("nop", &[0xea]),
+            ("asl", &[0x0a]),
+            ("asl a", &[0x0a]),
+            ("lda #$12", &[0xa9, 0x12]),
+            ("lda #-1", &[0xa9, 0xff]),
+            ("lda $12", &[0xa5, 0x12]),
+            ("lda $12,x", &[0xb5, 0x12]),
+            ("ldx $12,y", &[0xb6, 0x12]),
+            ("lda $1234", &[0xad, 0x34, 0x12]),
+            ("lda $0012+$1000,X", &[0xbd, 0x12, 0x10]),
+            ("lda $1234,y", &[0xb9, 0x34, 0x12]),
+            ("jmp ($1234)", &[0x6c, 0x34, 0x12]),
+            ("lda ($12,x)", &[0xa1, 0x12]),
+            ("lda ($12),y", &[0xb1, 0x12]),
+            ("lda ( $12 ) , Y", &[0xb1, 0x12]),
+            ("bne *", &[0xd0, 0xfe]),
+            ("bne * + 2", &[0xd0, 0x00]),
+            // Zero page indexed by Y only exists for LDX and STX.
+            ("lda $12,y", &[0xb9, 0x12, 0x00]),
+            // An expression starting with parentheses is not indirect.
+            ("lda ($10+2)*2", &[0xa5, 0x24]),
+            ("lda ($10+2)*2,x", &[0xb5, 0x24]),
+            ("jmp $12", &[0x4c, 0x12, 0x00]),
+        ];
+        for &(src, expected) in cases {
+            assert_eq!(bytes(src), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn expressions() {
+        let cases: &[(&str, u8)] = &[
+            ("1 + 2 * 3", 7),
+            ("(1 + 2) * 3", 9),
+            ("%1010 | 1", 11),
+            ("'A'", 0x41),
+            ("<$1234", 0x34),
+            (">$1234", 0x12),
+            ("~0 & $0f", 0x0f),
+            ("1 << 4 >> 2", 4),
+            ("$f0 ^ $ff", 0x0f),
+            ("17 % 5 + 10 / 3", 5),
+            ("-(2 - 3)", 1),
+        ];
+        for &(expr, value) in cases {
+            assert_eq!(bytes(&alloc::format!(".byte {expr}")), [value], "{expr}");
+        }
+    }
+
+    #[test]
+    fn directives() {
+        assert_eq!(
+            bytes(".byte 1, \"ab\", <$1234\n.db 2\n.word $1234, -1\n.dw 3"),
+            [1, b'a', b'b', 0x34, 2, 0x34, 0x12, 0xff, 0xff, 3, 0]
+        );
+        // Mnemonics and directives are case-insensitive.
+        assert_eq!(
+            bytes(".BYTE 1\nLDA #2 ; comment\n\t; only a comment"),
+            [1, 0xa9, 2]
+        );
+    }
+
+    #[test]
+    fn org_segments() {
+        let program = Assembler::new()
+            .assemble(".byte 1\n.org $1000\n.byte 2, 3\n.org $2000\n.org $3000\nstart: nop")
+            .unwrap();
+        assert_eq!(
+            program.segments(),
+            [
+                Segment {
+                    origin: 0x0000,
+                    data: alloc::vec![1]
+                },
+                Segment {
+                    origin: 0x1000,
+                    data: alloc::vec![2, 3]
+                },
+                Segment {
+                    origin: 0x3000,
+                    data: alloc::vec![0xea]
+                },
+            ]
+        );
+        assert_eq!(program.symbol("start"), Some(0x3000));
+
+        let (start, image) = program.to_image();
+        assert_eq!((start, image.len()), (0, 0x3001));
+        assert_eq!((image[0], image[0x1001], image[0x3000]), (1, 3, 0xea));
+
+        let mut mem = alloc::vec![0; 0x10000];
+        program.write_to(&mut mem);
+        assert_eq!(&mem[0x1000..0x1002], [2, 3]);
+    }
+
+    #[test]
+    fn labels_and_constants() {
+        let program = Assembler::new()
+            .assemble(
+                "value = $42\n.org $0200\nstart: lda #value\n  sta store\n  jmp start\nstore: .byte 0",
+            )
+            .unwrap();
+        assert_eq!(
+            program.segments()[0].data,
+            [0xa9, 0x42, 0x8d, 0x08, 0x02, 0x4c, 0x00, 0x02, 0x00]
+        );
+        assert_eq!(program.symbol("store"), Some(0x0208));
+        assert_eq!(program.symbol("value"), Some(0x42));
+
+        let mut asm = Assembler::new();
+        asm.define("port", 0xd000);
+        let program = asm.assemble("sta port").unwrap();
+        assert_eq!(program.segments()[0].data, [0x8d, 0x00, 0xd0]);
+        assert_eq!(program.symbol("port"), Some(0xd000));
+    }
+
+    #[test]
+    fn forward_references() {
+        // A label defined later is not known in the first pass, so absolute
+        // addressing is used even though it would fit in the zero page.
+        assert_eq!(bytes("lda zp\nzp: nop"), [0xad, 0x03, 0x00, 0xea]);
+        assert_eq!(bytes("beq done\nnop\ndone: rts"), [0xf0, 0x01, 0xea, 0x60]);
+        assert_eq!(bytes(".word end\nend:"), [0x02, 0x00]);
+
+        // Constants can refer to symbols defined further down, using the
+        // address of their own definition for `*`.
+        let program = Assembler::new()
+            .assemble(".org $1000\nx = * + later\nnop\ny = x + 1\nlater = 4")
+            .unwrap();
+        assert_eq!(program.symbol("x"), Some(0x1004));
+        assert_eq!(program.symbol("y"), Some(0x1005));
+    }
+
+    #[test]
+    fn local_labels() {
+        let src = "\
+first:
+@x:     nop
+        bne @x
+second: bne @x
+@x:     nop
+        bne @x";
+        assert_eq!(bytes(src), [0xea, 0xd0, 0xfd, 0xd0, 0x00, 0xea, 0xd0, 0xfd]);
+
+        let program = Assembler::new().assemble(src).unwrap();
+        assert_eq!(program.symbol("first@x"), Some(0));
+        assert_eq!(program.symbol("second@x"), Some(5));
+
+        // The same local label under one global label is a duplicate.
+        assert_eq!(
+            error("a:\n@x: nop\n@x: nop").kind,
+            ErrorKind::DuplicateSymbol("a@x".to_string())
+        );
+    }
+
+    #[test]
+    fn illegal_opcodes() {
+        assert_eq!(
+            error("lax $12").kind,
+            ErrorKind::IllegalOpcode(Mnemonic::LAX)
+        );
+
+        let mut asm = Assembler::new();
+        asm.set_illegal(true);
+        assert!(asm.illegal());
+        let program = asm.assemble("lax $12\nnop").unwrap();
+        assert_eq!(program.segments()[0].data, [0xa7, 0x12, 0xea]);
+    }
+
+    #[test]
+    fn errors() {
+        let cases: &[(&str, usize, ErrorKind)] = &[
+            ("nop\nfoo", 2, ErrorKind::UnknownMnemonic("foo".to_string())),
+            ("lda", 1, ErrorKind::InvalidAddressingMode(Mnemonic::LDA)),
+            ("jmp #1", 1, ErrorKind::InvalidAddressingMode(Mnemonic::JMP)),
+            ("sta #1", 1, ErrorKind::InvalidAddressingMode(Mnemonic::STA)),
+            (
+                "lda nothing",
+                1,
+                ErrorKind::UndefinedSymbol("nothing".to_string()),
+            ),
+            (
+                "x = y\ny = x",
+                1,
+                ErrorKind::UndefinedSymbol("y".to_string()),
+            ),
+            ("a:\na:", 2, ErrorKind::DuplicateSymbol("a".to_string())),
+            ("a = 1\na:", 2, ErrorKind::DuplicateSymbol("a".to_string())),
+            (
+                "x = later\nx = 5\nlater:",
+                2,
+                ErrorKind::DuplicateSymbol("x".to_string()),
+            ),
+            (
+                "x = later\nx:\nlater:",
+                2,
+                ErrorKind::DuplicateSymbol("x".to_string()),
+            ),
+            ("lda #256", 1, ErrorKind::ValueOutOfRange(256)),
+            (".byte -129", 1, ErrorKind::ValueOutOfRange(-129)),
+            (".word $10000", 1, ErrorKind::ValueOutOfRange(0x10000)),
+            ("lda (x,x)\nx = 256", 1, ErrorKind::ValueOutOfRange(256)),
+            (".org $10000", 1, ErrorKind::ValueOutOfRange(0x10000)),
+            (".org -1", 1, ErrorKind::ValueOutOfRange(-1)),
+            ("bne * + 130", 1, ErrorKind::BranchOutOfRange(128)),
+            ("bne * - 127", 1, ErrorKind::BranchOutOfRange(-129)),
+            (".org $ffff\nnop\nnop", 3, ErrorKind::AddressOverflow),
+            (
+                ".byte 1 / 0",
+                1,
+                ErrorKind::Syntax("division by zero".to_string()),
+            ),
+        ];
+        for (src, line, kind) in cases {
+            assert_eq!(
+                error(src),
+                Error {
+                    line: *line,
+                    kind: kind.clone()
+                },
+                "{src}"
+            );
+        }
+
+        for src in [
+            "lda #",
+            "lda ($12,y)",
+            "lda ($12),x",
+            "lda $12,z",
+            "lda #1 2",
+            "1nop",
+        ] {
+            assert!(
+                matches!(error(src).kind, ErrorKind::Syntax(_)),
+                "{src} should be a syntax error"
+            );
+        }
+    }
+
+    #[test]
+    fn error_display() {
+        assert_eq!(
+            error("nop\n.byte x").to_string(),
+            "line 2: undefined symbol `x`"
+        );
+    }
+}
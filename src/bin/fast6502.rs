@@ -0,0 +1,634 @@
+//! Machine language monitor for running and debugging 6502 programs.
+//!
+//! Loads an optional binary image into a flat 64 KiB memory and reads
+//! commands from standard input, see `help` for the list of commands. Numbers
+//! are hexadecimal, optionally prefixed by `$`.
+
+use std::{
+    env,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+    process::ExitCode,
+};
+
+use fast6502::{
+    asm::Assembler,
+    debug::{Breakpoint, Debugger, StopReason},
+    disasm::{Disassembler, Line},
+    memory::Memory,
+    symbols::SymbolTable,
+    tracer::{TraceFormat, Tracer},
+    CycleTicker, Opcode, M6502,
+};
+
+const USAGE: &str = "usage: fast6502 [FILE [LOAD-ADDR [START-ADDR]]]";
+
+const HELP: &str = "\
+r [REG=VALUE ...]        show or change registers (a x y sp p pc)
+m [START [END]]          dump memory
+> ADDR BYTE ...          write bytes to memory
+d [START [END]]          disassemble
+a [ADDR] INSTRUCTION     assemble a single instruction
+z [COUNT]                step instructions
+t [COUNT]                step instructions, showing each one
+g [ADDR]                 run until a breakpoint or trap is hit, for at most
+                         100 million cycles at a time
+break [ADDR]             add a breakpoint, or list them
+delete [INDEX]           delete a breakpoint, or all of them
+l FILE ADDR              load a binary image
+sym FILE                 load labels from a ca65 .dbg, VICE or label = $addr file
+reset                    run the reset sequence
+x                        exit";
+
+/// Lines of disassembly shown when no end address is given.
+const DISASM_LINES: usize = 16;
+
+/// Bytes of memory shown when no end address is given.
+const DUMP_BYTES: u16 = 128;
+
+/// Cycles after which `g` gives control back, since a run can't be
+/// interrupted.
+const RUN_CYCLES: u64 = 100_000_000;
+
+/// Appends a line to the monitor's output.
+macro_rules! say {
+    ($monitor:expr, $($arg:tt)*) => {{
+        // Writing to a `String` can't fail.
+        let _ = writeln!($monitor.out, $($arg)*);
+    }};
+}
+
+struct Monitor {
+    cpu: CycleTicker,
+    mem: Vec<u8>,
+    debugger: Debugger<Vec<Breakpoint>>,
+    symbols: SymbolTable,
+    next_dump: u16,
+    next_disasm: u16,
+    next_asm: u16,
+    /// Cycles a single `g` may run for.
+    run_cycles: u64,
+    /// Output of the commands run so far, printed by `main` after each one.
+    out: String,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() > 3 || args.iter().any(|a| a == "-h" || a == "--help") {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    }
+
+    let mut monitor = Monitor::new();
+    if let Some(path) = args.first() {
+        let load = match args.get(1).map(|s| parse_num(s)) {
+            Some(Ok(addr)) => addr,
+            Some(Err(e)) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+            None => 0,
+        };
+        let start = match args.get(2).map(|s| parse_num(s)) {
+            Some(Ok(addr)) => addr,
+            Some(Err(e)) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+            None => load,
+        };
+        let loaded = monitor.load(path, load);
+        print!("{}", monitor.take_output());
+        if let Err(e) = loaded {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        monitor.cpu.state_mut().pc = start;
+        monitor.set_cursors(start);
+    }
+
+    monitor.print_registers();
+    print!("{}", monitor.take_output());
+    let stdin = io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        let _ = io::stdout().flush();
+        line.clear();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => return ExitCode::SUCCESS,
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        let result = monitor.command(line.trim());
+        print!("{}", monitor.take_output());
+        match result {
+            Ok(true) => {}
+            Ok(false) => return ExitCode::SUCCESS,
+            Err(e) => println!("error: {e}"),
+        }
+    }
+}
+
+impl Monitor {
+    fn new() -> Monitor {
+        let mut debugger = Debugger::new(Vec::new());
+        debugger.set_detect_traps(true);
+        Monitor {
+            cpu: CycleTicker::new(M6502::new()),
+            mem: vec![0; 0x10000],
+            debugger,
+            symbols: SymbolTable::new(),
+            next_dump: 0,
+            next_disasm: 0,
+            next_asm: 0,
+            run_cycles: RUN_CYCLES,
+            out: String::new(),
+        }
+    }
+
+    /// Returns the output of the commands run since the last call.
+    fn take_output(&mut self) -> String {
+        std::mem::take(&mut self.out)
+    }
+
+    fn set_cursors(&mut self, addr: u16) {
+        self.next_dump = addr;
+        self.next_disasm = addr;
+        self.next_asm = addr;
+    }
+
+    fn load(&mut self, path: &str, addr: u16) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        let len = data.len().min(0x10000 - usize::from(addr));
+        self.mem[usize::from(addr)..usize::from(addr) + len].copy_from_slice(&data[..len]);
+        say!(
+            self,
+            "loaded {len} bytes at ${addr:04X}-${:04X}",
+            usize::from(addr) + len.max(1) - 1
+        );
+        Ok(())
+    }
+
+    /// Runs a single command, returning whether to keep going.
+    fn command(&mut self, line: &str) -> Result<bool, String> {
+        let (cmd, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let args: Vec<&str> = rest.split_whitespace().collect();
+
+        match cmd {
+            "" => {}
+            "help" | "?" => say!(self, "{HELP}"),
+            "x" | "q" | "exit" | "quit" => return Ok(false),
+            "r" => {
+                for arg in args {
+                    self.set_register(arg)?;
+                }
+                self.print_registers();
+            }
+            "m" => {
+                let (start, end) =
+                    self.range(&args, self.next_dump, |s| s.saturating_add(DUMP_BYTES - 1))?;
+                self.dump(start, end);
+            }
+            ">" => {
+                let (addr, bytes) = args.split_first().ok_or("missing address")?;
+                let addr = self.parse_addr(addr)?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    self.mem.set(addr.wrapping_add(i as u16), parse_byte(byte)?);
+                }
+            }
+            "d" => {
+                let start = args.first().map(|s| self.parse_addr(s)).transpose()?;
+                let end = args.get(1).map(|s| self.parse_addr(s)).transpose()?;
+                self.disassemble(start.unwrap_or(self.next_disasm), end);
+            }
+            "a" => {
+                // Mnemonics like `adc` are valid hex numbers too, so only
+                // take the first argument as an address if it isn't one.
+                let addr = match args.first() {
+                    Some(s) if args.len() > 1 && !is_mnemonic(s) => Some(self.parse_addr(s)?),
+                    _ => None,
+                };
+                let (addr, src) = match addr {
+                    Some(addr) => (addr, rest.trim_start()[args[0].len()..].trim()),
+                    None => (self.next_asm, rest.trim()),
+                };
+                self.assemble(addr, src)?;
+            }
+            "z" | "t" => {
+                let count = args
+                    .first()
+                    .map(|s| parse_count(s))
+                    .transpose()?
+                    .unwrap_or(1);
+                for _ in 0..count {
+                    if cmd == "t" {
+                        self.print_trace();
+                    }
+                    if self.cpu.is_jammed() {
+                        say!(self, "processor jammed");
+                        break;
+                    }
+                    self.cpu.step(&mut self.mem);
+                }
+                self.print_registers();
+            }
+            "g" => {
+                if let Some(addr) = args.first() {
+                    self.cpu.state_mut().pc = self.parse_addr(addr)?;
+                }
+                let reason = self
+                    .debugger
+                    .run(&mut self.cpu, &mut self.mem, self.run_cycles);
+                match reason {
+                    StopReason::Breakpoint(index) => say!(self, "breakpoint {index} hit"),
+                    StopReason::Trap(addr) => say!(self, "trapped at ${addr:04X}"),
+                    StopReason::Jammed => say!(self, "processor jammed"),
+                    StopReason::Limit => say!(self, "stopped after {} cycles", self.run_cycles),
+                    _ => {}
+                }
+                self.print_registers();
+            }
+            "break" | "bk" => match args.first() {
+                Some(addr) => {
+                    let addr = self.parse_addr(addr)?;
+                    let breakpoints = self.debugger.breakpoints_mut();
+                    breakpoints.push(Breakpoint::Pc(addr));
+                    say!(self, "breakpoint {} at ${addr:04X}", breakpoints.len() - 1);
+                }
+                None => {
+                    for (i, bp) in self.debugger.breakpoints().iter().enumerate() {
+                        if let Breakpoint::Pc(addr) = bp {
+                            say!(self, "{i}: ${addr:04X}");
+                        }
+                    }
+                }
+            },
+            "delete" | "del" => match args.first() {
+                Some(index) => {
+                    let index = parse_count(index)?;
+                    let breakpoints = self.debugger.breakpoints_mut();
+                    if index >= breakpoints.len() {
+                        return Err(format!("no breakpoint {index}"));
+                    }
+                    breakpoints.remove(index);
+                }
+                None => self.debugger.breakpoints_mut().clear(),
+            },
+            "l" => {
+                let [path, addr] = args[..] else {
+                    return Err("expected a file and an address".into());
+                };
+                let addr = parse_num(addr)?;
+                self.load(path, addr)?;
+                self.set_cursors(addr);
+            }
+            "sym" => {
+                let [path] = args[..] else {
+                    return Err("expected a file".into());
+                };
+                self.load_symbols(path)?;
+            }
+            "reset" => {
+                self.cpu.reset();
+                self.cpu.step(&mut self.mem);
+                self.print_registers();
+            }
+            _ => return Err(format!("unknown command `{cmd}`, try `help`")),
+        }
+        Ok(true)
+    }
+
+    /// Parses an optional start and end address, defaulting to `start` and
+    /// `end(start)`.
+    fn range(
+        &self,
+        args: &[&str],
+        start: u16,
+        end: impl Fn(u16) -> u16,
+    ) -> Result<(u16, u16), String> {
+        let start = match args.first() {
+            Some(s) => self.parse_addr(s)?,
+            None => start,
+        };
+        let end = match args.get(1) {
+            Some(s) => self.parse_addr(s)?,
+            None => end(start),
+        };
+        Ok((start, end.max(start)))
+    }
+
+    fn set_register(&mut self, arg: &str) -> Result<(), String> {
+        let (reg, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("expected REG=VALUE, got `{arg}`"))?;
+        let value = parse_num(value)?;
+        let byte = u8::try_from(value).map_err(|_| format!("${value:X} does not fit in {reg}"));
+        let state = self.cpu.state_mut();
+        match reg.to_ascii_lowercase().as_str() {
+            "a" => state.a = byte?,
+            "x" => state.x = byte?,
+            "y" => state.y = byte?,
+            "sp" | "s" => state.sp = byte?,
+            "p" => state.flags = byte? & !0x30,
+            "pc" => state.pc = value,
+            _ => return Err(format!("unknown register `{reg}`")),
+        }
+        Ok(())
+    }
+
+    fn print_registers(&mut self) {
+        let s = self.cpu.state();
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if (s.flags | 0x20) & (0x80 >> i) != 0 {
+                    c
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        say!(
+            self,
+            "  PC  A  X  Y  SP NV-BDIZC  CYCLES\n{:04X} {:02X} {:02X} {:02X} {:02X} {} {}",
+            s.pc,
+            s.a,
+            s.x,
+            s.y,
+            s.sp,
+            flags,
+            self.cpu.cycles()
+        );
+    }
+
+    fn print_trace(&mut self) {
+        let mut tracer = Tracer::with_symbols(String::new(), TraceFormat::Nestest, &self.symbols);
+        let _ = tracer.trace(&self.cpu, &self.mem);
+        self.out.push_str(&tracer.into_inner());
+    }
+
+    fn dump(&mut self, start: u16, end: u16) {
+        let mut addr = start;
+        loop {
+            let line_end = (addr | 0x000f).min(end);
+            let bytes: Vec<u8> = (addr..=line_end)
+                .map(|a| self.mem.get(a).unwrap_or(0))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02X}")).collect();
+            let text: String = bytes
+                .iter()
+                .map(|&b| {
+                    if b.is_ascii_graphic() || b == b' ' {
+                        char::from(b)
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            say!(self, "{addr:04X}  {:<47}  {text}", hex.join(" "));
+
+            match line_end.checked_add(1) {
+                Some(next) if line_end < end => addr = next,
+                next => {
+                    self.next_dump = next.unwrap_or(0);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn disassemble(&mut self, start: u16, end: Option<u16>) {
+        let mut disasm = Disassembler::new(&self.mem, start);
+        disasm.set_illegal(true);
+        let mut next = start;
+        for (i, line) in disasm.by_ref().enumerate() {
+            let bytes: Vec<String> = (0..u16::from(line.size()))
+                .map(|o| format!("{:02X}", self.mem[usize::from(line.addr().wrapping_add(o))]))
+                .collect();
+            if let Some(label) = self.symbols.label(line.addr()) {
+                say!(self, "{label}:");
+            }
+            match line {
+                Line::Instruction(instr) => say!(
+                    self,
+                    "{:04X}  {:<9} {}",
+                    line.addr(),
+                    bytes.join(" "),
+                    instr.with_symbols(&self.symbols)
+                ),
+                Line::Byte { .. } => {
+                    say!(self, "{:04X}  {:<9} {line}", line.addr(), bytes.join(" "))
+                }
+            }
+            next = line.addr().wrapping_add(u16::from(line.size()));
+            let last = match end {
+                Some(end) => next > end || next <= line.addr(),
+                None => i + 1 >= DISASM_LINES,
+            };
+            if last {
+                break;
+            }
+        }
+        self.next_disasm = next;
+    }
+
+    fn load_symbols(&mut self, path: &str) -> Result<(), String> {
+        let src = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let result = if path.ends_with(".dbg") {
+            self.symbols.load_ca65_dbg(&src)
+        } else if src.lines().any(|l| l.starts_with("al ")) {
+            self.symbols.load_vice(&src)
+        } else {
+            self.symbols.load_map(&src)
+        };
+        result.map_err(|e| format!("{path}: {e}"))?;
+        say!(self, "{} labels", self.symbols.len());
+        Ok(())
+    }
+
+    /// Parses an address given as a label or a number.
+    fn parse_addr(&self, s: &str) -> Result<u16, String> {
+        match self.symbols.addr(s) {
+            Some(addr) => Ok(addr),
+            None => parse_num(s),
+        }
+    }
+
+    fn assemble(&mut self, addr: u16, src: &str) -> Result<(), String> {
+        if src.is_empty() {
+            return Err("missing instruction".into());
+        }
+        let mut assembler = Assembler::new();
+        assembler.set_illegal(true);
+        let program = assembler
+            .assemble(&format!(".org ${addr:04X}\n{src}"))
+            .map_err(|e| e.to_string())?;
+        program.write_to(&mut self.mem);
+        let (_, image) = program.to_image();
+        self.disassemble(addr, Some(addr));
+        self.next_asm = addr.wrapping_add(image.len() as u16);
+        Ok(())
+    }
+}
+
+/// Returns whether `s` is the name of an instruction.
+fn is_mnemonic(s: &str) -> bool {
+    Opcode::iter().any(|op| op.mnemonic().as_str().eq_ignore_ascii_case(s))
+}
+
+/// Parses a hexadecimal number, optionally prefixed by `$`.
+fn parse_num(s: &str) -> Result<u16, String> {
+    let digits = s.strip_prefix('$').unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number `{s}`"))
+}
+
+fn parse_byte(s: &str) -> Result<u8, String> {
+    u8::try_from(parse_num(s)?).map_err(|_| format!("`{s}` does not fit in a byte"))
+}
+
+/// Parses a decimal count.
+fn parse_count(s: &str) -> Result<usize, String> {
+    s.parse().map_err(|_| format!("invalid count `{s}`"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `line`, returning its output or error.
+    fn run(monitor: &mut Monitor, line: &str) -> Result<String, String> {
+        let result = monitor.command(line);
+        let out = monitor.take_output();
+        result.map(|_| out)
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(parse_num("c000"), Ok(0xc000));
+        assert_eq!(parse_num("$C000"), Ok(0xc000));
+        assert_eq!(parse_num("$0"), Ok(0));
+        assert!(parse_num("10000").is_err());
+        assert!(parse_num("$").is_err());
+        assert!(parse_num("start").is_err());
+        assert_eq!(parse_byte("$ff"), Ok(0xff));
+        assert!(parse_byte("100").is_err());
+        assert_eq!(parse_count("10"), Ok(10));
+        assert!(parse_count("$10").is_err());
+
+        let mut monitor = Monitor::new();
+        monitor.symbols.insert("start", 0x8000);
+        assert_eq!(monitor.parse_addr("start"), Ok(0x8000));
+        assert_eq!(monitor.parse_addr("$1234"), Ok(0x1234));
+        assert!(monitor.parse_addr("end").is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        let monitor = Monitor::new();
+        let end = |s: u16| s.saturating_add(DUMP_BYTES - 1);
+        assert_eq!(monitor.range(&[], 0x1000, end), Ok((0x1000, 0x107f)));
+        assert_eq!(monitor.range(&["2000"], 0x1000, end), Ok((0x2000, 0x207f)));
+        assert_eq!(monitor.range(&["ffc0"], 0, end), Ok((0xffc0, 0xffff)));
+        assert_eq!(
+            monitor.range(&["2000", "2003"], 0, end),
+            Ok((0x2000, 0x2003))
+        );
+        assert_eq!(
+            monitor.range(&["2000", "1000"], 0, end),
+            Ok((0x2000, 0x2000))
+        );
+        assert!(monitor.range(&["2000", "x"], 0, end).is_err());
+
+        // A dump stops at the end of memory and wraps the cursor.
+        let mut monitor = Monitor::new();
+        run(&mut monitor, "> fff8 41 42").unwrap();
+        let out = run(&mut monitor, "m fff0").unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.starts_with("FFF0  00 00 00 00 00 00 00 00 41 42 00"));
+        assert!(out.ends_with("........AB......\n"));
+        assert_eq!(monitor.next_dump, 0);
+        let out = run(&mut monitor, "m").unwrap();
+        assert_eq!(out.lines().count(), 8);
+        assert!(out.starts_with("0000  "));
+        assert_eq!(monitor.next_dump, 0x80);
+    }
+
+    #[test]
+    fn mnemonics() {
+        assert!(is_mnemonic("adc"));
+        assert!(is_mnemonic("DEC"));
+        assert!(is_mnemonic("lax"));
+        assert!(!is_mnemonic("c000"));
+        assert!(!is_mnemonic("ad"));
+
+        // Mnemonics that are also hex numbers aren't taken as the address.
+        let mut monitor = Monitor::new();
+        monitor.set_cursors(0x0300);
+        let out = run(&mut monitor, "a adc #$01").unwrap();
+        assert_eq!(out.trim(), "0300  69 01     ADC #$01");
+        run(&mut monitor, "a dec $10").unwrap();
+        assert_eq!(monitor.mem[0x0302..0x0304], [0xc6, 0x10]);
+        run(&mut monitor, "a 400 dec $10").unwrap();
+        assert_eq!(monitor.mem[0x0400..0x0402], [0xc6, 0x10]);
+        assert_eq!(monitor.next_asm, 0x0402);
+        run(&mut monitor, "a bcc").unwrap_err();
+        run(&mut monitor, "a").unwrap_err();
+    }
+
+    #[test]
+    fn run_is_bounded() {
+        let mut monitor = Monitor::new();
+        monitor.run_cycles = 1000;
+        // loop: INX; JMP loop
+        run(&mut monitor, "> 300 e8 4c 00 03").unwrap();
+        let out = run(&mut monitor, "g 300").unwrap();
+        assert!(out.starts_with("stopped after 1000 cycles\n"), "{out}");
+        assert!((1000..1010).contains(&monitor.cpu.cycles()));
+
+        run(&mut monitor, "break 301").unwrap();
+        let out = run(&mut monitor, "g 300").unwrap();
+        assert!(out.starts_with("breakpoint 0 hit\n"), "{out}");
+        assert_eq!(monitor.cpu.state().pc, 0x0301);
+        run(&mut monitor, "delete").unwrap();
+
+        // JMP *
+        run(&mut monitor, "> 310 4c 10 03").unwrap();
+        let out = run(&mut monitor, "g 310").unwrap();
+        assert!(out.starts_with("trapped at $0310\n"), "{out}");
+
+        run(&mut monitor, "> 320 02").unwrap();
+        let out = run(&mut monitor, "g 320").unwrap();
+        assert!(out.starts_with("processor jammed\n"), "{out}");
+    }
+
+    #[test]
+    fn commands() {
+        let mut monitor = Monitor::new();
+        run(&mut monitor, "r a=ff x=$10 pc=1234 p=ff").unwrap();
+        let state = monitor.cpu.state();
+        assert_eq!(
+            (state.a, state.x, state.pc, state.flags),
+            (0xff, 0x10, 0x1234, 0xcf)
+        );
+        run(&mut monitor, "r a=100").unwrap_err();
+        run(&mut monitor, "r q=1").unwrap_err();
+        run(&mut monitor, "r a").unwrap_err();
+
+        run(&mut monitor, "> ffff 01 02").unwrap();
+        assert_eq!((monitor.mem[0xffff], monitor.mem[0]), (1, 2));
+        run(&mut monitor, ">").unwrap_err();
+        run(&mut monitor, "> 10 100").unwrap_err();
+
+        run(&mut monitor, "delete 0").unwrap_err();
+        run(&mut monitor, "l").unwrap_err();
+        run(&mut monitor, "frobnicate").unwrap_err();
+        assert_eq!(monitor.command(""), Ok(true));
+        assert_eq!(monitor.command("x"), Ok(false));
+    }
+}
@@ -1,7 +1,21 @@
-// Op is an enum table for all 256 possible cpu instructions, mapping bytes to functions.
+use core::fmt;
+
+use super::M6502;
+
+/// An enum table for all 256 possible cpu instructions, mapping bytes to
+/// functions.
+///
+/// Each documented opcode is named after its mnemonic and addressing mode,
+/// e.g. `LDA_abX` for `LDA $1234,X`. The addressing modes are abbreviated as
+/// `imp`, `acc`, `imm`, `zpg`, `zpX`, `zpY`, `abs`, `abX`, `abY`, `ind`, `inX`,
+/// `inY` and `rel`. The illegal opcodes are numbered `UDI_001` to `UDI_105`,
+/// use [`Opcode::info`] to find out what they do.
+///
+/// Every byte is a valid opcode, so converting from a [`u8`] never fails.
 #[allow(non_camel_case_types)]
 #[repr(u8)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Opcode {
     BRK_imp = 0x00,
     ORA_inX = 0x01,
@@ -260,3 +274,870 @@ pub enum Opcode {
     INC_abX = 0xfe,
     UDI_105 = 0xff,
 }
+
+impl Opcode {
+    /// Returns the opcode for `byte`.
+    #[inline]
+    pub const fn from_byte(byte: u8) -> Opcode {
+        // SAFETY: `Opcode` is `repr(u8)` and has a variant for every one of the
+        // 256 possible values, so every byte is a valid `Opcode`.
+        unsafe { core::mem::transmute::<u8, Opcode>(byte) }
+    }
+
+    /// Returns the byte value of the opcode.
+    #[inline]
+    pub const fn to_byte(self) -> u8 {
+        self as u8
+    }
+
+    /// Returns an iterator over all 256 opcodes, in order of their byte value.
+    pub fn iter() -> impl DoubleEndedIterator<Item = Opcode> + ExactSizeIterator {
+        (0..=u8::MAX).map(Opcode::from_byte)
+    }
+
+    /// Returns the static information about the opcode.
+    #[inline]
+    pub const fn info(self) -> &'static OpcodeInfo {
+        OpcodeInfo::of(self as u8)
+    }
+
+    /// Returns the instruction mnemonic.
+    #[inline]
+    pub const fn mnemonic(self) -> Mnemonic {
+        self.info().mnemonic
+    }
+
+    /// Returns the addressing mode.
+    #[inline]
+    pub const fn mode(self) -> AddressingMode {
+        self.info().mode
+    }
+
+    /// Returns whether the opcode is an illegal opcode.
+    #[inline]
+    pub const fn is_illegal(self) -> bool {
+        self.info().illegal
+    }
+}
+
+impl From<u8> for Opcode {
+    #[inline]
+    fn from(byte: u8) -> Opcode {
+        Opcode::from_byte(byte)
+    }
+}
+
+impl From<Opcode> for u8 {
+    #[inline]
+    fn from(opcode: Opcode) -> u8 {
+        opcode as u8
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.mnemonic(), f)
+    }
+}
+
+/// The instruction mnemonics of the 6502, including the commonly used names
+/// for the illegal opcodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mnemonic {
+    /// Add with carry.
+    ADC,
+    /// AND immediate then logical shift right (illegal).
+    ALR,
+    /// AND immediate then copy bit 7 to carry (illegal).
+    ANC,
+    /// Logical AND.
+    AND,
+    /// Unstable AND of X and immediate with A (illegal).
+    ANE,
+    /// AND immediate then rotate right (illegal).
+    ARR,
+    /// Arithmetic shift left.
+    ASL,
+    /// Branch if carry clear.
+    BCC,
+    /// Branch if carry set.
+    BCS,
+    /// Branch if equal.
+    BEQ,
+    /// Bit test.
+    BIT,
+    /// Branch if minus.
+    BMI,
+    /// Branch if not equal.
+    BNE,
+    /// Branch if plus.
+    BPL,
+    /// Break.
+    BRK,
+    /// Branch if overflow clear.
+    BVC,
+    /// Branch if overflow set.
+    BVS,
+    /// Clear carry flag.
+    CLC,
+    /// Clear decimal flag.
+    CLD,
+    /// Clear interrupt disable flag.
+    CLI,
+    /// Clear overflow flag.
+    CLV,
+    /// Compare with A.
+    CMP,
+    /// Compare with X.
+    CPX,
+    /// Compare with Y.
+    CPY,
+    /// Decrement memory then compare with A (illegal).
+    DCP,
+    /// Decrement memory.
+    DEC,
+    /// Decrement X.
+    DEX,
+    /// Decrement Y.
+    DEY,
+    /// Logical XOR.
+    EOR,
+    /// Increment memory.
+    INC,
+    /// Increment X.
+    INX,
+    /// Increment Y.
+    INY,
+    /// Increment memory then subtract with carry (illegal).
+    ISC,
+    /// Halt the processor (illegal).
+    JAM,
+    /// Jump.
+    JMP,
+    /// Jump to subroutine.
+    JSR,
+    /// AND memory with stack pointer into A, X and stack pointer (illegal).
+    LAS,
+    /// Load A and X (illegal).
+    LAX,
+    /// Load A.
+    LDA,
+    /// Load X.
+    LDX,
+    /// Load Y.
+    LDY,
+    /// Logical shift right.
+    LSR,
+    /// Unstable AND of immediate with A into A and X (illegal).
+    LXA,
+    /// No operation.
+    NOP,
+    /// Logical OR.
+    ORA,
+    /// Push A.
+    PHA,
+    /// Push processor status.
+    PHP,
+    /// Pull A.
+    PLA,
+    /// Pull processor status.
+    PLP,
+    /// Rotate memory left then AND with A (illegal).
+    RLA,
+    /// Rotate left.
+    ROL,
+    /// Rotate right.
+    ROR,
+    /// Rotate memory right then add with carry (illegal).
+    RRA,
+    /// Return from interrupt.
+    RTI,
+    /// Return from subroutine.
+    RTS,
+    /// Store A AND X (illegal).
+    SAX,
+    /// Subtract with carry.
+    SBC,
+    /// Subtract immediate from A AND X into X (illegal).
+    SBX,
+    /// Set carry flag.
+    SEC,
+    /// Set decimal flag.
+    SED,
+    /// Set interrupt disable flag.
+    SEI,
+    /// Store A AND X AND high byte of address plus one (illegal).
+    SHA,
+    /// Store X AND high byte of address plus one (illegal).
+    SHX,
+    /// Store Y AND high byte of address plus one (illegal).
+    SHY,
+    /// Shift memory left then OR with A (illegal).
+    SLO,
+    /// Shift memory right then XOR with A (illegal).
+    SRE,
+    /// Store A.
+    STA,
+    /// Store X.
+    STX,
+    /// Store Y.
+    STY,
+    /// Store A AND X into stack pointer, then store it AND high byte of address plus one (illegal).
+    TAS,
+    /// Transfer A to X.
+    TAX,
+    /// Transfer A to Y.
+    TAY,
+    /// Transfer stack pointer to X.
+    TSX,
+    /// Transfer X to A.
+    TXA,
+    /// Transfer X to stack pointer.
+    TXS,
+    /// Transfer Y to A.
+    TYA,
+}
+
+impl Mnemonic {
+    /// Returns the mnemonic as an uppercase string, e.g. `"LDA"`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Mnemonic::ADC => "ADC",
+            Mnemonic::ALR => "ALR",
+            Mnemonic::ANC => "ANC",
+            Mnemonic::AND => "AND",
+            Mnemonic::ANE => "ANE",
+            Mnemonic::ARR => "ARR",
+            Mnemonic::ASL => "ASL",
+            Mnemonic::BCC => "BCC",
+            Mnemonic::BCS => "BCS",
+            Mnemonic::BEQ => "BEQ",
+            Mnemonic::BIT => "BIT",
+            Mnemonic::BMI => "BMI",
+            Mnemonic::BNE => "BNE",
+            Mnemonic::BPL => "BPL",
+            Mnemonic::BRK => "BRK",
+            Mnemonic::BVC => "BVC",
+            Mnemonic::BVS => "BVS",
+            Mnemonic::CLC => "CLC",
+            Mnemonic::CLD => "CLD",
+            Mnemonic::CLI => "CLI",
+            Mnemonic::CLV => "CLV",
+            Mnemonic::CMP => "CMP",
+            Mnemonic::CPX => "CPX",
+            Mnemonic::CPY => "CPY",
+            Mnemonic::DCP => "DCP",
+            Mnemonic::DEC => "DEC",
+            Mnemonic::DEX => "DEX",
+            Mnemonic::DEY => "DEY",
+            Mnemonic::EOR => "EOR",
+            Mnemonic::INC => "INC",
+            Mnemonic::INX => "INX",
+            Mnemonic::INY => "INY",
+            Mnemonic::ISC => "ISC",
+            Mnemonic::JAM => "JAM",
+            Mnemonic::JMP => "JMP",
+            Mnemonic::JSR => "JSR",
+            Mnemonic::LAS => "LAS",
+            Mnemonic::LAX => "LAX",
+            Mnemonic::LDA => "LDA",
+            Mnemonic::LDX => "LDX",
+            Mnemonic::LDY => "LDY",
+            Mnemonic::LSR => "LSR",
+            Mnemonic::LXA => "LXA",
+            Mnemonic::NOP => "NOP",
+            Mnemonic::ORA => "ORA",
+            Mnemonic::PHA => "PHA",
+            Mnemonic::PHP => "PHP",
+            Mnemonic::PLA => "PLA",
+            Mnemonic::PLP => "PLP",
+            Mnemonic::RLA => "RLA",
+            Mnemonic::ROL => "ROL",
+            Mnemonic::ROR => "ROR",
+            Mnemonic::RRA => "RRA",
+            Mnemonic::RTI => "RTI",
+            Mnemonic::RTS => "RTS",
+            Mnemonic::SAX => "SAX",
+            Mnemonic::SBC => "SBC",
+            Mnemonic::SBX => "SBX",
+            Mnemonic::SEC => "SEC",
+            Mnemonic::SED => "SED",
+            Mnemonic::SEI => "SEI",
+            Mnemonic::SHA => "SHA",
+            Mnemonic::SHX => "SHX",
+            Mnemonic::SHY => "SHY",
+            Mnemonic::SLO => "SLO",
+            Mnemonic::SRE => "SRE",
+            Mnemonic::STA => "STA",
+            Mnemonic::STX => "STX",
+            Mnemonic::STY => "STY",
+            Mnemonic::TAS => "TAS",
+            Mnemonic::TAX => "TAX",
+            Mnemonic::TAY => "TAY",
+            Mnemonic::TSX => "TSX",
+            Mnemonic::TXA => "TXA",
+            Mnemonic::TXS => "TXS",
+            Mnemonic::TYA => "TYA",
+        }
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// The addressing modes of the 6502, deciding how an instruction finds its
+/// operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressingMode {
+    /// No operand, e.g. `CLC`.
+    Implied,
+    /// Operates on the accumulator, e.g. `ASL A`.
+    Accumulator,
+    /// 8-bit constant operand, e.g. `LDA #$10`.
+    Immediate,
+    /// 8-bit address in the zero page, e.g. `LDA $10`.
+    ZeroPage,
+    /// Zero page address indexed by X, e.g. `LDA $10,X`.
+    ZeroPageX,
+    /// Zero page address indexed by Y, e.g. `LDX $10,Y`.
+    ZeroPageY,
+    /// 16-bit address, e.g. `LDA $1234`.
+    Absolute,
+    /// 16-bit address indexed by X, e.g. `LDA $1234,X`.
+    AbsoluteX,
+    /// 16-bit address indexed by Y, e.g. `LDA $1234,Y`.
+    AbsoluteY,
+    /// 16-bit address of the actual address, e.g. `JMP ($1234)`.
+    Indirect,
+    /// Zero page address indexed by X of the actual address, e.g.
+    /// `LDA ($10,X)`.
+    IndirectX,
+    /// Zero page address of the actual address which is then indexed by Y,
+    /// e.g. `LDA ($10),Y`.
+    IndirectY,
+    /// Signed 8-bit offset from the next instruction, e.g. `BNE $C010`.
+    Relative,
+}
+
+impl AddressingMode {
+    /// Returns the number of operand bytes following the opcode.
+    pub const fn operand_len(self) -> u8 {
+        match self {
+            AddressingMode::Implied | AddressingMode::Accumulator => 0,
+            AddressingMode::Immediate
+            | AddressingMode::ZeroPage
+            | AddressingMode::ZeroPageX
+            | AddressingMode::ZeroPageY
+            | AddressingMode::IndirectX
+            | AddressingMode::IndirectY
+            | AddressingMode::Relative => 1,
+            AddressingMode::Absolute
+            | AddressingMode::AbsoluteX
+            | AddressingMode::AbsoluteY
+            | AddressingMode::Indirect => 2,
+        }
+    }
+}
+
+/// How an instruction accesses the memory pointed to by its operand.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryAccess {
+    /// The operand does not refer to memory, or the instruction only uses it
+    /// as a jump target. Stack accesses are not counted.
+    None,
+    /// The instruction reads memory.
+    Read,
+    /// The instruction writes memory.
+    Write,
+    /// The instruction reads memory, modifies the value and writes it back.
+    ReadModifyWrite,
+}
+
+/// Static information about what an opcode does.
+///
+/// The information for every opcode can be found in [`OPCODE_INFO`], indexed
+/// by opcode, or looked up with [`OpcodeInfo::of`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpcodeInfo {
+    /// The instruction mnemonic.
+    pub mnemonic: Mnemonic,
+    /// The addressing mode.
+    pub mode: AddressingMode,
+    /// Length of the instruction in bytes, including the opcode.
+    pub len: u8,
+    /// Base number of cycles the instruction takes to execute.
+    pub cycles: u8,
+    /// Whether the instruction takes an extra cycle when indexing crosses a
+    /// page boundary. For branches this is the extra cycle when branching to
+    /// a different page, which comes on top of the extra cycle for taking the
+    /// branch.
+    pub page_cross: bool,
+    /// How the instruction accesses the memory pointed to by its operand.
+    pub access: MemoryAccess,
+    /// The status flags that may be changed by the instruction, as a mask of
+    /// the `FLAG_x` constants of [`M6502`]. Flags only changed when pushed to
+    /// the stack are not included.
+    pub flags: u8,
+    /// Whether the opcode is an illegal opcode.
+    pub illegal: bool,
+}
+
+impl OpcodeInfo {
+    /// Returns the information for `opcode`.
+    #[inline]
+    pub const fn of(opcode: u8) -> &'static OpcodeInfo {
+        &OPCODE_INFO[opcode as usize]
+    }
+}
+
+/// Information for every opcode, indexed by opcode.
+pub const OPCODE_INFO: [OpcodeInfo; 256] = table::OPCODES;
+
+mod table {
+    use super::{AddressingMode, MemoryAccess, Mnemonic, OpcodeInfo, M6502};
+    use super::{AddressingMode::*, Mnemonic::*};
+
+    const N: u8 = M6502::FLAG_N;
+    const V: u8 = M6502::FLAG_V;
+    const D: u8 = M6502::FLAG_D;
+    const I: u8 = M6502::FLAG_I;
+    const Z: u8 = M6502::FLAG_Z;
+    const C: u8 = M6502::FLAG_C;
+
+    const fn access(mnemonic: Mnemonic, mode: AddressingMode) -> MemoryAccess {
+        if let Implied | Accumulator | Immediate | Relative = mode {
+            return MemoryAccess::None;
+        }
+
+        match mnemonic {
+            ADC | AND | BIT | CMP | CPX | CPY | EOR | LAS | LAX | LDA | LDX | LDY | NOP | ORA
+            | SBC => MemoryAccess::Read,
+            SAX | SHA | SHX | SHY | STA | STX | STY | TAS => MemoryAccess::Write,
+            ASL | DCP | DEC | INC | ISC | LSR | RLA | ROL | ROR | RRA | SLO | SRE => {
+                MemoryAccess::ReadModifyWrite
+            }
+            _ => MemoryAccess::None,
+        }
+    }
+
+    const fn flags(mnemonic: Mnemonic) -> u8 {
+        match mnemonic {
+            ADC | ARR | ISC | RRA | SBC => N | V | Z | C,
+            BIT => N | V | Z,
+            ALR | ANC | ASL | CMP | CPX | CPY | DCP | LSR | RLA | ROL | ROR | SBX | SLO | SRE => {
+                N | Z | C
+            }
+            AND | ANE | DEC | DEX | DEY | EOR | INC | INX | INY | LAS | LAX | LDA | LDX | LDY
+            | LXA | ORA | PLA | TAX | TAY | TSX | TXA | TYA => N | Z,
+            CLC | SEC => C,
+            CLD | SED => D,
+            BRK | CLI | SEI => I,
+            CLV => V,
+            PLP | RTI => N | V | D | I | Z | C,
+            _ => 0,
+        }
+    }
+
+    const fn info(
+        mnemonic: Mnemonic,
+        mode: AddressingMode,
+        cycles: u8,
+        illegal: bool,
+    ) -> OpcodeInfo {
+        let access = access(mnemonic, mode);
+        let page_cross = match mode {
+            Relative => true,
+            AbsoluteX | AbsoluteY | IndirectY => matches!(access, MemoryAccess::Read),
+            _ => false,
+        };
+
+        OpcodeInfo {
+            mnemonic,
+            mode,
+            len: 1 + mode.operand_len(),
+            cycles,
+            page_cross,
+            access,
+            flags: flags(mnemonic),
+            illegal,
+        }
+    }
+
+    /// Information for every opcode, indexed by opcode.
+    pub(super) const OPCODES: [OpcodeInfo; 256] = [
+        info(BRK, Implied, 7, false),     // 0x00
+        info(ORA, IndirectX, 6, false),   // 0x01
+        info(JAM, Implied, 2, true),      // 0x02
+        info(SLO, IndirectX, 8, true),    // 0x03
+        info(NOP, ZeroPage, 3, true),     // 0x04
+        info(ORA, ZeroPage, 3, false),    // 0x05
+        info(ASL, ZeroPage, 5, false),    // 0x06
+        info(SLO, ZeroPage, 5, true),     // 0x07
+        info(PHP, Implied, 3, false),     // 0x08
+        info(ORA, Immediate, 2, false),   // 0x09
+        info(ASL, Accumulator, 2, false), // 0x0a
+        info(ANC, Immediate, 2, true),    // 0x0b
+        info(NOP, Absolute, 4, true),     // 0x0c
+        info(ORA, Absolute, 4, false),    // 0x0d
+        info(ASL, Absolute, 6, false),    // 0x0e
+        info(SLO, Absolute, 6, true),     // 0x0f
+        info(BPL, Relative, 2, false),    // 0x10
+        info(ORA, IndirectY, 5, false),   // 0x11
+        info(JAM, Implied, 2, true),      // 0x12
+        info(SLO, IndirectY, 8, true),    // 0x13
+        info(NOP, ZeroPageX, 4, true),    // 0x14
+        info(ORA, ZeroPageX, 4, false),   // 0x15
+        info(ASL, ZeroPageX, 6, false),   // 0x16
+        info(SLO, ZeroPageX, 6, true),    // 0x17
+        info(CLC, Implied, 2, false),     // 0x18
+        info(ORA, AbsoluteY, 4, false),   // 0x19
+        info(NOP, Implied, 2, true),      // 0x1a
+        info(SLO, AbsoluteY, 7, true),    // 0x1b
+        info(NOP, AbsoluteX, 4, true),    // 0x1c
+        info(ORA, AbsoluteX, 4, false),   // 0x1d
+        info(ASL, AbsoluteX, 7, false),   // 0x1e
+        info(SLO, AbsoluteX, 7, true),    // 0x1f
+        info(JSR, Absolute, 6, false),    // 0x20
+        info(AND, IndirectX, 6, false),   // 0x21
+        info(JAM, Implied, 2, true),      // 0x22
+        info(RLA, IndirectX, 8, true),    // 0x23
+        info(BIT, ZeroPage, 3, false),    // 0x24
+        info(AND, ZeroPage, 3, false),    // 0x25
+        info(ROL, ZeroPage, 5, false),    // 0x26
+        info(RLA, ZeroPage, 5, true),     // 0x27
+        info(PLP, Implied, 4, false),     // 0x28
+        info(AND, Immediate, 2, false),   // 0x29
+        info(ROL, Accumulator, 2, false), // 0x2a
+        info(ANC, Immediate, 2, true),    // 0x2b
+        info(BIT, Absolute, 4, false),    // 0x2c
+        info(AND, Absolute, 4, false),    // 0x2d
+        info(ROL, Absolute, 6, false),    // 0x2e
+        info(RLA, Absolute, 6, true),     // 0x2f
+        info(BMI, Relative, 2, false),    // 0x30
+        info(AND, IndirectY, 5, false),   // 0x31
+        info(JAM, Implied, 2, true),      // 0x32
+        info(RLA, IndirectY, 8, true),    // 0x33
+        info(NOP, ZeroPageX, 4, true),    // 0x34
+        info(AND, ZeroPageX, 4, false),   // 0x35
+        info(ROL, ZeroPageX, 6, false),   // 0x36
+        info(RLA, ZeroPageX, 6, true),    // 0x37
+        info(SEC, Implied, 2, false),     // 0x38
+        info(AND, AbsoluteY, 4, false),   // 0x39
+        info(NOP, Implied, 2, true),      // 0x3a
+        info(RLA, AbsoluteY, 7, true),    // 0x3b
+        info(NOP, AbsoluteX, 4, true),    // 0x3c
+        info(AND, AbsoluteX, 4, false),   // 0x3d
+        info(ROL, AbsoluteX, 7, false),   // 0x3e
+        info(RLA, AbsoluteX, 7, true),    // 0x3f
+        info(RTI, Implied, 6, false),     // 0x40
+        info(EOR, IndirectX, 6, false),   // 0x41
+        info(JAM, Implied, 2, true),      // 0x42
+        info(SRE, IndirectX, 8, true),    // 0x43
+        info(NOP, ZeroPage, 3, true),     // 0x44
+        info(EOR, ZeroPage, 3, false),    // 0x45
+        info(LSR, ZeroPage, 5, false),    // 0x46
+        info(SRE, ZeroPage, 5, true),     // 0x47
+        info(PHA, Implied, 3, false),     // 0x48
+        info(EOR, Immediate, 2, false),   // 0x49
+        info(LSR, Accumulator, 2, false), // 0x4a
+        info(ALR, Immediate, 2, true),    // 0x4b
+        info(JMP, Absolute, 3, false),    // 0x4c
+        info(EOR, Absolute, 4, false),    // 0x4d
+        info(LSR, Absolute, 6, false),    // 0x4e
+        info(SRE, Absolute, 6, true),     // 0x4f
+        info(BVC, Relative, 2, false),    // 0x50
+        info(EOR, IndirectY, 5, false),   // 0x51
+        info(JAM, Implied, 2, true),      // 0x52
+        info(SRE, IndirectY, 8, true),    // 0x53
+        info(NOP, ZeroPageX, 4, true),    // 0x54
+        info(EOR, ZeroPageX, 4, false),   // 0x55
+        info(LSR, ZeroPageX, 6, false),   // 0x56
+        info(SRE, ZeroPageX, 6, true),    // 0x57
+        info(CLI, Implied, 2, false),     // 0x58
+        info(EOR, AbsoluteY, 4, false),   // 0x59
+        info(NOP, Implied, 2, true),      // 0x5a
+        info(SRE, AbsoluteY, 7, true),    // 0x5b
+        info(NOP, AbsoluteX, 4, true),    // 0x5c
+        info(EOR, AbsoluteX, 4, false),   // 0x5d
+        info(LSR, AbsoluteX, 7, false),   // 0x5e
+        info(SRE, AbsoluteX, 7, true),    // 0x5f
+        info(RTS, Implied, 6, false),     // 0x60
+        info(ADC, IndirectX, 6, false),   // 0x61
+        info(JAM, Implied, 2, true),      // 0x62
+        info(RRA, IndirectX, 8, true),    // 0x63
+        info(NOP, ZeroPage, 3, true),     // 0x64
+        info(ADC, ZeroPage, 3, false),    // 0x65
+        info(ROR, ZeroPage, 5, false),    // 0x66
+        info(RRA, ZeroPage, 5, true),     // 0x67
+        info(PLA, Implied, 4, false),     // 0x68
+        info(ADC, Immediate, 2, false),   // 0x69
+        info(ROR, Accumulator, 2, false), // 0x6a
+        info(ARR, Immediate, 2, true),    // 0x6b
+        info(JMP, Indirect, 5, false),    // 0x6c
+        info(ADC, Absolute, 4, false),    // 0x6d
+        info(ROR, Absolute, 6, false),    // 0x6e
+        info(RRA, Absolute, 6, true),     // 0x6f
+        info(BVS, Relative, 2, false),    // 0x70
+        info(ADC, IndirectY, 5, false),   // 0x71
+        info(JAM, Implied, 2, true),      // 0x72
+        info(RRA, IndirectY, 8, true),    // 0x73
+        info(NOP, ZeroPageX, 4, true),    // 0x74
+        info(ADC, ZeroPageX, 4, false),   // 0x75
+        info(ROR, ZeroPageX, 6, false),   // 0x76
+        info(RRA, ZeroPageX, 6, true),    // 0x77
+        info(SEI, Implied, 2, false),     // 0x78
+        info(ADC, AbsoluteY, 4, false),   // 0x79
+        info(NOP, Implied, 2, true),      // 0x7a
+        info(RRA, AbsoluteY, 7, true),    // 0x7b
+        info(NOP, AbsoluteX, 4, true),    // 0x7c
+        info(ADC, AbsoluteX, 4, false),   // 0x7d
+        info(ROR, AbsoluteX, 7, false),   // 0x7e
+        info(RRA, AbsoluteX, 7, true),    // 0x7f
+        info(NOP, Immediate, 2, true),    // 0x80
+        info(STA, IndirectX, 6, false),   // 0x81
+        info(NOP, Immediate, 2, true),    // 0x82
+        info(SAX, IndirectX, 6, true),    // 0x83
+        info(STY, ZeroPage, 3, false),    // 0x84
+        info(STA, ZeroPage, 3, false),    // 0x85
+        info(STX, ZeroPage, 3, false),    // 0x86
+        info(SAX, ZeroPage, 3, true),     // 0x87
+        info(DEY, Implied, 2, false),     // 0x88
+        info(NOP, Immediate, 2, true),    // 0x89
+        info(TXA, Implied, 2, false),     // 0x8a
+        info(ANE, Immediate, 2, true),    // 0x8b
+        info(STY, Absolute, 4, false),    // 0x8c
+        info(STA, Absolute, 4, false),    // 0x8d
+        info(STX, Absolute, 4, false),    // 0x8e
+        info(SAX, Absolute, 4, true),     // 0x8f
+        info(BCC, Relative, 2, false),    // 0x90
+        info(STA, IndirectY, 6, false),   // 0x91
+        info(JAM, Implied, 2, true),      // 0x92
+        info(SHA, IndirectY, 6, true),    // 0x93
+        info(STY, ZeroPageX, 4, false),   // 0x94
+        info(STA, ZeroPageX, 4, false),   // 0x95
+        info(STX, ZeroPageY, 4, false),   // 0x96
+        info(SAX, ZeroPageY, 4, true),    // 0x97
+        info(TYA, Implied, 2, false),     // 0x98
+        info(STA, AbsoluteY, 5, false),   // 0x99
+        info(TXS, Implied, 2, false),     // 0x9a
+        info(TAS, AbsoluteY, 5, true),    // 0x9b
+        info(SHY, AbsoluteX, 5, true),    // 0x9c
+        info(STA, AbsoluteX, 5, false),   // 0x9d
+        info(SHX, AbsoluteY, 5, true),    // 0x9e
+        info(SHA, AbsoluteY, 5, true),    // 0x9f
+        info(LDY, Immediate, 2, false),   // 0xa0
+        info(LDA, IndirectX, 6, false),   // 0xa1
+        info(LDX, Immediate, 2, false),   // 0xa2
+        info(LAX, IndirectX, 6, true),    // 0xa3
+        info(LDY, ZeroPage, 3, false),    // 0xa4
+        info(LDA, ZeroPage, 3, false),    // 0xa5
+        info(LDX, ZeroPage, 3, false),    // 0xa6
+        info(LAX, ZeroPage, 3, true),     // 0xa7
+        info(TAY, Implied, 2, false),     // 0xa8
+        info(LDA, Immediate, 2, false),   // 0xa9
+        info(TAX, Implied, 2, false),     // 0xaa
+        info(LXA, Immediate, 2, true),    // 0xab
+        info(LDY, Absolute, 4, false),    // 0xac
+        info(LDA, Absolute, 4, false),    // 0xad
+        info(LDX, Absolute, 4, false),    // 0xae
+        info(LAX, Absolute, 4, true),     // 0xaf
+        info(BCS, Relative, 2, false),    // 0xb0
+        info(LDA, IndirectY, 5, false),   // 0xb1
+        info(JAM, Implied, 2, true),      // 0xb2
+        info(LAX, IndirectY, 5, true),    // 0xb3
+        info(LDY, ZeroPageX, 4, false),   // 0xb4
+        info(LDA, ZeroPageX, 4, false),   // 0xb5
+        info(LDX, ZeroPageY, 4, false),   // 0xb6
+        info(LAX, ZeroPageY, 4, true),    // 0xb7
+        info(CLV, Implied, 2, false),     // 0xb8
+        info(LDA, AbsoluteY, 4, false),   // 0xb9
+        info(TSX, Implied, 2, false),     // 0xba
+        info(LAS, AbsoluteY, 4, true),    // 0xbb
+        info(LDY, AbsoluteX, 4, false),   // 0xbc
+        info(LDA, AbsoluteX, 4, false),   // 0xbd
+        info(LDX, AbsoluteY, 4, false),   // 0xbe
+        info(LAX, AbsoluteY, 4, true),    // 0xbf
+        info(CPY, Immediate, 2, false),   // 0xc0
+        info(CMP, IndirectX, 6, false),   // 0xc1
+        info(NOP, Immediate, 2, true),    // 0xc2
+        info(DCP, IndirectX, 8, true),    // 0xc3
+        info(CPY, ZeroPage, 3, false),    // 0xc4
+        info(CMP, ZeroPage, 3, false),    // 0xc5
+        info(DEC, ZeroPage, 5, false),    // 0xc6
+        info(DCP, ZeroPage, 5, true),     // 0xc7
+        info(INY, Implied, 2, false),     // 0xc8
+        info(CMP, Immediate, 2, false),   // 0xc9
+        info(DEX, Implied, 2, false),     // 0xca
+        info(SBX, Immediate, 2, true),    // 0xcb
+        info(CPY, Absolute, 4, false),    // 0xcc
+        info(CMP, Absolute, 4, false),    // 0xcd
+        info(DEC, Absolute, 6, false),    // 0xce
+        info(DCP, Absolute, 6, true),     // 0xcf
+        info(BNE, Relative, 2, false),    // 0xd0
+        info(CMP, IndirectY, 5, false),   // 0xd1
+        info(JAM, Implied, 2, true),      // 0xd2
+        info(DCP, IndirectY, 8, true),    // 0xd3
+        info(NOP, ZeroPageX, 4, true),    // 0xd4
+        info(CMP, ZeroPageX, 4, false),   // 0xd5
+        info(DEC, ZeroPageX, 6, false),   // 0xd6
+        info(DCP, ZeroPageX, 6, true),    // 0xd7
+        info(CLD, Implied, 2, false),     // 0xd8
+        info(CMP, AbsoluteY, 4, false),   // 0xd9
+        info(NOP, Implied, 2, true),      // 0xda
+        info(DCP, AbsoluteY, 7, true),    // 0xdb
+        info(NOP, AbsoluteX, 4, true),    // 0xdc
+        info(CMP, AbsoluteX, 4, false),   // 0xdd
+        info(DEC, AbsoluteX, 7, false),   // 0xde
+        info(DCP, AbsoluteX, 7, true),    // 0xdf
+        info(CPX, Immediate, 2, false),   // 0xe0
+        info(SBC, IndirectX, 6, false),   // 0xe1
+        info(NOP, Immediate, 2, true),    // 0xe2
+        info(ISC, IndirectX, 8, true),    // 0xe3
+        info(CPX, ZeroPage, 3, false),    // 0xe4
+        info(SBC, ZeroPage, 3, false),    // 0xe5
+        info(INC, ZeroPage, 5, false),    // 0xe6
+        info(ISC, ZeroPage, 5, true),     // 0xe7
+        info(INX, Implied, 2, false),     // 0xe8
+        info(SBC, Immediate, 2, false),   // 0xe9
+        info(NOP, Implied, 2, false),     // 0xea
+        info(SBC, Immediate, 2, true),    // 0xeb
+        info(CPX, Absolute, 4, false),    // 0xec
+        info(SBC, Absolute, 4, false),    // 0xed
+        info(INC, Absolute, 6, false),    // 0xee
+        info(ISC, Absolute, 6, true),     // 0xef
+        info(BEQ, Relative, 2, false),    // 0xf0
+        info(SBC, IndirectY, 5, false),   // 0xf1
+        info(JAM, Implied, 2, true),      // 0xf2
+        info(ISC, IndirectY, 8, true),    // 0xf3
+        info(NOP, ZeroPageX, 4, true),    // 0xf4
+        info(SBC, ZeroPageX, 4, false),   // 0xf5
+        info(INC, ZeroPageX, 6, false),   // 0xf6
+        info(ISC, ZeroPageX, 6, true),    // 0xf7
+        info(SED, Implied, 2, false),     // 0xf8
+        info(SBC, AbsoluteY, 4, false),   // 0xf9
+        info(NOP, Implied, 2, true),      // 0xfa
+        info(ISC, AbsoluteY, 7, true),    // 0xfb
+        info(NOP, AbsoluteX, 4, true),    // 0xfc
+        info(SBC, AbsoluteX, 4, false),   // 0xfd
+        info(INC, AbsoluteX, 7, false),   // 0xfe
+        info(ISC, AbsoluteX, 7, true),    // 0xff
+    ];
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const N: u8 = M6502::FLAG_N;
+    const V: u8 = M6502::FLAG_V;
+    const Z: u8 = M6502::FLAG_Z;
+    const C: u8 = M6502::FLAG_C;
+
+    #[test]
+    fn lengths() {
+        for (byte, info) in OPCODE_INFO.iter().enumerate() {
+            assert_eq!(info.len, 1 + info.mode.operand_len(), "{byte:02X}");
+            assert_eq!(OpcodeInfo::of(byte as u8), info);
+        }
+        let legal = OPCODE_INFO.iter().filter(|info| !info.illegal).count();
+        assert_eq!(legal, 151);
+    }
+
+    #[test]
+    fn access() {
+        for info in &OPCODE_INFO {
+            let expected = match (info.mnemonic, info.mode) {
+                (_, AddressingMode::Implied | AddressingMode::Accumulator) => MemoryAccess::None,
+                (_, AddressingMode::Immediate | AddressingMode::Relative) => MemoryAccess::None,
+                (Mnemonic::STA | Mnemonic::STX | Mnemonic::STY | Mnemonic::SAX, _) => {
+                    MemoryAccess::Write
+                }
+                (
+                    Mnemonic::ASL
+                    | Mnemonic::LSR
+                    | Mnemonic::ROL
+                    | Mnemonic::ROR
+                    | Mnemonic::INC
+                    | Mnemonic::DEC,
+                    _,
+                ) => MemoryAccess::ReadModifyWrite,
+                (Mnemonic::JMP | Mnemonic::JSR, _) => MemoryAccess::None,
+                _ => continue,
+            };
+            assert_eq!(info.access, expected, "{:?} {:?}", info.mnemonic, info.mode);
+        }
+
+        let access = |byte: u8| OpcodeInfo::of(byte).access;
+        assert_eq!(access(0xad), MemoryAccess::Read); // LDA $1234
+        assert_eq!(access(0x91), MemoryAccess::Write); // STA ($10),Y
+        assert_eq!(access(0x9f), MemoryAccess::Write); // SHA $1234,Y
+        assert_eq!(access(0xc7), MemoryAccess::ReadModifyWrite); // DCP $10
+        assert_eq!(access(0x0a), MemoryAccess::None); // ASL A
+        assert_eq!(access(0x6c), MemoryAccess::None); // JMP ($1234)
+    }
+
+    #[test]
+    fn flags() {
+        let flags = |byte: u8| OpcodeInfo::of(byte).flags;
+        assert_eq!(flags(0x69), N | V | Z | C); // ADC #
+        assert_eq!(flags(0xe5), N | V | Z | C); // SBC $10
+        assert_eq!(flags(0xcd), N | Z | C); // CMP $1234
+        assert_eq!(flags(0x24), N | V | Z); // BIT $10
+        assert_eq!(flags(0xee), N | Z); // INC $1234
+        assert_eq!(flags(0x28), N | V | M6502::FLAG_D | M6502::FLAG_I | Z | C); // PLP
+        assert_eq!(flags(0x18), C); // CLC
+        assert_eq!(flags(0x8d), 0); // STA $1234
+        assert_eq!(flags(0x4c), 0); // JMP $1234
+    }
+
+    #[test]
+    fn page_cross() {
+        for info in &OPCODE_INFO {
+            match info.mode {
+                AddressingMode::Relative => assert!(info.page_cross),
+                AddressingMode::AbsoluteX
+                | AddressingMode::AbsoluteY
+                | AddressingMode::IndirectY
+                    if info.access == MemoryAccess::Read => {}
+                _ => assert!(!info.page_cross, "{:?} {:?}", info.mnemonic, info.mode),
+            }
+        }
+
+        let page_cross = |byte: u8| OpcodeInfo::of(byte).page_cross;
+        assert!(page_cross(0xbd)); // LDA $1234,X
+        assert!(page_cross(0xb1)); // LDA ($10),Y
+        assert!(page_cross(0xbf)); // LAX $1234,Y
+        assert!(!page_cross(0x9d)); // STA $1234,X
+        assert!(!page_cross(0xfe)); // INC $1234,X
+    }
+
+    #[test]
+    fn opcode_bytes() {
+        for byte in 0..=u8::MAX {
+            let op = Opcode::from_byte(byte);
+            assert_eq!(op.to_byte(), byte);
+            assert_eq!(Opcode::from(byte), op);
+            assert_eq!(u8::from(op), byte);
+            assert_eq!(op.info(), &OPCODE_INFO[usize::from(byte)]);
+        }
+        assert_eq!(Opcode::from_byte(0xa9), Opcode::LDA_imm);
+        assert_eq!(Opcode::from_byte(0xff), Opcode::UDI_105);
+    }
+
+    #[test]
+    fn iter() {
+        assert_eq!(Opcode::iter().len(), 256);
+        assert!(Opcode::iter().map(Opcode::to_byte).eq(0..=u8::MAX));
+        assert!(Opcode::iter()
+            .rev()
+            .map(Opcode::to_byte)
+            .eq((0..=u8::MAX).rev()));
+    }
+}
@@ -1,5 +1,6 @@
 /// The internal state registers of the 6502 chip.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct M6502 {
     /// The accumulator is the processor register used in arithmetic and
     /// logical operations, and push/pop stack operations.
@@ -1,7 +1,17 @@
-use super::{instruction::Opcode, M6502};
+use super::{
+    instruction::{AddressingMode, MemoryAccess, Mnemonic, Opcode},
+    M6502,
+};
 use crate::memory::Memory;
 
+mod snapshot;
+
+pub use snapshot::SnapshotError;
+
+/// Position within the instruction being executed, where the first cycle is
+/// the opcode fetch.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 enum ClockCycle {
     Cycle1,
     Cycle2,
@@ -10,231 +20,968 @@ enum ClockCycle {
     Cycle5,
     Cycle6,
     Cycle7,
+    Cycle8,
+}
+
+impl ClockCycle {
+    #[inline]
+    fn next(self) -> ClockCycle {
+        match self {
+            ClockCycle::Cycle1 => ClockCycle::Cycle2,
+            ClockCycle::Cycle2 => ClockCycle::Cycle3,
+            ClockCycle::Cycle3 => ClockCycle::Cycle4,
+            ClockCycle::Cycle4 => ClockCycle::Cycle5,
+            ClockCycle::Cycle5 => ClockCycle::Cycle6,
+            ClockCycle::Cycle6 => ClockCycle::Cycle7,
+            ClockCycle::Cycle7 | ClockCycle::Cycle8 => ClockCycle::Cycle8,
+        }
+    }
+}
+
+/// An interrupt sequence run in place of an instruction. They all share the
+/// cycles of the `BRK` instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Interrupt {
+    Reset,
+    Nmi,
+    Irq,
 }
 
-struct CycleTicker {
+/// Status flag bits only seen when pushed onto the stack.
+const FLAGS_STACK_ONLY: u8 = M6502::FLAG_B | 0x20;
+
+/// Drives the 6502 one clock cycle at a time.
+///
+/// Every cycle makes exactly one access to memory, just like the real
+/// processor, including the dummy reads and writes the 6502 makes while
+/// working out addresses. Register changes made by an instruction are visible
+/// once its last cycle has run.
+///
+/// The processor starts executing at the program counter of the given state.
+/// Use [`reset`] to run the reset sequence instead, loading the program
+/// counter from the reset vector.
+///
+/// [`reset`]: CycleTicker::reset
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CycleTicker {
     cycle: ClockCycle,
     op: Opcode,
     addr: u16,
+    base: u16,
+    data: u8,
+    interrupt: Option<Interrupt>,
+    irq: bool,
+    nmi: bool,
+    nmi_pending: bool,
+    reset_pending: bool,
+    poll_i: bool,
+    jammed: bool,
+    cycles: u64,
     state: M6502,
 }
 
-pub fn tick_cycle<M: Memory>(cpu: &mut CycleTicker, mem: &mut M) {
-    match (cpu.op, cpu.cycle) {
-        // Add with carry instructions.
-        (Opcode::ADC_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::ADC_inY, _) => todo!("Not implemented OP!"),
-
-        // Logical AND instruction.
-        (Opcode::AND_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::AND_inY, _) => todo!("Not implemented OP!"),
-
-        // Arithmetic shift left instructions.
-        (Opcode::ASL_acc, _) => todo!("Not implemented OP!"),
-        (Opcode::ASL_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::ASL_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::ASL_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::ASL_abX, _) => todo!("Not implemented OP!"),
+impl CycleTicker {
+    /// Returns a new driver for `state`, about to fetch the instruction at
+    /// the program counter.
+    pub fn new(state: M6502) -> CycleTicker {
+        CycleTicker {
+            cycle: ClockCycle::Cycle1,
+            op: Opcode::NOP_imp,
+            addr: 0,
+            base: 0,
+            data: 0,
+            interrupt: None,
+            irq: false,
+            nmi: false,
+            nmi_pending: false,
+            reset_pending: false,
+            poll_i: state.flag_i(),
+            jammed: false,
+            cycles: 0,
+            state,
+        }
+    }
 
-        // Branch instructions.
-        (Opcode::BCC_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BCS_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BEQ_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BMI_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BNE_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BPL_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BVC_rel, _) => todo!("Not implemented OP!"),
-        (Opcode::BVS_rel, _) => todo!("Not implemented OP!"),
-
-        // Bit test instruction.
-        (Opcode::BIT_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::BIT_abs, _) => todo!("Not implemented OP!"),
-
-        // Break instruction.
-        (Opcode::BRK_imp, _) => todo!("Not implemented OP!"),
-
-        // Clear status flag instructions.
-        (Opcode::CLC_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::CLD_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::CLI_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::CLV_imp, _) => todo!("Not implemented OP!"),
-
-        // Compare instructions.
-        (Opcode::CMP_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::CMP_inY, _) => todo!("Not implemented OP!"),
-
-        (Opcode::CPX_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::CPX_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::CPX_abs, _) => todo!("Not implemented OP!"),
-
-        (Opcode::CPY_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::CPY_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::CPY_abs, _) => todo!("Not implemented OP!"),
-
-        // Decrement instructions.
-        (Opcode::DEC_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::DEC_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::DEC_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::DEC_abX, _) => todo!("Not implemented OP!"),
-
-        (Opcode::DEX_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::DEY_imp, _) => todo!("Not implemented OP!"),
-
-        // Logical XOR instructions.
-        (Opcode::EOR_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::EOR_inY, _) => todo!("Not implemented OP!"),
-
-        // Increment instructions.
-        (Opcode::INC_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::INC_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::INC_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::INC_abX, _) => todo!("Not implemented OP!"),
-
-        (Opcode::INX_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::INY_imp, _) => todo!("Not implemented OP!"),
+    /// Returns the registers.
+    #[inline]
+    pub fn state(&self) -> &M6502 {
+        &self.state
+    }
 
-        // Jump instructions.
-        (Opcode::JMP_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::JMP_ind, _) => todo!("Not implemented OP!"),
-
-        (Opcode::JSR_abs, _) => todo!("Not implemented OP!"),
-
-        // Load register A instructions.
-        (Opcode::LDA_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::LDA_inY, _) => todo!("Not implemented OP!"),
-
-        // Load register X instructions.
-        (Opcode::LDX_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::LDX_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::LDX_zpY, _) => todo!("Not implemented OP!"),
-        (Opcode::LDX_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::LDX_abY, _) => todo!("Not implemented OP!"),
-
-        // Load register Y instructions.
-        (Opcode::LDY_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::LDY_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::LDY_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::LDY_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::LDY_abX, _) => todo!("Not implemented OP!"),
-
-        // Logical shift right instructions.
-        (Opcode::LSR_acc, _) => todo!("Not implemented OP!"),
-        (Opcode::LSR_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::LSR_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::LSR_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::LSR_abX, _) => todo!("Not implemented OP!"),
-
-        // No operation instruction.
-        (Opcode::NOP_imp, _) => todo!("Not implemented OP!"),
-
-        // Logical OR instructions.
-        (Opcode::ORA_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::ORA_inY, _) => todo!("Not implemented OP!"),
+    /// Returns the registers, allowing them to be changed.
+    #[inline]
+    pub fn state_mut(&mut self) -> &mut M6502 {
+        &mut self.state
+    }
+
+    /// Consumes the driver returning the registers.
+    #[inline]
+    pub fn into_state(self) -> M6502 {
+        self.state
+    }
+
+    /// Returns the number of cycles run so far.
+    #[inline]
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Sets the number of cycles run so far.
+    #[inline]
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    /// Returns whether the processor is between two instructions, i.e. the
+    /// next cycle fetches an opcode or starts an interrupt sequence.
+    #[inline]
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.cycle == ClockCycle::Cycle1
+    }
+
+    /// Returns whether the processor has been halted by one of the `JAM`
+    /// illegal opcodes. Only a reset gets it going again.
+    #[inline]
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /// Pulls the reset line. The reset sequence runs once the current
+    /// instruction has finished, loading the program counter from `$FFFC`.
+    pub fn reset(&mut self) {
+        self.reset_pending = true;
+        if self.jammed {
+            self.jammed = false;
+            self.cycle = ClockCycle::Cycle1;
+        }
+    }
+
+    /// Returns whether the IRQ line is asserted.
+    #[inline]
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Sets whether the IRQ line is asserted. The IRQ is level triggered so
+    /// an interrupt is taken after every instruction for as long as the line
+    /// is asserted and interrupts are enabled.
+    #[inline]
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq = asserted;
+    }
+
+    /// Returns whether the NMI line is asserted.
+    #[inline]
+    pub fn nmi(&self) -> bool {
+        self.nmi
+    }
+
+    /// Sets whether the NMI line is asserted. The NMI is edge triggered so
+    /// only asserting the line when it was not already asserted triggers an
+    /// interrupt.
+    #[inline]
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = asserted;
+    }
+
+    /// Runs a single clock cycle.
+    pub fn tick<M: Memory>(&mut self, mem: &mut M) {
+        self.cycles += 1;
+        if self.jammed {
+            mem.read(0xffff);
+            return;
+        }
+
+        // Interrupts are polled before the last cycle of an instruction, so
+        // a change to the interrupt disable flag made by the last cycle only
+        // takes effect after the next instruction.
+        let poll_i = self.state.flag_i();
+        if self.cycle == ClockCycle::Cycle1 {
+            self.begin(mem);
+        } else {
+            tick_cycle(self, mem);
+        }
+        self.poll_i = poll_i;
+    }
+
+    /// Runs cycles until the current instruction, or interrupt sequence, has
+    /// finished. When called between two instructions the whole next
+    /// instruction is run. Returns the number of cycles run.
+    pub fn step<M: Memory>(&mut self, mem: &mut M) -> u32 {
+        let start = self.cycles;
+        loop {
+            self.tick(mem);
+            if self.cycle == ClockCycle::Cycle1 || self.jammed {
+                break;
+            }
+        }
+        (self.cycles - start) as u32
+    }
+
+    /// Fetches the next opcode, or starts an interrupt sequence.
+    fn begin<M: Memory>(&mut self, mem: &mut M) {
+        self.interrupt = if self.reset_pending {
+            Some(Interrupt::Reset)
+        } else if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if self.irq && !self.poll_i {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        };
+
+        if self.interrupt.is_some() {
+            // The opcode is fetched but thrown away.
+            mem.read(self.state.pc);
+            self.op = Opcode::BRK_imp;
+        } else {
+            self.op = Opcode::from(mem.fetch(self.state.pc));
+            self.state.pc = self.state.pc.wrapping_add(1);
+        }
+        self.cycle = ClockCycle::Cycle2;
+    }
+
+    #[inline]
+    fn next(&mut self) {
+        self.cycle = self.cycle.next();
+    }
+
+    #[inline]
+    fn done(&mut self) {
+        self.cycle = ClockCycle::Cycle1;
+    }
+
+    #[inline]
+    fn read_pc<M: Memory>(&mut self, mem: &mut M) -> u8 {
+        let value = mem.read(self.state.pc);
+        self.state.pc = self.state.pc.wrapping_add(1);
+        value
+    }
+
+    #[inline]
+    fn stack_addr(&self) -> u16 {
+        0x0100 | u16::from(self.state.sp)
+    }
+
+    #[inline]
+    fn push<M: Memory>(&mut self, mem: &mut M, value: u8) {
+        // The reset sequence goes through the motions of an interrupt, but
+        // with the write line held high.
+        if self.interrupt == Some(Interrupt::Reset) {
+            mem.read(self.stack_addr());
+        } else {
+            mem.write(self.stack_addr(), value);
+        }
+        self.state.sp = self.state.sp.wrapping_sub(1);
+    }
+
+    #[inline]
+    fn pull<M: Memory>(&mut self, mem: &mut M) -> u8 {
+        self.state.sp = self.state.sp.wrapping_add(1);
+        mem.read(self.stack_addr())
+    }
+
+    /// Returns the index register used by the addressing mode.
+    #[inline]
+    fn index(&self, mode: AddressingMode) -> u8 {
+        match mode {
+            AddressingMode::ZeroPageY | AddressingMode::AbsoluteY | AddressingMode::IndirectY => {
+                self.state.y
+            }
+            _ => self.state.x,
+        }
+    }
+
+    /// Returns the effective address before the carry from indexing has been
+    /// added to the high byte.
+    #[inline]
+    fn unfixed_addr(&self) -> u16 {
+        (self.base & 0xff00) | (self.addr & 0x00ff)
+    }
+
+    #[inline]
+    fn set_nz(&mut self, value: u8) {
+        self.state.set_flag_n(value & 0x80 != 0);
+        self.state.set_flag_z(value == 0);
+    }
+
+    fn branch_taken(&self) -> bool {
+        let s = &self.state;
+        match self.op.mnemonic() {
+            Mnemonic::BCC => !s.flag_c(),
+            Mnemonic::BCS => s.flag_c(),
+            Mnemonic::BNE => !s.flag_z(),
+            Mnemonic::BEQ => s.flag_z(),
+            Mnemonic::BPL => !s.flag_n(),
+            Mnemonic::BMI => s.flag_n(),
+            Mnemonic::BVC => !s.flag_v(),
+            _ => s.flag_v(),
+        }
+    }
+
+    fn compare(&mut self, reg: u8, value: u8) {
+        self.state.set_flag_c(reg >= value);
+        self.set_nz(reg.wrapping_sub(value));
+    }
+
+    fn adc(&mut self, value: u8) {
+        let a = self.state.a;
+        let c = u16::from(self.state.flag_c());
+        let sum = u16::from(a) + u16::from(value) + c;
+
+        if !self.state.flag_d() {
+            let result = sum as u8;
+            self.state.set_flag_c(sum > 0xff);
+            self.state
+                .set_flag_v((a ^ result) & (value ^ result) & 0x80 != 0);
+            self.set_nz(result);
+            self.state.a = result;
+            return;
+        }
+
+        // The NMOS 6502 sets the zero flag from the binary result, while the
+        // negative and overflow flags come from the result before the high
+        // digit is adjusted.
+        let mut lo = u16::from(a & 0x0f) + u16::from(value & 0x0f) + c;
+        if lo >= 0x0a {
+            lo = ((lo + 0x06) & 0x0f) + 0x10;
+        }
+        let signed = i16::from((a & 0xf0) as i8) + i16::from((value & 0xf0) as i8) + lo as i16;
+        let mut result = u16::from(a & 0xf0) + u16::from(value & 0xf0) + lo;
+        if result >= 0xa0 {
+            result += 0x60;
+        }
+
+        self.state.set_flag_z(sum as u8 == 0);
+        self.state.set_flag_n(signed & 0x80 != 0);
+        self.state.set_flag_v(!(-128..=127).contains(&signed));
+        self.state.set_flag_c(result > 0xff);
+        self.state.a = result as u8;
+    }
+
+    fn sbc(&mut self, value: u8) {
+        let a = self.state.a;
+        let borrow = i16::from(!self.state.flag_c());
+
+        // The flags are always set as for binary mode.
+        let decimal = self.state.flag_d();
+        self.state.set_flag_d(false);
+        self.adc(!value);
+        self.state.set_flag_d(decimal);
+
+        if decimal {
+            let mut lo = i16::from(a & 0x0f) - i16::from(value & 0x0f) - borrow;
+            if lo < 0 {
+                lo = ((lo - 0x06) & 0x0f) - 0x10;
+            }
+            let mut result = i16::from(a & 0xf0) - i16::from(value & 0xf0) + lo;
+            if result < 0 {
+                result -= 0x60;
+            }
+            self.state.a = result as u8;
+        }
+    }
+
+    fn arr(&mut self, value: u8) {
+        let t = self.state.a & value;
+        let c = self.state.flag_c();
+        let mut result = (t >> 1) | (u8::from(c) << 7);
+
+        if !self.state.flag_d() {
+            self.set_nz(result);
+            self.state.set_flag_c(result & 0x40 != 0);
+            self.state
+                .set_flag_v(((result >> 6) ^ (result >> 5)) & 0x01 != 0);
+            self.state.a = result;
+            return;
+        }
+
+        self.state.set_flag_n(c);
+        self.state.set_flag_z(result == 0);
+        self.state.set_flag_v((t ^ result) & 0x40 != 0);
+        if (t & 0x0f) + (t & 0x01) > 0x05 {
+            result = (result & 0xf0) | (result.wrapping_add(0x06) & 0x0f);
+        }
+        let carry = u16::from(t & 0xf0) + u16::from(t & 0x10) > 0x50;
+        if carry {
+            result = result.wrapping_add(0x60);
+        }
+        self.state.set_flag_c(carry);
+        self.state.a = result;
+    }
+
+    /// Runs an implied or accumulator instruction.
+    fn execute_implied(&mut self) {
+        let s = &mut self.state;
+        match self.op.mnemonic() {
+            Mnemonic::CLC => s.set_flag_c(false),
+            Mnemonic::CLD => s.set_flag_d(false),
+            Mnemonic::CLI => s.set_flag_i(false),
+            Mnemonic::CLV => s.set_flag_v(false),
+            Mnemonic::SEC => s.set_flag_c(true),
+            Mnemonic::SED => s.set_flag_d(true),
+            Mnemonic::SEI => s.set_flag_i(true),
+            Mnemonic::TAX => {
+                s.x = s.a;
+                self.set_nz(self.state.x);
+            }
+            Mnemonic::TAY => {
+                s.y = s.a;
+                self.set_nz(self.state.y);
+            }
+            Mnemonic::TSX => {
+                s.x = s.sp;
+                self.set_nz(self.state.x);
+            }
+            Mnemonic::TXA => {
+                s.a = s.x;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::TXS => s.sp = s.x,
+            Mnemonic::TYA => {
+                s.a = s.y;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::INX => {
+                s.x = s.x.wrapping_add(1);
+                self.set_nz(self.state.x);
+            }
+            Mnemonic::INY => {
+                s.y = s.y.wrapping_add(1);
+                self.set_nz(self.state.y);
+            }
+            Mnemonic::DEX => {
+                s.x = s.x.wrapping_sub(1);
+                self.set_nz(self.state.x);
+            }
+            Mnemonic::DEY => {
+                s.y = s.y.wrapping_sub(1);
+                self.set_nz(self.state.y);
+            }
+            Mnemonic::ASL | Mnemonic::LSR | Mnemonic::ROL | Mnemonic::ROR => {
+                self.state.a = self.modify(self.state.a);
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs an instruction reading a value from memory or an immediate
+    /// operand.
+    fn execute_read(&mut self, value: u8) {
+        match self.op.mnemonic() {
+            Mnemonic::ADC => self.adc(value),
+            Mnemonic::SBC => self.sbc(value),
+            Mnemonic::AND => {
+                self.state.a &= value;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::ORA => {
+                self.state.a |= value;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::EOR => {
+                self.state.a ^= value;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::BIT => {
+                self.state.set_flag_n(value & 0x80 != 0);
+                self.state.set_flag_v(value & 0x40 != 0);
+                self.state.set_flag_z(self.state.a & value == 0);
+            }
+            Mnemonic::CMP => self.compare(self.state.a, value),
+            Mnemonic::CPX => self.compare(self.state.x, value),
+            Mnemonic::CPY => self.compare(self.state.y, value),
+            Mnemonic::LDA => {
+                self.state.a = value;
+                self.set_nz(value);
+            }
+            Mnemonic::LDX => {
+                self.state.x = value;
+                self.set_nz(value);
+            }
+            Mnemonic::LDY => {
+                self.state.y = value;
+                self.set_nz(value);
+            }
+            Mnemonic::LAX => {
+                self.state.a = value;
+                self.state.x = value;
+                self.set_nz(value);
+            }
+            Mnemonic::LAS => {
+                let value = value & self.state.sp;
+                self.state.a = value;
+                self.state.x = value;
+                self.state.sp = value;
+                self.set_nz(value);
+            }
+            Mnemonic::ANC => {
+                self.state.a &= value;
+                self.set_nz(self.state.a);
+                self.state.set_flag_c(self.state.a & 0x80 != 0);
+            }
+            Mnemonic::ALR => {
+                let a = self.state.a & value;
+                self.state.set_flag_c(a & 0x01 != 0);
+                self.state.a = a >> 1;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::ARR => self.arr(value),
+            // The unstable instructions use the magic constant seen on most
+            // chips.
+            Mnemonic::ANE => {
+                self.state.a = (self.state.a | 0xee) & self.state.x & value;
+                self.set_nz(self.state.a);
+            }
+            Mnemonic::LXA => {
+                let a = (self.state.a | 0xee) & value;
+                self.state.a = a;
+                self.state.x = a;
+                self.set_nz(a);
+            }
+            Mnemonic::SBX => {
+                let ax = self.state.a & self.state.x;
+                self.state.set_flag_c(ax >= value);
+                self.state.x = ax.wrapping_sub(value);
+                self.set_nz(self.state.x);
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the value written by a store instruction.
+    fn store_value(&self) -> u8 {
+        match self.op.mnemonic() {
+            Mnemonic::STX => self.state.x,
+            Mnemonic::STY => self.state.y,
+            Mnemonic::SAX => self.state.a & self.state.x,
+            _ => self.state.a,
+        }
+    }
+
+    /// Writes the value of a store instruction. The unstable stores `SHA`,
+    /// `SHX`, `SHY` and `TAS` AND the value with the high byte of the base
+    /// address plus one, and when indexing crosses a page that value also
+    /// replaces the high byte of the address.
+    fn write_store<M: Memory>(&mut self, mem: &mut M) {
+        let hi = (self.base >> 8) as u8;
+        let unstable = match self.op.mnemonic() {
+            Mnemonic::SHA => Some(self.state.a & self.state.x),
+            Mnemonic::SHX => Some(self.state.x),
+            Mnemonic::SHY => Some(self.state.y),
+            Mnemonic::TAS => {
+                self.state.sp = self.state.a & self.state.x;
+                Some(self.state.sp)
+            }
+            _ => None,
+        };
+
+        match unstable {
+            Some(value) => {
+                let value = value & hi.wrapping_add(1);
+                let addr = if self.unfixed_addr() != self.addr {
+                    (u16::from(value) << 8) | (self.addr & 0x00ff)
+                } else {
+                    self.addr
+                };
+                mem.write(addr, value);
+            }
+            None => mem.write(self.addr, self.store_value()),
+        }
+    }
+
+    /// Runs a read-modify-write instruction, returning the value to write
+    /// back.
+    fn modify(&mut self, value: u8) -> u8 {
+        let c = u8::from(self.state.flag_c());
+        let result = match self.op.mnemonic() {
+            Mnemonic::ASL | Mnemonic::SLO => {
+                self.state.set_flag_c(value & 0x80 != 0);
+                value << 1
+            }
+            Mnemonic::LSR | Mnemonic::SRE => {
+                self.state.set_flag_c(value & 0x01 != 0);
+                value >> 1
+            }
+            Mnemonic::ROL | Mnemonic::RLA => {
+                self.state.set_flag_c(value & 0x80 != 0);
+                (value << 1) | c
+            }
+            Mnemonic::ROR | Mnemonic::RRA => {
+                self.state.set_flag_c(value & 0x01 != 0);
+                (value >> 1) | (c << 7)
+            }
+            Mnemonic::INC | Mnemonic::ISC => value.wrapping_add(1),
+            _ => value.wrapping_sub(1),
+        };
+
+        match self.op.mnemonic() {
+            Mnemonic::SLO => self.execute_combined(Mnemonic::ORA, result),
+            Mnemonic::RLA => self.execute_combined(Mnemonic::AND, result),
+            Mnemonic::SRE => self.execute_combined(Mnemonic::EOR, result),
+            Mnemonic::RRA => self.adc(result),
+            Mnemonic::DCP => self.compare(self.state.a, result),
+            Mnemonic::ISC => self.sbc(result),
+            _ => self.set_nz(result),
+        }
+        result
+    }
+
+    /// Runs the logical part of the combined illegal instructions.
+    fn execute_combined(&mut self, mnemonic: Mnemonic, value: u8) {
+        match mnemonic {
+            Mnemonic::ORA => self.state.a |= value,
+            Mnemonic::AND => self.state.a &= value,
+            _ => self.state.a ^= value,
+        }
+        self.set_nz(self.state.a);
+    }
+}
+
+/// Runs a single cycle of the current instruction, after the opcode fetch.
+fn tick_cycle<M: Memory>(cpu: &mut CycleTicker, mem: &mut M) {
+    use ClockCycle::*;
+
+    match (cpu.op, cpu.cycle) {
+        // Break instruction, also running the interrupt and reset sequences.
+        (Opcode::BRK_imp, Cycle2) => {
+            mem.read(cpu.state.pc);
+            if cpu.interrupt.is_none() {
+                cpu.state.pc = cpu.state.pc.wrapping_add(1);
+            }
+            cpu.next();
+        }
+        (Opcode::BRK_imp, Cycle3) => {
+            cpu.push(mem, (cpu.state.pc >> 8) as u8);
+            cpu.next();
+        }
+        (Opcode::BRK_imp, Cycle4) => {
+            cpu.push(mem, cpu.state.pc as u8);
+            cpu.next();
+        }
+        (Opcode::BRK_imp, Cycle5) => {
+            let flags = match cpu.interrupt {
+                None => cpu.state.flags | FLAGS_STACK_ONLY,
+                Some(_) => (cpu.state.flags & !M6502::FLAG_B) | 0x20,
+            };
+            cpu.push(mem, flags);
+
+            // A pending NMI hijacks the vector of a BRK or IRQ.
+            cpu.addr = if cpu.interrupt == Some(Interrupt::Reset) {
+                0xfffc
+            } else if cpu.nmi_pending {
+                cpu.nmi_pending = false;
+                0xfffa
+            } else {
+                0xfffe
+            };
+            cpu.next();
+        }
+        (Opcode::BRK_imp, Cycle6) => {
+            cpu.data = mem.read(cpu.addr);
+            cpu.state.set_flag_i(true);
+            cpu.next();
+        }
+        (Opcode::BRK_imp, _) => {
+            let hi = mem.read(cpu.addr.wrapping_add(1));
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            if cpu.interrupt == Some(Interrupt::Reset) {
+                cpu.reset_pending = false;
+            }
+            cpu.interrupt = None;
+            cpu.done();
+        }
+
+        // Jump to subroutine instruction.
+        (Opcode::JSR_abs, Cycle2) => {
+            cpu.data = cpu.read_pc(mem);
+            cpu.next();
+        }
+        (Opcode::JSR_abs, Cycle3) => {
+            mem.read(cpu.stack_addr());
+            cpu.next();
+        }
+        (Opcode::JSR_abs, Cycle4) => {
+            cpu.push(mem, (cpu.state.pc >> 8) as u8);
+            cpu.next();
+        }
+        (Opcode::JSR_abs, Cycle5) => {
+            cpu.push(mem, cpu.state.pc as u8);
+            cpu.next();
+        }
+        (Opcode::JSR_abs, _) => {
+            let hi = mem.read(cpu.state.pc);
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            cpu.done();
+        }
+
+        // Return instructions.
+        (Opcode::RTS_imp | Opcode::RTI_imp, Cycle2) => {
+            mem.read(cpu.state.pc);
+            cpu.next();
+        }
+        (Opcode::RTS_imp | Opcode::RTI_imp, Cycle3) => {
+            mem.read(cpu.stack_addr());
+            cpu.next();
+        }
+        (Opcode::RTI_imp, Cycle4) => {
+            cpu.state.flags = cpu.pull(mem) & !FLAGS_STACK_ONLY;
+            cpu.next();
+        }
+        (Opcode::RTI_imp, Cycle5) | (Opcode::RTS_imp, Cycle4) => {
+            cpu.data = cpu.pull(mem);
+            cpu.next();
+        }
+        (Opcode::RTI_imp, _) => {
+            let hi = cpu.pull(mem);
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            cpu.done();
+        }
+        (Opcode::RTS_imp, Cycle5) => {
+            let hi = cpu.pull(mem);
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            cpu.next();
+        }
+        (Opcode::RTS_imp, _) => {
+            cpu.read_pc(mem);
+            cpu.done();
+        }
 
         // Push register instructions.
-        (Opcode::PHA_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::PHP_imp, _) => todo!("Not implemented OP!"),
+        (Opcode::PHA_imp | Opcode::PHP_imp, Cycle2) => {
+            mem.read(cpu.state.pc);
+            cpu.next();
+        }
+        (Opcode::PHA_imp, _) => {
+            cpu.push(mem, cpu.state.a);
+            cpu.done();
+        }
+        (Opcode::PHP_imp, _) => {
+            cpu.push(mem, cpu.state.flags | FLAGS_STACK_ONLY);
+            cpu.done();
+        }
 
         // Pull register instructions.
-        (Opcode::PLA_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::PLP_imp, _) => todo!("Not implemented OP!"),
-
-        // Rotate left instructions.
-        (Opcode::ROL_acc, _) => todo!("Not implemented OP!"),
-        (Opcode::ROL_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::ROL_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::ROL_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::ROL_abX, _) => todo!("Not implemented OP!"),
-
-        // Rotate right instructions.
-        (Opcode::ROR_acc, _) => todo!("Not implemented OP!"),
-        (Opcode::ROR_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::ROR_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::ROR_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::ROR_abX, _) => todo!("Not implemented OP!"),
+        (Opcode::PLA_imp | Opcode::PLP_imp, Cycle2) => {
+            mem.read(cpu.state.pc);
+            cpu.next();
+        }
+        (Opcode::PLA_imp | Opcode::PLP_imp, Cycle3) => {
+            mem.read(cpu.stack_addr());
+            cpu.next();
+        }
+        (Opcode::PLA_imp, _) => {
+            cpu.state.a = cpu.pull(mem);
+            cpu.set_nz(cpu.state.a);
+            cpu.done();
+        }
+        (Opcode::PLP_imp, _) => {
+            cpu.state.flags = cpu.pull(mem) & !FLAGS_STACK_ONLY;
+            cpu.done();
+        }
 
-        // Return instructions.
-        (Opcode::RTI_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::RTS_imp, _) => todo!("Not implemented OP!"),
-
-        // Subtract with carry instructions.
-        (Opcode::SBC_imm, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::SBC_inY, _) => todo!("Not implemented OP!"),
-
-        // Set status flag instructions.
-        (Opcode::SEC_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::SED_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::SEI_imp, _) => todo!("Not implemented OP!"),
-
-        // Store register A instructions.
-        (Opcode::STA_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_abs, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_abX, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_abY, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_inX, _) => todo!("Not implemented OP!"),
-        (Opcode::STA_inY, _) => todo!("Not implemented OP!"),
-
-        // Store register X instructions.
-        (Opcode::STX_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::STX_zpY, _) => todo!("Not implemented OP!"),
-        (Opcode::STX_abs, _) => todo!("Not implemented OP!"),
-
-        // Store register Y instructions.
-        (Opcode::STY_zpg, _) => todo!("Not implemented OP!"),
-        (Opcode::STY_zpX, _) => todo!("Not implemented OP!"),
-        (Opcode::STY_abs, _) => todo!("Not implemented OP!"),
-
-        // Transfer instructions.
-        (Opcode::TAX_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::TAY_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::TSX_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::TXA_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::TXS_imp, _) => todo!("Not implemented OP!"),
-        (Opcode::TYA_imp, _) => todo!("Not implemented OP!"),
-
-        (_, _) => panic!("Unknown instruction!"),
+        // Jump instructions.
+        (Opcode::JMP_abs | Opcode::JMP_ind, Cycle2) => {
+            cpu.data = cpu.read_pc(mem);
+            cpu.next();
+        }
+        (Opcode::JMP_abs, _) => {
+            let hi = mem.read(cpu.state.pc);
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            cpu.done();
+        }
+        (Opcode::JMP_ind, Cycle3) => {
+            let hi = cpu.read_pc(mem);
+            cpu.addr = u16::from_le_bytes([cpu.data, hi]);
+            cpu.next();
+        }
+        (Opcode::JMP_ind, Cycle4) => {
+            cpu.data = mem.read(cpu.addr);
+            cpu.next();
+        }
+        (Opcode::JMP_ind, _) => {
+            // The high byte is read without carrying into the high byte of
+            // the pointer, so `JMP ($10FF)` reads from $10FF and $1000.
+            let addr = (cpu.addr & 0xff00) | (cpu.addr.wrapping_add(1) & 0x00ff);
+            let hi = mem.read(addr);
+            cpu.state.pc = u16::from_le_bytes([cpu.data, hi]);
+            cpu.done();
+        }
+
+        // Every other instruction follows the pattern of its addressing mode.
+        (op, cycle) => tick_addressing(cpu, mem, op, cycle),
+    }
+}
+
+/// Runs a single cycle of an instruction following the common pattern for
+/// its addressing mode and kind of memory access.
+fn tick_addressing<M: Memory>(cpu: &mut CycleTicker, mem: &mut M, op: Opcode, cycle: ClockCycle) {
+    use AddressingMode::*;
+    use ClockCycle::*;
+    use MemoryAccess::*;
+
+    let info = op.info();
+    match (info.mode, info.access, cycle) {
+        // Illegal opcodes halting the processor.
+        (Implied, _, _) if info.mnemonic == Mnemonic::JAM => {
+            mem.read(cpu.state.pc);
+            cpu.jammed = true;
+            cpu.done();
+        }
+
+        // Single byte instructions.
+        (Implied | Accumulator, _, _) => {
+            mem.read(cpu.state.pc);
+            cpu.execute_implied();
+            cpu.done();
+        }
+
+        (Immediate, _, _) => {
+            let value = cpu.read_pc(mem);
+            cpu.execute_read(value);
+            cpu.done();
+        }
+
+        // Branch instructions.
+        (Relative, _, Cycle2) => {
+            cpu.data = cpu.read_pc(mem);
+            if cpu.branch_taken() {
+                cpu.next();
+            } else {
+                cpu.done();
+            }
+        }
+        (Relative, _, Cycle3) => {
+            mem.read(cpu.state.pc);
+            cpu.base = cpu.state.pc;
+            cpu.addr = cpu.state.pc.wrapping_add(cpu.data as i8 as u16);
+            cpu.state.pc = cpu.unfixed_addr();
+            if cpu.state.pc == cpu.addr {
+                cpu.done();
+            } else {
+                cpu.next();
+            }
+        }
+        (Relative, _, _) => {
+            mem.read(cpu.state.pc);
+            cpu.state.pc = cpu.addr;
+            cpu.done();
+        }
+
+        // Fetching the address.
+        (ZeroPage, _, Cycle2) => {
+            cpu.addr = u16::from(cpu.read_pc(mem));
+            cpu.next();
+        }
+        (ZeroPageX | ZeroPageY, _, Cycle2) => {
+            cpu.base = u16::from(cpu.read_pc(mem));
+            cpu.next();
+        }
+        (ZeroPageX | ZeroPageY, _, Cycle3) => {
+            mem.read(cpu.base);
+            cpu.addr = u16::from((cpu.base as u8).wrapping_add(cpu.index(info.mode)));
+            cpu.next();
+        }
+        (Absolute | AbsoluteX | AbsoluteY, _, Cycle2) => {
+            cpu.data = cpu.read_pc(mem);
+            cpu.next();
+        }
+        (Absolute, _, Cycle3) => {
+            let hi = cpu.read_pc(mem);
+            cpu.addr = u16::from_le_bytes([cpu.data, hi]);
+            cpu.next();
+        }
+        (AbsoluteX | AbsoluteY, _, Cycle3) => {
+            let hi = cpu.read_pc(mem);
+            cpu.base = u16::from_le_bytes([cpu.data, hi]);
+            cpu.addr = cpu.base.wrapping_add(u16::from(cpu.index(info.mode)));
+            cpu.next();
+        }
+        (IndirectX | IndirectY, _, Cycle2) => {
+            cpu.base = u16::from(cpu.read_pc(mem));
+            cpu.next();
+        }
+        (IndirectX, _, Cycle3) => {
+            mem.read(cpu.base);
+            cpu.base = u16::from((cpu.base as u8).wrapping_add(cpu.state.x));
+            cpu.next();
+        }
+        (IndirectX, _, Cycle4) => {
+            cpu.data = mem.read(cpu.base);
+            cpu.next();
+        }
+        (IndirectX, _, Cycle5) => {
+            let hi = mem.read(u16::from((cpu.base as u8).wrapping_add(1)));
+            cpu.addr = u16::from_le_bytes([cpu.data, hi]);
+            cpu.next();
+        }
+        (IndirectY, _, Cycle3) => {
+            cpu.data = mem.read(cpu.base);
+            cpu.next();
+        }
+        (IndirectY, _, Cycle4) => {
+            let hi = mem.read(u16::from((cpu.base as u8).wrapping_add(1)));
+            cpu.base = u16::from_le_bytes([cpu.data, hi]);
+            cpu.addr = cpu.base.wrapping_add(u16::from(cpu.state.y));
+            cpu.next();
+        }
+
+        // Reading from an indexed address. The first read is made before the
+        // carry has been added to the high byte and only repeated if needed.
+        (AbsoluteX | AbsoluteY, Read, Cycle4) | (IndirectY, Read, Cycle5) => {
+            let value = mem.read(cpu.unfixed_addr());
+            if cpu.unfixed_addr() == cpu.addr {
+                cpu.execute_read(value);
+                cpu.done();
+            } else {
+                cpu.next();
+            }
+        }
+        // Writing to or modifying an indexed address always takes the extra
+        // cycle.
+        (AbsoluteX | AbsoluteY, _, Cycle4) | (IndirectY, _, Cycle5) => {
+            mem.read(cpu.unfixed_addr());
+            cpu.next();
+        }
+
+        // Reading the value.
+        (_, Read | None, _) => {
+            let value = mem.read(cpu.addr);
+            cpu.execute_read(value);
+            cpu.done();
+        }
+
+        // Writing the value.
+        (_, Write, _) => {
+            cpu.write_store(mem);
+            cpu.done();
+        }
+
+        // Read, modify and write back the value, where the unmodified value
+        // is written back first.
+        (ZeroPage, ReadModifyWrite, Cycle3)
+        | (ZeroPageX | Absolute, ReadModifyWrite, Cycle4)
+        | (AbsoluteX | AbsoluteY, ReadModifyWrite, Cycle5)
+        | (IndirectX | IndirectY, ReadModifyWrite, Cycle6) => {
+            cpu.data = mem.read(cpu.addr);
+            cpu.next();
+        }
+        (ZeroPage, ReadModifyWrite, Cycle4)
+        | (ZeroPageX | Absolute, ReadModifyWrite, Cycle5)
+        | (AbsoluteX | AbsoluteY, ReadModifyWrite, Cycle6)
+        | (IndirectX | IndirectY, ReadModifyWrite, Cycle7) => {
+            mem.write(cpu.addr, cpu.data);
+            cpu.data = cpu.modify(cpu.data);
+            cpu.next();
+        }
+        (_, ReadModifyWrite, _) => {
+            mem.write(cpu.addr, cpu.data);
+            cpu.done();
+        }
     }
 }
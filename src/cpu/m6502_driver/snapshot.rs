@@ -0,0 +1,288 @@
+//! Binary snapshots of the complete processor state.
+
+use core::fmt;
+
+use super::{ClockCycle, CycleTicker, Interrupt};
+use crate::{Opcode, M6502};
+
+/// Bytes identifying a snapshot.
+const MAGIC: [u8; 3] = *b"F65";
+
+/// Version of the snapshot format, increased whenever the layout changes.
+const VERSION: u8 = 1;
+
+/// An error restoring a snapshot, see [`CycleTicker::from_snapshot`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SnapshotError {
+    /// The data is not a snapshot.
+    BadMagic,
+    /// The snapshot was made by an unsupported version of the format.
+    UnsupportedVersion(u8),
+    /// The data is shorter than a snapshot.
+    Truncated,
+    /// The snapshot holds a value that is out of range.
+    Invalid,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a processor snapshot"),
+            SnapshotError::UnsupportedVersion(v) => {
+                write!(f, "unsupported snapshot version {v}")
+            }
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid => write!(f, "snapshot holds an invalid value"),
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+const IRQ: u8 = 0x01;
+const NMI: u8 = 0x02;
+const NMI_PENDING: u8 = 0x04;
+const RESET_PENDING: u8 = 0x08;
+const POLL_I: u8 = 0x10;
+const JAMMED: u8 = 0x20;
+
+impl CycleTicker {
+    /// Length of a snapshot in bytes.
+    pub const SNAPSHOT_LEN: usize = 28;
+
+    /// Returns a snapshot of the complete processor state, including the
+    /// position within the current instruction, pending interrupts and the
+    /// cycle count. Memory is not included.
+    ///
+    /// The snapshot is a versioned little endian binary format, which can be
+    /// restored at any later time with [`from_snapshot`].
+    ///
+    /// [`from_snapshot`]: CycleTicker::from_snapshot
+    pub fn snapshot(&self) -> [u8; CycleTicker::SNAPSHOT_LEN] {
+        let s = &self.state;
+        let [pc_lo, pc_hi] = s.pc.to_le_bytes();
+        let [addr_lo, addr_hi] = self.addr.to_le_bytes();
+        let [base_lo, base_hi] = self.base.to_le_bytes();
+        let interrupt = match self.interrupt {
+            None => 0,
+            Some(Interrupt::Reset) => 1,
+            Some(Interrupt::Nmi) => 2,
+            Some(Interrupt::Irq) => 3,
+        };
+        let lines = [
+            (self.irq, IRQ),
+            (self.nmi, NMI),
+            (self.nmi_pending, NMI_PENDING),
+            (self.reset_pending, RESET_PENDING),
+            (self.poll_i, POLL_I),
+            (self.jammed, JAMMED),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |bits, (_, bit)| bits | bit);
+
+        let mut out = [0; CycleTicker::SNAPSHOT_LEN];
+        out[..3].copy_from_slice(&MAGIC);
+        out[3..20].copy_from_slice(&[
+            VERSION,
+            s.a,
+            s.x,
+            s.y,
+            s.sp,
+            s.flags,
+            pc_lo,
+            pc_hi,
+            self.cycle as u8,
+            self.op.to_byte(),
+            addr_lo,
+            addr_hi,
+            base_lo,
+            base_hi,
+            self.data,
+            interrupt,
+            lines,
+        ]);
+        out[20..].copy_from_slice(&self.cycles.to_le_bytes());
+        out
+    }
+
+    /// Restores a processor from a snapshot made by [`snapshot`]. Any bytes
+    /// after the snapshot are ignored.
+    ///
+    /// [`snapshot`]: CycleTicker::snapshot
+    pub fn from_snapshot(data: &[u8]) -> Result<CycleTicker, SnapshotError> {
+        if data.len() < 4 {
+            return Err(SnapshotError::Truncated);
+        }
+        if data[..3] != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        if data[3] != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(data[3]));
+        }
+        let Some(data) = data.get(..CycleTicker::SNAPSHOT_LEN) else {
+            return Err(SnapshotError::Truncated);
+        };
+
+        let cycle = match data[11] {
+            0 => ClockCycle::Cycle1,
+            1 => ClockCycle::Cycle2,
+            2 => ClockCycle::Cycle3,
+            3 => ClockCycle::Cycle4,
+            4 => ClockCycle::Cycle5,
+            5 => ClockCycle::Cycle6,
+            6 => ClockCycle::Cycle7,
+            7 => ClockCycle::Cycle8,
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let interrupt = match data[18] {
+            0 => None,
+            1 => Some(Interrupt::Reset),
+            2 => Some(Interrupt::Nmi),
+            3 => Some(Interrupt::Irq),
+            _ => return Err(SnapshotError::Invalid),
+        };
+        let lines = data[19];
+        if lines & !(IRQ | NMI | NMI_PENDING | RESET_PENDING | POLL_I | JAMMED) != 0 {
+            return Err(SnapshotError::Invalid);
+        }
+        let mut cycles = [0; 8];
+        cycles.copy_from_slice(&data[20..28]);
+
+        Ok(CycleTicker {
+            cycle,
+            op: Opcode::from_byte(data[12]),
+            addr: u16::from_le_bytes([data[13], data[14]]),
+            base: u16::from_le_bytes([data[15], data[16]]),
+            data: data[17],
+            interrupt,
+            irq: lines & IRQ != 0,
+            nmi: lines & NMI != 0,
+            nmi_pending: lines & NMI_PENDING != 0,
+            reset_pending: lines & RESET_PENDING != 0,
+            poll_i: lines & POLL_I != 0,
+            jammed: lines & JAMMED != 0,
+            cycles: u64::from_le_bytes(cycles),
+            state: M6502 {
+                a: data[4],
+                x: data[5],
+                y: data[6],
+                sp: data[7],
+                flags: data[8] & !super::FLAGS_STACK_ONLY,
+                pc: u16::from_le_bytes([data[9], data[10]]),
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cycles run by [`run`].
+    const TICKS: usize = 60;
+
+    /// Cycle at which the NMI line is asserted, in the middle of the first
+    /// instruction.
+    const NMI_AT: usize = 3;
+
+    /// Returns memory with `INC $10F8,X` and `CLI` at `$0200`, followed by
+    /// `NOP`s, an NMI handler counting in X and an IRQ handler counting in Y
+    /// and then trapping.
+    fn memory() -> [u8; 0x10000] {
+        let mut mem = [0xea; 0x10000];
+        mem[0x0200..0x0204].copy_from_slice(&[0xfe, 0xf8, 0x10, 0x58]);
+        mem[0x0300..0x0302].copy_from_slice(&[0xe8, 0x40]); // INX, RTI
+        mem[0x0310..0x0314].copy_from_slice(&[0xc8, 0x4c, 0x11, 0x03]); // INY, JMP *
+        mem[0xfffa..0xfffc].copy_from_slice(&[0x00, 0x03]);
+        mem[0xfffe..].copy_from_slice(&[0x10, 0x03]);
+        mem
+    }
+
+    /// Runs cycles `from` up to [`TICKS`] with the IRQ line asserted,
+    /// asserting NMI at cycle [`NMI_AT`].
+    fn run(cpu: &mut CycleTicker, mem: &mut [u8; 0x10000], from: usize) {
+        for tick in from..TICKS {
+            if tick == NMI_AT {
+                cpu.set_nmi(true);
+            }
+            cpu.tick(mem);
+        }
+    }
+
+    #[test]
+    fn restore_every_cycle() {
+        let start = || {
+            let mut cpu = CycleTicker::new(M6502 {
+                pc: 0x0200,
+                x: 0x10,
+                sp: 0xfd,
+                flags: 0x04,
+                ..M6502::new()
+            });
+            cpu.set_irq(true);
+            cpu
+        };
+
+        let mut cpu = start();
+        let mut mem = memory();
+        run(&mut cpu, &mut mem, 0);
+        let expected = (cpu.snapshot(), mem);
+        // Both handlers ran once and the instruction was not repeated.
+        assert_eq!((cpu.state().x, cpu.state().y, mem[0x1108]), (0x11, 1, 0xeb));
+
+        for at in 0..TICKS {
+            let mut cpu = start();
+            let mut mem = memory();
+            for tick in 0..at {
+                if tick == NMI_AT {
+                    cpu.set_nmi(true);
+                }
+                cpu.tick(&mut mem);
+            }
+            let snapshot = cpu.snapshot();
+            let mut restored = CycleTicker::from_snapshot(&snapshot).unwrap();
+            assert_eq!(restored.snapshot(), snapshot, "cycle {at}");
+
+            run(&mut restored, &mut mem, at);
+            assert!(
+                (restored.snapshot(), mem) == expected,
+                "restored at cycle {at}"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let snapshot = CycleTicker::new(M6502::new()).snapshot();
+        let restore = |f: fn(&mut [u8; CycleTicker::SNAPSHOT_LEN])| {
+            let mut data = snapshot;
+            f(&mut data);
+            CycleTicker::from_snapshot(&data).map(|cpu| cpu.snapshot())
+        };
+
+        assert_eq!(restore(|_| ()), Ok(snapshot));
+        assert_eq!(restore(|d| d[0] = b'X'), Err(SnapshotError::BadMagic));
+        assert_eq!(
+            restore(|d| d[3] = VERSION + 1),
+            Err(SnapshotError::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(restore(|d| d[11] = 8), Err(SnapshotError::Invalid));
+        assert_eq!(restore(|d| d[18] = 4), Err(SnapshotError::Invalid));
+        assert_eq!(restore(|d| d[19] = 0x40), Err(SnapshotError::Invalid));
+
+        for len in [0, 3, 4, CycleTicker::SNAPSHOT_LEN - 1] {
+            assert_eq!(
+                CycleTicker::from_snapshot(&snapshot[..len]).err(),
+                Some(SnapshotError::Truncated),
+                "{len} bytes"
+            );
+        }
+        let mut long = [0; CycleTicker::SNAPSHOT_LEN + 4];
+        long[..CycleTicker::SNAPSHOT_LEN].copy_from_slice(&snapshot);
+        assert_eq!(
+            CycleTicker::from_snapshot(&long).map(|cpu| cpu.snapshot()),
+            Ok(snapshot)
+        );
+    }
+}
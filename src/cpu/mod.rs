@@ -2,4 +2,6 @@ mod instruction;
 mod m6502;
 mod m6502_driver;
 
+pub use instruction::{AddressingMode, MemoryAccess, Mnemonic, Opcode, OpcodeInfo, OPCODE_INFO};
 pub use m6502::M6502;
+pub use m6502_driver::{CycleTicker, SnapshotError};
@@ -0,0 +1,388 @@
+//! Breakpoints and a run loop stopping at them.
+//!
+//! A [`Debugger`] runs a [`CycleTicker`] one instruction at a time, checking
+//! its [`Breakpoint`]s before each instruction. The run stops before the
+//! instruction of a matching breakpoint is executed and reports why it
+//! stopped as a [`StopReason`]. With no breakpoints set the checks are
+//! skipped entirely.
+//!
+//! Test programs often signal that they are done by jumping to themselves.
+//! Such traps can be detected as well, see [`Debugger::set_detect_traps`].
+//!
+//! Memory watchpoints are handled by [`WatchMemory`], which
+//! [`Debugger::run_watched`] checks after every instruction.
+
+use crate::{
+    memory::{
+        watch::{WatchHit, WatchMemory, Watchpoint},
+        Memory,
+    },
+    CycleTicker, Opcode,
+};
+
+/// A processor register, see [`Breakpoint::Register`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Register {
+    /// The accumulator.
+    A,
+    /// The X index register.
+    X,
+    /// The Y index register.
+    Y,
+    /// The stack pointer.
+    Sp,
+    /// The status flags, without the break and unused bits.
+    Flags,
+}
+
+impl Register {
+    /// Returns the value of the register.
+    pub fn get(self, cpu: &CycleTicker) -> u8 {
+        let state = cpu.state();
+        match self {
+            Register::A => state.a,
+            Register::X => state.x,
+            Register::Y => state.y,
+            Register::Sp => state.sp,
+            Register::Flags => state.flags,
+        }
+    }
+}
+
+/// A condition stopping a [`Debugger`] run before the next instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Breakpoint {
+    /// The program counter is at the address.
+    Pc(u16),
+    /// The next instruction has the opcode, e.g. `BRK`.
+    Opcode(Opcode),
+    /// The register holds the value.
+    Register(Register, u8),
+    /// The cycle count has reached the value since the previous instruction.
+    Cycle(u64),
+}
+
+impl Breakpoint {
+    /// Returns whether the breakpoint matches before the next instruction,
+    /// where `prev_cycles` is the cycle count before the previous one. The
+    /// opcode is looked up with [`Memory::get`] so checking never triggers
+    /// any read related side effects.
+    pub fn matches<M: Memory + ?Sized>(
+        &self,
+        cpu: &CycleTicker,
+        mem: &M,
+        prev_cycles: u64,
+    ) -> bool {
+        match *self {
+            Breakpoint::Pc(addr) => cpu.state().pc == addr,
+            Breakpoint::Opcode(op) => mem.get(cpu.state().pc) == Some(op.to_byte()),
+            Breakpoint::Register(reg, value) => reg.get(cpu) == value,
+            Breakpoint::Cycle(cycle) => prev_cycles < cycle && cycle <= cpu.cycles(),
+        }
+    }
+}
+
+/// Why a [`Debugger`] run stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum StopReason {
+    /// The breakpoint with the index matched. The instruction it matched
+    /// before has not been executed.
+    Breakpoint(usize),
+    /// A watchpoint matched an access made by the instruction just executed.
+    Watchpoint(WatchHit),
+    /// The instruction at the address did not change the program counter,
+    /// e.g. `JMP *` or a taken `BNE *`. The instruction has been executed.
+    Trap(u16),
+    /// The processor was halted by a `JAM` opcode.
+    Jammed,
+    /// The cycle limit was reached.
+    Limit,
+    /// Running backwards reached the oldest recorded instruction, see
+    /// [`History`](crate::reverse::History).
+    HistoryExhausted,
+}
+
+/// Runs a processor until a breakpoint matches.
+///
+/// The breakpoints can be stored in anything that can be viewed as a slice of
+/// [`Breakpoint`]s, e.g. an array, a slice or a `Vec`.
+///
+/// Breakpoints are not checked before the first instruction of a run, so
+/// running again after stopping at a breakpoint continues past it.
+#[derive(Debug, Clone)]
+pub struct Debugger<B> {
+    breakpoints: B,
+    detect_traps: bool,
+}
+
+impl<B: AsRef<[Breakpoint]>> Debugger<B> {
+    /// Returns a new debugger stopping at `breakpoints`.
+    pub fn new(breakpoints: B) -> Debugger<B> {
+        Debugger {
+            breakpoints,
+            detect_traps: false,
+        }
+    }
+
+    /// Returns whether runs stop at traps.
+    #[inline]
+    pub fn detect_traps(&self) -> bool {
+        self.detect_traps
+    }
+
+    /// Sets whether runs stop with [`StopReason::Trap`] after an instruction
+    /// leaving the program counter unchanged. Off by default.
+    #[inline]
+    pub fn set_detect_traps(&mut self, detect: bool) {
+        self.detect_traps = detect;
+    }
+
+    /// Returns the breakpoints.
+    #[inline]
+    pub fn breakpoints(&self) -> &B {
+        &self.breakpoints
+    }
+
+    /// Returns the breakpoints, allowing them to be changed.
+    #[inline]
+    pub fn breakpoints_mut(&mut self) -> &mut B {
+        &mut self.breakpoints
+    }
+
+    /// Consumes the debugger returning the breakpoints.
+    #[inline]
+    pub fn into_inner(self) -> B {
+        self.breakpoints
+    }
+
+    /// Returns the index of the first breakpoint matching before the next
+    /// instruction, see [`Breakpoint::matches`].
+    pub fn check<M: Memory + ?Sized>(
+        &self,
+        cpu: &CycleTicker,
+        mem: &M,
+        prev_cycles: u64,
+    ) -> Option<usize> {
+        self.breakpoints
+            .as_ref()
+            .iter()
+            .position(|b| b.matches(cpu, mem, prev_cycles))
+    }
+
+    /// Runs instructions until a breakpoint matches, a trap is detected if
+    /// enabled, the processor jams or at least `max_cycles` cycles have been
+    /// run. If called in the middle of an instruction that instruction is
+    /// finished first.
+    ///
+    /// Breakpoints are checked before the cycle limit, so a run stopping with
+    /// [`StopReason::Limit`] can be resumed without skipping one.
+    pub fn run<M: Memory>(
+        &self,
+        cpu: &mut CycleTicker,
+        mem: &mut M,
+        max_cycles: u64,
+    ) -> StopReason {
+        self.run_until(cpu, mem, max_cycles, |_| None)
+    }
+
+    /// Like [`run`], but also stops after any instruction making an access
+    /// matching one of the watchpoints of `mem`. Any hit recorded before the
+    /// run is thrown away.
+    ///
+    /// [`run`]: Debugger::run
+    pub fn run_watched<M: Memory, W: AsRef<[Watchpoint]>>(
+        &self,
+        cpu: &mut CycleTicker,
+        mem: &mut WatchMemory<M, W>,
+        max_cycles: u64,
+    ) -> StopReason {
+        mem.take_hit();
+        self.run_until(cpu, mem, max_cycles, |mem| {
+            mem.take_hit().map(StopReason::Watchpoint)
+        })
+    }
+
+    fn run_until<M: Memory>(
+        &self,
+        cpu: &mut CycleTicker,
+        mem: &mut M,
+        max_cycles: u64,
+        mut after: impl FnMut(&mut M) -> Option<StopReason>,
+    ) -> StopReason {
+        let start = cpu.cycles();
+        let check = !self.breakpoints.as_ref().is_empty();
+        let mut prev_cycles = start;
+        let mut first = true;
+
+        loop {
+            if cpu.is_jammed() {
+                return StopReason::Jammed;
+            }
+            if check && !first {
+                if let Some(index) = self.check(cpu, mem, prev_cycles) {
+                    return StopReason::Breakpoint(index);
+                }
+            }
+            if cpu.cycles() - start >= max_cycles {
+                return StopReason::Limit;
+            }
+
+            first = false;
+            prev_cycles = cpu.cycles();
+            let pc = self.trap_pc(cpu);
+            cpu.step(mem);
+            if let Some(reason) = after(mem) {
+                return reason;
+            }
+            if let Some(reason) = self.check_trap(cpu, pc) {
+                return reason;
+            }
+        }
+    }
+
+    /// Returns the address of the next instruction if traps are detected,
+    /// to be passed to [`check_trap`](Debugger::check_trap) after it ran.
+    pub(crate) fn trap_pc(&self, cpu: &CycleTicker) -> Option<u16> {
+        (self.detect_traps && cpu.at_instruction_boundary()).then(|| cpu.state().pc)
+    }
+
+    /// Returns [`StopReason::Trap`] if the instruction run from `pc` left
+    /// the program counter unchanged.
+    pub(crate) fn check_trap(&self, cpu: &CycleTicker, pc: Option<u16>) -> Option<StopReason> {
+        pc.filter(|&pc| cpu.state().pc == pc).map(StopReason::Trap)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::Access, M6502};
+
+    /// Counts up in X forever, storing it at `$0300`. Each loop takes nine
+    /// cycles.
+    const COUNT: &[u8] = &[
+        0xa2, 0x00, // $0200 LDX #0
+        0xe8, // $0202 INX
+        0x8e, 0x00, 0x03, // $0203 STX $0300
+        0x4c, 0x02, 0x02, // $0206 JMP $0202
+    ];
+
+    fn setup(program: &[u8]) -> (CycleTicker, [u8; 0x400]) {
+        let mut mem = [0; 0x400];
+        mem[0x200..0x200 + program.len()].copy_from_slice(program);
+        let cpu = CycleTicker::new(M6502 {
+            pc: 0x0200,
+            ..M6502::new()
+        });
+        (cpu, mem)
+    }
+
+    /// Runs [`COUNT`] until `breakpoint` matches, returning the program
+    /// counter and cycle count.
+    fn stop_at(breakpoint: Breakpoint) -> (u16, u64) {
+        let (mut cpu, mut mem) = setup(COUNT);
+        let debugger = Debugger::new([breakpoint]);
+        assert_eq!(
+            debugger.run(&mut cpu, &mut mem, 1000),
+            StopReason::Breakpoint(0)
+        );
+        (cpu.state().pc, cpu.cycles())
+    }
+
+    #[test]
+    fn breakpoints() {
+        assert_eq!(stop_at(Breakpoint::Pc(0x0206)), (0x0206, 8));
+        assert_eq!(stop_at(Breakpoint::Opcode(Opcode::from(0x4c))), (0x0206, 8));
+        assert_eq!(stop_at(Breakpoint::Register(Register::X, 3)), (0x0203, 22));
+        assert_eq!(stop_at(Breakpoint::Cycle(19)), (0x0202, 20));
+        assert_eq!(stop_at(Breakpoint::Cycle(20)), (0x0202, 20));
+    }
+
+    #[test]
+    fn resume() {
+        let (mut cpu, mut mem) = setup(COUNT);
+        let debugger = Debugger::new([Breakpoint::Pc(0x0206), Breakpoint::Pc(0x0202)]);
+        let mut stops = [(StopReason::Limit, 0, 0); 4];
+        for stop in &mut stops {
+            let reason = debugger.run(&mut cpu, &mut mem, 1000);
+            *stop = (reason, cpu.state().pc, cpu.state().x);
+        }
+        assert_eq!(
+            stops,
+            [
+                (StopReason::Breakpoint(1), 0x0202, 0),
+                (StopReason::Breakpoint(0), 0x0206, 1),
+                (StopReason::Breakpoint(1), 0x0202, 1),
+                (StopReason::Breakpoint(0), 0x0206, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn limit() {
+        let (mut cpu, mut mem) = setup(COUNT);
+        let debugger = Debugger::new([]);
+        assert_eq!(debugger.run(&mut cpu, &mut mem, 7), StopReason::Limit);
+        assert_eq!(cpu.cycles(), 8);
+        assert_eq!(debugger.run(&mut cpu, &mut mem, 0), StopReason::Limit);
+        assert_eq!(cpu.cycles(), 8);
+
+        // A breakpoint reached exactly at the limit is reported.
+        let (mut cpu, mut mem) = setup(COUNT);
+        let debugger = Debugger::new([Breakpoint::Pc(0x0206)]);
+        assert_eq!(
+            debugger.run(&mut cpu, &mut mem, 8),
+            StopReason::Breakpoint(0)
+        );
+        assert_eq!(cpu.cycles(), 8);
+    }
+
+    #[test]
+    fn traps_and_jams() {
+        // JMP *
+        let (mut cpu, mut mem) = setup(&[0x4c, 0x00, 0x02]);
+        let mut debugger = Debugger::new([]);
+        assert_eq!(debugger.run(&mut cpu, &mut mem, 30), StopReason::Limit);
+        debugger.set_detect_traps(true);
+        assert!(debugger.detect_traps());
+        assert_eq!(
+            debugger.run(&mut cpu, &mut mem, 30),
+            StopReason::Trap(0x0200)
+        );
+
+        // INX, JAM
+        let (mut cpu, mut mem) = setup(&[0xe8, 0x02]);
+        assert_eq!(debugger.run(&mut cpu, &mut mem, 30), StopReason::Jammed);
+        assert!(cpu.is_jammed());
+        assert_eq!(cpu.state().x, 1);
+    }
+
+    #[test]
+    fn watchpoints() {
+        let (mut cpu, mem) = setup(COUNT);
+        let watch = Watchpoint {
+            start: 0x0300,
+            end: 0x0300,
+            read: false,
+            write: true,
+            execute: false,
+        };
+        let mut mem = WatchMemory::new(mem, [watch]);
+        let debugger = Debugger::new([]);
+        for x in 1..=2 {
+            assert_eq!(
+                debugger.run_watched(&mut cpu, &mut mem, 1000),
+                StopReason::Watchpoint(WatchHit {
+                    index: 0,
+                    addr: 0x0300,
+                    value: x,
+                    access: Access::Write,
+                })
+            );
+            assert_eq!(cpu.state().pc, 0x0206);
+        }
+    }
+}
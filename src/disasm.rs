@@ -0,0 +1,434 @@
+//! Disassembler for 6502 machine code.
+//!
+//! Instructions are decoded from any [`Memory`] using [`Memory::get`], so
+//! disassembling never triggers any read related side effects. Decoded
+//! instructions format in the standard 6502 assembly syntax, e.g.
+//! `LDA ($10),Y` or `BNE $C010`, or with labels in place of addresses
+//! using [`Instruction::with_symbols`], e.g. `BNE loop`.
+
+use core::fmt;
+
+use crate::{memory::Memory, AddressingMode, Mnemonic, Opcode};
+
+/// A decoded instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Instruction {
+    /// Address of the opcode.
+    pub addr: u16,
+    /// The opcode.
+    pub opcode: Opcode,
+    /// The instruction mnemonic.
+    pub mnemonic: Mnemonic,
+    /// The addressing mode.
+    pub mode: AddressingMode,
+    /// The operand as stored after the opcode, zero if there is none. For
+    /// relative branches this is the sign extended offset, see
+    /// [`target`](Instruction::target) for the resulting address.
+    pub operand: u16,
+    /// Length of the instruction in bytes, including the opcode.
+    pub len: u8,
+    /// Base number of cycles the instruction takes to execute, not including
+    /// any extra cycles for crossing page boundaries or taking branches.
+    pub cycles: u8,
+    /// Whether the opcode is an illegal opcode.
+    pub illegal: bool,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`. Any byte not available in memory
+    /// is treated as zero.
+    pub fn decode<M: Memory + ?Sized>(mem: &M, addr: u16) -> Instruction {
+        let byte = |offset: u16| mem.get(addr.wrapping_add(offset)).unwrap_or(0);
+
+        let opcode = Opcode::from(byte(0));
+        let info = opcode.info();
+        let operand = match info.mode {
+            AddressingMode::Relative => byte(1) as i8 as u16,
+            mode => match mode.operand_len() {
+                0 => 0,
+                1 => u16::from(byte(1)),
+                _ => u16::from_le_bytes([byte(1), byte(2)]),
+            },
+        };
+
+        Instruction {
+            addr,
+            opcode,
+            mnemonic: info.mnemonic,
+            mode: info.mode,
+            operand,
+            len: info.len,
+            cycles: info.cycles,
+            illegal: info.illegal,
+        }
+    }
+
+    /// Returns the address of the instruction following this one.
+    #[inline]
+    pub const fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.len as u16)
+    }
+
+    /// Returns the address the instruction may jump or branch to, if it is
+    /// known without looking at memory or registers.
+    pub const fn target(&self) -> Option<u16> {
+        match (self.mnemonic, self.mode) {
+            (_, AddressingMode::Relative) => Some(self.next_addr().wrapping_add(self.operand)),
+            (Mnemonic::JMP | Mnemonic::JSR, AddressingMode::Absolute) => Some(self.operand),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw bytes of the instruction. Only the first [`len`] bytes
+    /// are used.
+    ///
+    /// [`len`]: Instruction::len
+    pub const fn bytes(&self) -> [u8; 3] {
+        let [lo, hi] = self.operand.to_le_bytes();
+        [self.opcode.to_byte(), lo, hi]
+    }
+}
+
+impl Instruction {
+    /// Returns a value formatting the instruction with addresses replaced by
+    /// labels from `symbols` where there is one, e.g. `BNE loop`.
+    #[inline]
+    pub fn with_symbols<'a, S: Symbols + ?Sized>(&'a self, symbols: &'a S) -> WithSymbols<'a, S> {
+        WithSymbols {
+            instr: self,
+            symbols,
+        }
+    }
+
+    fn fmt_with<S: Symbols + ?Sized>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        symbols: &S,
+    ) -> fmt::Result {
+        let m = self.mnemonic;
+        let op = self.operand;
+        let zp = Operand::new(op, 2, symbols);
+        let abs = Operand::new(op, 4, symbols);
+        match self.mode {
+            AddressingMode::Implied => write!(f, "{m}"),
+            AddressingMode::Accumulator => write!(f, "{m} A"),
+            AddressingMode::Immediate => write!(f, "{m} #${op:02X}"),
+            AddressingMode::ZeroPage => write!(f, "{m} {zp}"),
+            AddressingMode::ZeroPageX => write!(f, "{m} {zp},X"),
+            AddressingMode::ZeroPageY => write!(f, "{m} {zp},Y"),
+            AddressingMode::Absolute => write!(f, "{m} {abs}"),
+            AddressingMode::AbsoluteX => write!(f, "{m} {abs},X"),
+            AddressingMode::AbsoluteY => write!(f, "{m} {abs},Y"),
+            AddressingMode::Indirect => write!(f, "{m} ({abs})"),
+            AddressingMode::IndirectX => write!(f, "{m} ({zp},X)"),
+            AddressingMode::IndirectY => write!(f, "{m} ({zp}),Y"),
+            AddressingMode::Relative => {
+                let target = self.next_addr().wrapping_add(op);
+                write!(f, "{m} {}", Operand::new(target, 4, symbols))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &())
+    }
+}
+
+/// Looks up labels for addresses, used to show labels in place of addresses
+/// in disassembly and traces.
+pub trait Symbols {
+    /// Returns the label of the address, if any.
+    fn label(&self, addr: u16) -> Option<&str>;
+}
+
+/// No symbols at all.
+impl Symbols for () {
+    #[inline]
+    fn label(&self, _addr: u16) -> Option<&str> {
+        None
+    }
+}
+
+impl<S: Symbols + ?Sized> Symbols for &S {
+    #[inline]
+    fn label(&self, addr: u16) -> Option<&str> {
+        (**self).label(addr)
+    }
+}
+
+/// An instruction formatted with labels, see [`Instruction::with_symbols`].
+#[derive(Debug, Clone, Copy)]
+pub struct WithSymbols<'a, S: ?Sized> {
+    instr: &'a Instruction,
+    symbols: &'a S,
+}
+
+impl<S: Symbols + ?Sized> fmt::Display for WithSymbols<'_, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.instr.fmt_with(f, self.symbols)
+    }
+}
+
+/// An address operand, formatted as its label if there is one or otherwise
+/// as hex with the given number of digits.
+pub(crate) struct Operand<'a> {
+    addr: u16,
+    digits: usize,
+    label: Option<&'a str>,
+}
+
+impl<'a> Operand<'a> {
+    pub(crate) fn new<S: Symbols + ?Sized>(
+        addr: u16,
+        digits: usize,
+        symbols: &'a S,
+    ) -> Operand<'a> {
+        Operand {
+            addr,
+            digits,
+            label: symbols.label(addr),
+        }
+    }
+}
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label {
+            Some(label) => f.write_str(label),
+            None => write!(f, "${:0width$X}", self.addr, width = self.digits),
+        }
+    }
+}
+
+/// A single line of disassembly, see [`Disassembler`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Line {
+    /// A decoded instruction.
+    Instruction(Instruction),
+    /// A byte not decoded as an instruction, formatted as a `.byte`
+    /// directive.
+    Byte {
+        /// Address of the byte.
+        addr: u16,
+        /// The byte value.
+        value: u8,
+    },
+}
+
+impl Line {
+    /// Returns the address of the line.
+    #[inline]
+    pub const fn addr(&self) -> u16 {
+        match self {
+            Line::Instruction(instr) => instr.addr,
+            Line::Byte { addr, .. } => *addr,
+        }
+    }
+
+    /// Returns the number of bytes covered by the line.
+    #[inline]
+    pub const fn size(&self) -> u8 {
+        match self {
+            Line::Instruction(instr) => instr.len,
+            Line::Byte { .. } => 1,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Line::Instruction(instr) => fmt::Display::fmt(instr, f),
+            Line::Byte { value, .. } => write!(f, ".byte ${value:02X}"),
+        }
+    }
+}
+
+/// An iterator disassembling consecutive instructions from memory.
+///
+/// By default illegal opcodes are not decoded as instructions but shown as
+/// single `.byte` lines, since they more often than not mean that the bytes
+/// are data rather than code. Use [`set_illegal`] to decode them as well.
+///
+/// The iterator stops at the first address not available in memory, or after
+/// the instruction at the end of the address space.
+///
+/// [`set_illegal`]: Disassembler::set_illegal
+#[derive(Debug, Clone)]
+pub struct Disassembler<'a, M: ?Sized> {
+    mem: &'a M,
+    addr: Option<u16>,
+    illegal: bool,
+}
+
+impl<'a, M: Memory + ?Sized> Disassembler<'a, M> {
+    /// Returns a new disassembler starting at `addr`.
+    pub fn new(mem: &'a M, addr: u16) -> Disassembler<'a, M> {
+        Disassembler {
+            mem,
+            addr: Some(addr),
+            illegal: false,
+        }
+    }
+
+    /// Returns whether illegal opcodes are decoded as instructions.
+    #[inline]
+    pub fn illegal(&self) -> bool {
+        self.illegal
+    }
+
+    /// Sets whether illegal opcodes are decoded as instructions.
+    #[inline]
+    pub fn set_illegal(&mut self, illegal: bool) {
+        self.illegal = illegal;
+    }
+
+    /// Returns the address of the next line.
+    #[inline]
+    pub fn addr(&self) -> Option<u16> {
+        self.addr
+    }
+}
+
+impl<M: Memory + ?Sized> Iterator for Disassembler<'_, M> {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        let addr = self.addr?;
+        self.mem.get(addr)?;
+
+        let instr = Instruction::decode(self.mem, addr);
+        let line = if instr.illegal && !self.illegal {
+            Line::Byte {
+                addr,
+                value: instr.opcode.to_byte(),
+            }
+        } else {
+            Line::Instruction(instr)
+        };
+
+        self.addr = addr.checked_add(u16::from(line.size()));
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec::Vec};
+
+    use super::*;
+
+    extern crate alloc;
+
+    /// A single label.
+    struct Label(u16, &'static str);
+
+    impl Symbols for Label {
+        fn label(&self, addr: u16) -> Option<&str> {
+            (addr == self.0).then_some(self.1)
+        }
+    }
+
+    /// Decodes `bytes` placed at `$C000`.
+    fn decode(bytes: &[u8]) -> Instruction {
+        let mut mem = [0; 0x10000];
+        mem[0xc000..0xc000 + bytes.len()].copy_from_slice(bytes);
+        Instruction::decode(&mem, 0xc000)
+    }
+
+    #[test]
+    fn format() {
+        let cases: &[(&[u8], &str)] = &[
+            (&[0xea], "NOP"),
+            (&[0x0a], "ASL A"),
+            (&[0xa9, 0x12], "LDA #$12"),
+            (&[0xa5, 0x12], "LDA $12"),
+            (&[0xb5, 0x12], "LDA $12,X"),
+            (&[0xb6, 0x12], "LDX $12,Y"),
+            (&[0xad, 0x34, 0x12], "LDA $1234"),
+            (&[0xbd, 0x34, 0x12], "LDA $1234,X"),
+            (&[0xb9, 0x34, 0x12], "LDA $1234,Y"),
+            (&[0x6c, 0x34, 0x12], "JMP ($1234)"),
+            (&[0xa1, 0x12], "LDA ($12,X)"),
+            (&[0xb1, 0x12], "LDA ($12),Y"),
+            (&[0xd0, 0x10], "BNE $C012"),
+            (&[0xd0, 0xfe], "BNE $C000"),
+            (&[0xa7, 0x12], "LAX $12"),
+        ];
+        for &(bytes, text) in cases {
+            let instr = decode(bytes);
+            assert_eq!(instr.to_string(), text);
+            assert_eq!(usize::from(instr.len), bytes.len(), "{text}");
+            assert_eq!(&instr.bytes()[..bytes.len()], bytes, "{text}");
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let branch = decode(&[0x10, 0x80]);
+        assert_eq!(branch.operand, 0xff80);
+        assert_eq!(
+            (branch.next_addr(), branch.target()),
+            (0xc002, Some(0xbf82))
+        );
+        assert_eq!(decode(&[0x20, 0x00, 0x80]).target(), Some(0x8000));
+        assert_eq!(decode(&[0x4c, 0x00, 0x80]).target(), Some(0x8000));
+        assert_eq!(decode(&[0x6c, 0x00, 0x80]).target(), None);
+        assert_eq!(decode(&[0xad, 0x00, 0x80]).target(), None);
+    }
+
+    #[test]
+    fn symbols() {
+        let labels = Label(0x0010, "ptr");
+        assert_eq!(
+            decode(&[0xb1, 0x10]).with_symbols(&labels).to_string(),
+            "LDA (ptr),Y"
+        );
+        assert_eq!(
+            decode(&[0xad, 0x10, 0x00])
+                .with_symbols(&labels)
+                .to_string(),
+            "LDA ptr"
+        );
+        assert_eq!(
+            decode(&[0xa9, 0x10]).with_symbols(&labels).to_string(),
+            "LDA #$10"
+        );
+
+        let labels = Label(0xc000, "loop");
+        assert_eq!(
+            decode(&[0xd0, 0xfe]).with_symbols(&labels).to_string(),
+            "BNE loop"
+        );
+    }
+
+    #[test]
+    fn disassembler() {
+        // LDA #1, an illegal SLO $10, RTS. Shown as bytes, the operand of
+        // SLO decodes as a BPL and disassembly stops after the memory.
+        let mem: &[u8] = &[0xa9, 0x01, 0x07, 0x10, 0x60];
+        let lines: Vec<_> = Disassembler::new(&mem, 0).map(|l| l.to_string()).collect();
+        assert_eq!(lines, ["LDA #$01", ".byte $07", "BPL $0065"]);
+
+        let mut disasm = Disassembler::new(&mem, 0);
+        disasm.set_illegal(true);
+        assert!(disasm.illegal());
+        let lines: Vec<_> = disasm.map(|l| (l.addr(), l.size())).collect();
+        assert_eq!(lines, [(0, 2), (2, 2), (4, 1)]);
+
+        // Stops at the end of the address space.
+        let mut mem = [0xea; 0x10000];
+        mem[0xfffe] = 0xad;
+        let mut disasm = Disassembler::new(&mem, 0xfffd);
+        assert_eq!(disasm.next().map(|l| l.to_string()).as_deref(), Some("NOP"));
+        assert_eq!(
+            disasm.next().map(|l| l.to_string()).as_deref(),
+            Some("LDA $EAEA")
+        );
+        assert_eq!((disasm.next(), disasm.addr()), (None, None));
+    }
+}
@@ -0,0 +1,1052 @@
+//! GDB remote serial protocol stub.
+//!
+//! [`GdbStub`] serves a single GDB session over any byte stream, e.g. a
+//! `TcpStream` accepted from a listener or the standard input and output of
+//! the process. Connect with `target remote` from GDB or any other frontend
+//! speaking the protocol.
+//!
+//! The registers are described to GDB through a target description in the
+//! order `a`, `x`, `y`, `p`, `sp` and `pc`, where `pc` is 16 bits. Memory is
+//! accessed with [`Memory::get`] and [`Memory::set`] so the debugger never
+//! triggers any side effects.
+//!
+//! Supported are register and memory reads and writes, software and
+//! hardware breakpoints, single-step and continue. A continue runs until a
+//! breakpoint is hit, the program traps by jumping or branching to itself,
+//! the processor jams or GDB interrupts it, which is checked for through
+//! [`Connection::poll_interrupt`] every so often. Serving with a
+//! [`History`] also supports reverse step and reverse continue.
+//!
+//! Requires feature `std`.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt::Write as _;
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+};
+
+use crate::{
+    debug::{Breakpoint, Debugger, StopReason},
+    memory::Memory,
+    reverse::History,
+    CycleTicker,
+};
+
+extern crate alloc;
+extern crate std;
+
+/// Target description sent to GDB.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.m6502.core">
+    <reg name="a" bitsize="8" type="int8"/>
+    <reg name="x" bitsize="8" type="int8"/>
+    <reg name="y" bitsize="8" type="int8"/>
+    <reg name="p" bitsize="8" type="int8"/>
+    <reg name="sp" bitsize="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+/// Largest packet GDB may send, as advertised in reply to `qSupported`.
+const PACKET_SIZE: usize = 0x1000;
+
+/// Most bytes sent in reply to a memory read, so the hex encoded reply fits
+/// in [`PACKET_SIZE`]. GDB asks again for the rest.
+const MAX_READ: usize = PACKET_SIZE / 2 - 4;
+
+/// Number of cycles run between checks for an interrupt from GDB.
+const CONTINUE_CYCLES: u64 = 1 << 20;
+
+/// Byte sent by GDB to interrupt a running target, i.e. Ctrl-C.
+const INTERRUPT: u8 = 0x03;
+
+/// Signal reported when GDB interrupted a continue.
+const SIGINT: u8 = 2;
+
+/// Signal reported when stopping at a breakpoint or after a step.
+const SIGTRAP: u8 = 5;
+
+/// Signal reported when the processor has jammed.
+const SIGILL: u8 = 4;
+
+/// What to do after handling a packet.
+enum Action {
+    Reply(String),
+    Continue,
+    Step,
+    ReverseContinue,
+    ReverseStep,
+    Detach,
+    Kill,
+}
+
+/// A byte stream a [`GdbStub`] talks to GDB over.
+pub trait Connection: Read + Write {
+    /// Returns whether GDB asked to interrupt the running target, consuming
+    /// the request. Must not block and must leave any other data unread.
+    /// Should fail once GDB has closed the connection, so a running target
+    /// is not left running for nobody.
+    ///
+    /// The default never reports an interrupt.
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Connection for TcpStream {
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+        self.set_nonblocking(true)?;
+        let peeked = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+        match peeked {
+            Ok(1) if byte[0] == INTERRUPT => {
+                self.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(false),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl<C: Connection + ?Sized> Connection for &mut C {
+    #[inline]
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        (**self).poll_interrupt()
+    }
+}
+
+/// A GDB remote protocol server for a single session.
+///
+/// Breakpoints set from GDB are kept by the stub and are only checked while
+/// it runs the processor. Traps are always detected, see
+/// [`Debugger::set_detect_traps`].
+#[derive(Debug)]
+pub struct GdbStub<S> {
+    stream: S,
+    debugger: Debugger<Vec<Breakpoint>>,
+    no_ack: bool,
+    reversible: bool,
+    continue_cycles: u64,
+}
+
+/// How the processor is run, with or without recording history.
+trait Target {
+    type Mem: Memory;
+
+    fn mem(&mut self) -> &mut Self::Mem;
+
+    /// Called after GDB changed the registers or memory.
+    fn edited(&mut self);
+
+    fn step(&mut self, cpu: &mut CycleTicker);
+
+    fn run(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<Vec<Breakpoint>>,
+        max_cycles: u64,
+    ) -> StopReason;
+
+    fn step_back(&mut self, cpu: &mut CycleTicker) -> StopReason;
+
+    fn reverse_continue(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<Vec<Breakpoint>>,
+    ) -> StopReason;
+}
+
+/// Plain memory, running forward only.
+struct Forward<'a, M>(&'a mut M);
+
+impl<M: Memory> Target for Forward<'_, M> {
+    type Mem = M;
+
+    fn mem(&mut self) -> &mut M {
+        self.0
+    }
+
+    fn edited(&mut self) {}
+
+    fn step(&mut self, cpu: &mut CycleTicker) {
+        cpu.step(self.0);
+    }
+
+    fn run(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<Vec<Breakpoint>>,
+        max_cycles: u64,
+    ) -> StopReason {
+        debugger.run(cpu, self.0, max_cycles)
+    }
+
+    fn step_back(&mut self, _cpu: &mut CycleTicker) -> StopReason {
+        StopReason::HistoryExhausted
+    }
+
+    fn reverse_continue(
+        &mut self,
+        _cpu: &mut CycleTicker,
+        _debugger: &Debugger<Vec<Breakpoint>>,
+    ) -> StopReason {
+        StopReason::HistoryExhausted
+    }
+}
+
+impl<M: Memory> Target for History<M> {
+    type Mem = M;
+
+    fn mem(&mut self) -> &mut M {
+        self.mem_mut()
+    }
+
+    /// The edits are not recorded, so the history is thrown away rather
+    /// than stepping back into a state mixing old and new values.
+    fn edited(&mut self) {
+        self.clear();
+    }
+
+    fn step(&mut self, cpu: &mut CycleTicker) {
+        History::step(self, cpu);
+    }
+
+    fn run(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<Vec<Breakpoint>>,
+        max_cycles: u64,
+    ) -> StopReason {
+        History::run(self, cpu, debugger, max_cycles)
+    }
+
+    fn step_back(&mut self, cpu: &mut CycleTicker) -> StopReason {
+        if History::step_back(self, cpu) {
+            StopReason::Limit
+        } else {
+            StopReason::HistoryExhausted
+        }
+    }
+
+    fn reverse_continue(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<Vec<Breakpoint>>,
+    ) -> StopReason {
+        History::reverse_continue(self, cpu, debugger)
+    }
+}
+
+impl<S: Connection> GdbStub<S> {
+    /// Returns a new stub talking to GDB over `stream`.
+    pub fn new(stream: S) -> GdbStub<S> {
+        let mut debugger = Debugger::new(Vec::new());
+        debugger.set_detect_traps(true);
+        GdbStub {
+            stream,
+            debugger,
+            no_ack: false,
+            reversible: false,
+            continue_cycles: CONTINUE_CYCLES,
+        }
+    }
+
+    /// Returns the stream.
+    #[inline]
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
+
+    /// Returns the addresses of the breakpoints set from GDB.
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.debugger.breakpoints().iter().filter_map(|b| match b {
+            Breakpoint::Pc(addr) => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Consumes the stub returning the stream.
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Serves GDB until it detaches, kills the session or closes the stream.
+    /// The processor is left in whatever state GDB left it in.
+    pub fn serve<M: Memory>(&mut self, cpu: &mut CycleTicker, mem: &mut M) -> io::Result<()> {
+        self.reversible = false;
+        self.serve_target(cpu, &mut Forward(mem))
+    }
+
+    /// Like [`serve`], but runs the processor through `history` so GDB can
+    /// also step and continue backwards, e.g. with `reverse-stepi`.
+    ///
+    /// Changing registers or memory from GDB clears the history, since the
+    /// changes are not recorded and could not be undone.
+    ///
+    /// [`serve`]: GdbStub::serve
+    pub fn serve_with_history<M: Memory>(
+        &mut self,
+        cpu: &mut CycleTicker,
+        history: &mut History<M>,
+    ) -> io::Result<()> {
+        self.reversible = true;
+        self.serve_target(cpu, history)
+    }
+
+    fn serve_target<T: Target>(&mut self, cpu: &mut CycleTicker, target: &mut T) -> io::Result<()> {
+        while let Some(packet) = self.recv()? {
+            match self.handle(&packet, cpu, target) {
+                Action::Reply(reply) => self.send(&reply)?,
+                Action::Continue => {
+                    let reply = match self.resume(cpu, target)? {
+                        Some(reason) => stop_reply(reason),
+                        None => signal_reply(SIGINT),
+                    };
+                    self.send(&reply)?;
+                }
+                Action::Step => {
+                    let reason = if cpu.is_jammed() {
+                        StopReason::Jammed
+                    } else {
+                        target.step(cpu);
+                        StopReason::Limit
+                    };
+                    self.send(&stop_reply(reason))?;
+                }
+                Action::ReverseContinue => {
+                    let reason = target.reverse_continue(cpu, &self.debugger);
+                    self.send(&stop_reply(reason))?;
+                }
+                Action::ReverseStep => {
+                    let reason = target.step_back(cpu);
+                    self.send(&stop_reply(reason))?;
+                }
+                Action::Detach => {
+                    self.send("OK")?;
+                    return Ok(());
+                }
+                Action::Kill => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the processor until it stops, returning `None` if GDB
+    /// interrupted it.
+    ///
+    /// The run is split in chunks so the stream can be polled in between.
+    /// Breakpoints are checked before the cycle limit of a run, so one
+    /// matching right at the end of a chunk stops the run rather than being
+    /// skipped when the next chunk starts.
+    fn resume<T: Target>(
+        &mut self,
+        cpu: &mut CycleTicker,
+        target: &mut T,
+    ) -> io::Result<Option<StopReason>> {
+        loop {
+            match target.run(cpu, &self.debugger, self.continue_cycles) {
+                StopReason::Limit => {
+                    if self.stream.poll_interrupt()? {
+                        return Ok(None);
+                    }
+                }
+                reason => return Ok(Some(reason)),
+            }
+        }
+    }
+
+    /// Handles a single packet.
+    fn handle<T: Target>(&mut self, packet: &str, cpu: &mut CycleTicker, target: &mut T) -> Action {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match cmd {
+            "?" => stop_reply(StopReason::Limit),
+            "g" => {
+                let mut reply = String::new();
+                for n in 0..6 {
+                    write_reg(&mut reply, cpu, n);
+                }
+                reply
+            }
+            "G" => {
+                let bytes = parse_hex_bytes(args);
+                match bytes.as_deref() {
+                    Some([a, x, y, p, sp, lo, hi, ..]) => {
+                        let state = cpu.state_mut();
+                        state.a = *a;
+                        state.x = *x;
+                        state.y = *y;
+                        state.flags = *p & !0x30;
+                        state.sp = *sp;
+                        state.pc = u16::from_le_bytes([*lo, *hi]);
+                        target.edited();
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < 6 => {
+                    let mut reply = String::new();
+                    write_reg(&mut reply, cpu, n);
+                    reply
+                }
+                _ => "E01".into(),
+            },
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(n, value)| {
+                    Some((usize::from_str_radix(n, 16).ok()?, parse_hex_bytes(value)?))
+                });
+                match parsed {
+                    Some((n, value)) if n < 6 && !value.is_empty() => {
+                        let state = cpu.state_mut();
+                        match n {
+                            0 => state.a = value[0],
+                            1 => state.x = value[0],
+                            2 => state.y = value[0],
+                            3 => state.flags = value[0] & !0x30,
+                            4 => state.sp = value[0],
+                            _ => {
+                                let hi = if value.len() > 1 { value[1] } else { 0 };
+                                state.pc = u16::from_le_bytes([value[0], hi]);
+                            }
+                        }
+                        target.edited();
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    // Reads are cut short at the end of the address space.
+                    let len = len.min(0x10000 - usize::from(addr)).min(MAX_READ);
+                    let mut reply = String::with_capacity(len * 2);
+                    for i in 0..len {
+                        let value = target.mem().get(addr.wrapping_add(i as u16)).unwrap_or(0);
+                        let _ = write!(reply, "{value:02x}");
+                    }
+                    reply
+                }
+                None => "E01".into(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    Some((parse_addr_len(range)?, parse_hex_bytes(data)?))
+                });
+                match parsed {
+                    Some(((addr, len), data))
+                        if data.len() == len && len <= 0x10000 - usize::from(addr) =>
+                    {
+                        let mem = target.mem();
+                        for (i, value) in data.into_iter().enumerate() {
+                            mem.set(addr.wrapping_add(i as u16), value);
+                        }
+                        target.edited();
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(addr) => {
+                    let breakpoints = self.debugger.breakpoints_mut();
+                    let bp = Breakpoint::Pc(addr);
+                    if cmd == "Z" {
+                        if !breakpoints.contains(&bp) {
+                            breakpoints.push(bp);
+                        }
+                    } else {
+                        breakpoints.retain(|b| *b != bp);
+                    }
+                    "OK".into()
+                }
+                None => String::new(),
+            },
+            "c" | "s" => {
+                if let Some(addr) = (!args.is_empty())
+                    .then(|| u16::from_str_radix(args, 16).ok())
+                    .flatten()
+                {
+                    cpu.state_mut().pc = addr;
+                    target.edited();
+                }
+                return if cmd == "c" {
+                    Action::Continue
+                } else {
+                    Action::Step
+                };
+            }
+            "b" if self.reversible && args == "c" => return Action::ReverseContinue,
+            "b" if self.reversible && args == "s" => return Action::ReverseStep,
+            "D" => return Action::Detach,
+            "k" => return Action::Kill,
+            "H" => "OK".into(),
+            "T" => "OK".into(),
+            "q" | "Q" => self.query(packet),
+            _ => String::new(),
+        };
+        Action::Reply(reply)
+    }
+
+    /// Handles general query and set packets.
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            let mut reply = String::new();
+            let _ = write!(
+                reply,
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;QStartNoAckMode+"
+            );
+            if self.reversible {
+                reply.push_str(";ReverseStep+;ReverseContinue+");
+            }
+            return reply;
+        }
+        if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            return match parse_addr_len(args) {
+                Some((offset, len)) => {
+                    let offset = usize::from(offset).min(TARGET_XML.len());
+                    let end = (offset + len).min(TARGET_XML.len());
+                    let prefix = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                    let mut reply = String::from(prefix);
+                    reply.push_str(&TARGET_XML[offset..end]);
+                    reply
+                }
+                None => "E01".into(),
+            };
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                "OK".into()
+            }
+            "qAttached" => "1".into(),
+            "qC" => "QC1".into(),
+            "qfThreadInfo" => "m1".into(),
+            "qsThreadInfo" => "l".into(),
+            _ => String::new(),
+        }
+    }
+
+    /// Receives the next packet, acknowledging it unless acknowledgements
+    /// have been turned off. Returns `None` once the stream is closed.
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        let mut byte = [0];
+        loop {
+            // Skip acknowledgements and interrupts until a packet starts.
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            loop {
+                if self.stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                match byte[0] {
+                    b'#' => break,
+                    b'}' => {
+                        if self.stream.read(&mut byte)? == 0 {
+                            return Ok(None);
+                        }
+                        data.push(byte[0] ^ 0x20);
+                    }
+                    b => data.push(b),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = core::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            let actual = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+            if !self.no_ack {
+                if expected != Some(actual) {
+                    self.stream.write_all(b"-")?;
+                    self.stream.flush()?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+                self.stream.flush()?;
+            }
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    /// Sends a packet.
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let mut packet = String::with_capacity(data.len() + 4);
+        packet.push('$');
+        let mut checksum = 0u8;
+        for c in data.chars() {
+            if matches!(c, '$' | '#' | '}' | '*') {
+                packet.push('}');
+                packet.push(char::from(c as u8 ^ 0x20));
+                checksum = checksum.wrapping_add(b'}').wrapping_add(c as u8 ^ 0x20);
+            } else {
+                packet.push(c);
+                checksum = checksum.wrapping_add(c as u8);
+            }
+        }
+        let _ = write!(packet, "#{checksum:02x}");
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()
+    }
+}
+
+/// Returns the stop reply packet for `reason`.
+fn stop_reply(reason: StopReason) -> String {
+    if reason == StopReason::HistoryExhausted {
+        return "T05replaylog:begin;".into();
+    }
+    signal_reply(match reason {
+        StopReason::Jammed => SIGILL,
+        _ => SIGTRAP,
+    })
+}
+
+/// Returns the stop reply packet reporting `signal`.
+fn signal_reply(signal: u8) -> String {
+    let mut reply = String::new();
+    let _ = write!(reply, "S{signal:02x}");
+    reply
+}
+
+/// Appends register `n` as hex in target byte order.
+fn write_reg(out: &mut String, cpu: &CycleTicker, n: usize) {
+    let state = cpu.state();
+    let _ = match n {
+        0 => write!(out, "{:02x}", state.a),
+        1 => write!(out, "{:02x}", state.x),
+        2 => write!(out, "{:02x}", state.y),
+        3 => write!(out, "{:02x}", state.flags | 0x20),
+        4 => write!(out, "{:02x}", state.sp),
+        _ => {
+            let [lo, hi] = state.pc.to_le_bytes();
+            write!(out, "{lo:02x}{hi:02x}")
+        }
+    };
+}
+
+/// Parses a string of hex byte pairs.
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Parses `addr,len` as used by memory packets. Fails for addresses outside
+/// the address space.
+fn parse_addr_len(s: &str) -> Option<(u16, usize)> {
+    let (addr, len) = s.split_once(',')?;
+    let addr = u16::from_str_radix(addr, 16).ok()?;
+    let len = usize::from_str_radix(len, 16).ok()?;
+    Some((addr, len))
+}
+
+/// Parses the arguments of a software or hardware breakpoint packet,
+/// `type,addr,kind`, returning the address.
+fn parse_breakpoint(s: &str) -> Option<u16> {
+    let mut parts = s.split(',');
+    let kind = parts.next()?;
+    if kind != "0" && kind != "1" {
+        return None;
+    }
+    let addr = u32::from_str_radix(parts.next()?, 16).ok()?;
+    Some(addr as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::ToString, vec};
+    use std::{io::Cursor, net::TcpListener};
+
+    use super::*;
+    use crate::M6502;
+
+    /// An in-memory connection reading scripted input and collecting the
+    /// output.
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Write::write(&mut self.output, buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Pipe {
+        fn poll_interrupt(&mut self) -> io::Result<bool> {
+            let pos = self.input.position() as usize;
+            let interrupt = self.input.get_ref().as_slice().get(pos) == Some(&INTERRUPT);
+            if interrupt {
+                self.input.set_position(pos as u64 + 1);
+            }
+            Ok(interrupt)
+        }
+    }
+
+    /// Returns `data` framed as a packet.
+    fn packet(data: &str) -> String {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        format!("${data}#{checksum:02x}")
+    }
+
+    /// Returns a stub reading `input`, which is sent as packets except for
+    /// entries starting with `\x03`.
+    fn stub(input: &[&str]) -> GdbStub<Pipe> {
+        let input: String = input
+            .iter()
+            .map(|s| match s.starts_with('\x03') {
+                true => s.to_string(),
+                false => packet(s),
+            })
+            .collect();
+        GdbStub::new(Pipe {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        })
+    }
+
+    /// Returns the packets sent by the stub, ignoring acknowledgements.
+    fn replies(stub: GdbStub<Pipe>) -> Vec<String> {
+        let output = String::from_utf8(stub.into_inner().output).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|p| p.split_once('#').unwrap().0.to_string())
+            .collect()
+    }
+
+    /// Returns a processor at `$0200` and memory holding `program` there.
+    fn setup(program: &[u8]) -> (CycleTicker, Vec<u8>) {
+        let mut mem = vec![0; 0x10000];
+        mem[0x200..0x200 + program.len()].copy_from_slice(program);
+        let cpu = CycleTicker::new(M6502 {
+            pc: 0x0200,
+            ..M6502::new()
+        });
+        (cpu, mem)
+    }
+
+    /// Serves `input` for `program`, returning the replies and the state
+    /// left behind.
+    fn serve(input: &[&str], program: &[u8]) -> (Vec<String>, CycleTicker, Vec<u8>) {
+        let (mut cpu, mut mem) = setup(program);
+        let mut stub = stub(input);
+        stub.serve(&mut cpu, &mut mem).unwrap();
+        (replies(stub), cpu, mem)
+    }
+
+    #[test]
+    fn registers() {
+        let (replies, cpu, _) = serve(
+            &["g", "G0102030405eeff", "g", "P3=c3", "p3", "p5", "p6"],
+            &[],
+        );
+        assert_eq!(
+            replies,
+            [
+                "00000020000002",
+                "OK",
+                "0102032405eeff",
+                "OK",
+                "e3",
+                "eeff",
+                "E01"
+            ]
+        );
+        let state = cpu.state();
+        assert_eq!(
+            (state.a, state.sp, state.pc, state.flags),
+            (1, 5, 0xffee, 0xc3)
+        );
+    }
+
+    #[test]
+    fn memory() {
+        let (replies, _, mem) = serve(&["M10,3:aabbcc", "m0f,5", "M10,2:aa", "mffff,2"], &[0xea]);
+        assert_eq!(replies, ["OK", "00aabbcc00", "E01", "00"]);
+        assert_eq!(&mem[0x10..0x13], [0xaa, 0xbb, 0xcc]);
+
+        // Out of the address space, or too long for a packet.
+        let (replies, _, mem) = serve(
+            &[
+                "m10000,1",
+                "m0,ffffffff",
+                "Mffff,2:aabb",
+                "M10000,1:aa",
+                "M0,ffffffffffffffff:",
+            ],
+            &[],
+        );
+        assert_eq!(replies[0], "E01");
+        assert_eq!(replies[1].len(), MAX_READ * 2);
+        assert_eq!(replies[2..], ["E01", "E01", "E01"]);
+        assert!(mem.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn queries() {
+        let (replies, _, _) = serve(
+            &[
+                "qSupported:xmlRegisters=i386",
+                "qXfer:features:read:target.xml:0,10",
+                "qAttached",
+                "vMustReplyEmpty",
+            ],
+            &[],
+        );
+        assert_eq!(
+            replies[0],
+            "PacketSize=1000;qXfer:features:read+;QStartNoAckMode+"
+        );
+        assert_eq!(replies[1], format!("m{}", &TARGET_XML[..0x10]));
+        assert_eq!(replies[2..], ["1", ""]);
+
+        let len = TARGET_XML.len();
+        let (replies, _, _) = serve(
+            &[&format!("qXfer:features:read:target.xml:{:x},100", len - 4)],
+            &[],
+        );
+        assert_eq!(replies, [format!("l{}", &TARGET_XML[len - 4..])]);
+    }
+
+    #[test]
+    fn acknowledgements() {
+        let mut stub = stub(&[]);
+        stub.stream.input = Cursor::new(b"+$g#00$g#67$QStartNoAckMode#b0$g#67".to_vec());
+        let (mut cpu, mut mem) = setup(&[]);
+        stub.serve(&mut cpu, &mut mem).unwrap();
+        let output = String::from_utf8(stub.into_inner().output).unwrap();
+        assert_eq!(
+            output,
+            format!(
+                "-+{}+{}{}",
+                packet("00000020000002"),
+                packet("OK"),
+                packet("00000020000002")
+            )
+        );
+    }
+
+    #[test]
+    fn step_and_continue() {
+        // NOP, NOP, NOP, JMP $0201.
+        let program = [0xea, 0xea, 0xea, 0x4c, 0x01, 0x02];
+        let (replies, cpu, _) = serve(&["s", "Z0,202,1", "c", "c", "z0,202,1", "s"], &program);
+        assert_eq!(replies, ["S05", "OK", "S05", "S05", "OK", "S05"]);
+        assert_eq!(cpu.state().pc, 0x0203);
+
+        // Continuing from an address.
+        let (replies, cpu, _) = serve(&["Z0,203,1", "c202"], &program);
+        assert_eq!(replies, ["OK", "S05"]);
+        assert_eq!((cpu.state().pc, cpu.cycles()), (0x0203, 2));
+    }
+
+    #[test]
+    fn breakpoint_at_chunk_boundary() {
+        // Eight NOPs followed by JMP *.
+        let mut program = [0xea; 11];
+        program[8..].copy_from_slice(&[0x4c, 0x08, 0x02]);
+
+        for chunk in 1..=8 {
+            for target in 0x0201..=0x0207u16 {
+                let (mut cpu, mut mem) = setup(&program);
+                let mut stub = stub(&[&format!("Z0,{target:x},1"), "c"]);
+                stub.continue_cycles = chunk;
+                stub.serve(&mut cpu, &mut mem).unwrap();
+                assert_eq!(cpu.state().pc, target, "chunk of {chunk} cycles");
+                assert_eq!(replies(stub), ["OK", "S05"]);
+            }
+        }
+    }
+
+    #[test]
+    fn traps_and_jams() {
+        // NOP, JMP *.
+        let (replies, cpu, _) = serve(&["c"], &[0xea, 0x4c, 0x01, 0x02]);
+        assert_eq!((replies[0].as_str(), cpu.state().pc), ("S05", 0x0201));
+
+        // A taken BNE *.
+        let (replies, cpu, _) = serve(&["c"], &[0xa2, 0x01, 0xd0, 0xfe]);
+        assert_eq!((replies[0].as_str(), cpu.state().pc), ("S05", 0x0202));
+
+        let (replies, cpu, _) = serve(&["c", "s", "?"], &[0xea, 0x02]);
+        assert_eq!(replies, ["S04", "S04", "S05"]);
+        assert!(cpu.is_jammed());
+    }
+
+    #[test]
+    fn interrupt() {
+        // NOP, JMP $0200, which never traps.
+        let (mut cpu, mut mem) = setup(&[0xea, 0x4c, 0x00, 0x02]);
+        let mut stub = stub(&["c", "\x03", "g"]);
+        stub.continue_cycles = 100;
+        stub.serve(&mut cpu, &mut mem).unwrap();
+        let replies = replies(stub);
+        assert_eq!(replies[0], "S02");
+        assert_eq!(replies.len(), 2);
+        assert!((100..110).contains(&cpu.cycles()));
+    }
+
+    #[test]
+    fn detach_and_kill() {
+        let (replies, _, _) = serve(&["D", "g"], &[]);
+        assert_eq!(replies, ["OK"]);
+        let (replies, _, _) = serve(&["k", "g"], &[]);
+        assert!(replies.is_empty());
+    }
+
+    #[test]
+    fn breakpoints() {
+        let (mut cpu, mut mem) = setup(&[]);
+        let mut stub = stub(&[
+            "Z0,1234,1",
+            "Z1,beef,1",
+            "Z0,1234,1",
+            "Z2,10,1",
+            "z0,1234,1",
+        ]);
+        stub.serve(&mut cpu, &mut mem).unwrap();
+        assert_eq!(stub.breakpoints().collect::<Vec<_>>(), [0xbeef]);
+        assert_eq!(replies(stub), ["OK", "OK", "OK", "", "OK"]);
+    }
+
+    #[test]
+    fn reverse_needs_history() {
+        let (replies, _, _) = serve(&["qSupported", "bs", "bc"], &[0xea]);
+        assert!(!replies[0].contains("Reverse"));
+        assert_eq!(replies[1..], ["", ""]);
+    }
+
+    /// Serves `input` for `program` through a history, returning the
+    /// replies, the state left behind and the history depth.
+    fn serve_history(input: &[&str], program: &[u8]) -> (Vec<String>, CycleTicker, usize) {
+        let (mut cpu, mem) = setup(program);
+        let mut history = History::new(mem, 100);
+        let mut stub = stub(input);
+        stub.serve_with_history(&mut cpu, &mut history).unwrap();
+        (replies(stub), cpu, history.depth())
+    }
+
+    #[test]
+    fn reverse() {
+        // INX, INX, JMP $0200.
+        let program = [0xe8, 0xe8, 0x4c, 0x00, 0x02];
+        let (replies, cpu, depth) = serve_history(
+            &[
+                "qSupported",
+                "s",
+                "s",
+                "bs",
+                "p1",
+                "Z0,201,1",
+                "c",
+                "c",
+                "bc",
+                "p1",
+                "bs",
+                "bs",
+                "bs",
+                "bs",
+                "bs",
+            ],
+            &program,
+        );
+        assert!(replies[0].ends_with(";ReverseStep+;ReverseContinue+"));
+        assert_eq!(replies[1..5], ["S05", "S05", "S05", "01"]);
+        assert_eq!(replies[5..10], ["OK", "S05", "S05", "S05", "03"]);
+        assert_eq!(replies[10..14], ["S05"; 4]);
+        assert_eq!(replies[14], "T05replaylog:begin;");
+        assert_eq!(
+            (cpu.state().pc, cpu.state().x, cpu.cycles()),
+            (0x0200, 0, 0)
+        );
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn edits_clear_history() {
+        let program = [0xe8, 0xe8, 0x4c, 0x00, 0x02];
+        for edit in ["G0102030405eeff", "P1=05", "M10,1:aa"] {
+            let (replies, _, depth) = serve_history(&["s", "s", edit, "bs"], &program);
+            assert_eq!(replies[3], "T05replaylog:begin;", "{edit}");
+            assert_eq!(depth, 0, "{edit}");
+        }
+
+        // Stepping from an address only keeps that step.
+        let (replies, cpu, _) = serve_history(&["s", "s", "s201", "bs", "bs"], &program);
+        assert_eq!(replies[3..], ["S05", "T05replaylog:begin;"]);
+        assert_eq!(cpu.state().pc, 0x0201);
+
+        // Reads keep it.
+        let (replies, _, depth) = serve_history(&["s", "g", "m10,1", "bs"], &program);
+        assert_eq!((replies[3].as_str(), depth), ("S05", 0));
+    }
+
+    #[test]
+    fn history_breakpoint_at_chunk_boundary() {
+        let mut program = [0xea; 11];
+        program[8..].copy_from_slice(&[0x4c, 0x08, 0x02]);
+
+        for chunk in 1..=8 {
+            for target in 0x0201..=0x0207u16 {
+                let (mut cpu, mem) = setup(&program);
+                let mut history = History::new(mem, 100);
+                let mut stub = stub(&[&format!("Z0,{target:x},1"), "c"]);
+                stub.continue_cycles = chunk;
+                stub.serve_with_history(&mut cpu, &mut history).unwrap();
+                assert_eq!(cpu.state().pc, target, "chunk of {chunk} cycles");
+                assert_eq!(replies(stub), ["OK", "S05"]);
+            }
+        }
+    }
+
+    #[test]
+    fn disconnect_while_running() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        client.write_all(packet("c").as_bytes()).unwrap();
+        drop(client);
+
+        // NOP, JMP $0200, which never traps.
+        let (mut cpu, mut mem) = setup(&[0xea, 0x4c, 0x00, 0x02]);
+        let mut stub = GdbStub::new(server);
+        stub.continue_cycles = 100;
+        assert!(stub.serve(&mut cpu, &mut mem).is_err());
+    }
+}
@@ -1,6 +1,22 @@
 #![no_std]
 
+#[cfg(any(feature = "alloc", doc))]
+pub mod asm;
 pub(crate) mod cpu;
+pub mod debug;
+pub mod disasm;
+#[cfg(any(feature = "std", doc))]
+pub mod gdb;
 pub mod memory;
+#[cfg(any(feature = "alloc", doc))]
+pub mod reverse;
+#[cfg(any(feature = "alloc", doc))]
+pub mod rewind;
+#[cfg(any(feature = "alloc", doc))]
+pub mod symbols;
+pub mod tracer;
 
-pub use cpu::M6502;
+pub use cpu::{
+    AddressingMode, CycleTicker, MemoryAccess, Mnemonic, Opcode, OpcodeInfo, SnapshotError, M6502,
+    OPCODE_INFO,
+};
@@ -1,6 +1,9 @@
 use alloc::{boxed::Box, vec::Vec};
 
-use super::Memory;
+use super::{
+    trace::{BusEvent, TraceBuffer},
+    Memory,
+};
 
 extern crate alloc;
 
@@ -15,6 +18,21 @@ impl<M: Memory + ?Sized> Memory for Box<M> {
     fn set(&mut self, addr: u16, value: u8) {
         self.as_mut().set(addr, value)
     }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        self.as_mut().read(addr)
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.as_mut().write(addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.as_mut().fetch(addr)
+    }
 }
 
 /// Requires feature `alloc`.
@@ -46,3 +64,11 @@ impl Memory for Vec<u8> {
         }
     }
 }
+
+/// Requires feature `alloc`.
+impl TraceBuffer for Vec<BusEvent> {
+    #[inline]
+    fn push(&mut self, event: BusEvent) {
+        Vec::push(self, event)
+    }
+}
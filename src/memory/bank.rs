@@ -0,0 +1,1235 @@
+//! Bank-switching helpers for cartridge mappers.
+//!
+//! The building block is [`BankedMemory`], a windowed view into a larger
+//! block of ROM or RAM where each window can be pointed at any bank of the
+//! underlying data. On top of that a few well-known mapper schemes are
+//! provided which handle the switch-on-access registers themselves:
+//!
+//!  - Atari 2600: [`AtariCart`] with the F8, F6, F4, E0 and 3F schemes.
+//!  - NES: [`Nrom`], [`Uxrom`], [`Cnrom`] and [`Mmc1`].
+//!
+//! All of them implement [`MemoryState`] so they can be rewound with
+//! [`Rewind::with_state`].
+//!
+//! Requires feature `alloc`.
+//!
+//! [`Rewind::with_state`]: crate::rewind::Rewind::with_state
+
+use alloc::{vec, vec::Vec};
+
+use super::Memory;
+use crate::rewind::MemoryState;
+
+extern crate alloc;
+
+/// A windowed view over a larger block of ROM or RAM.
+///
+/// The address range starting at `base` is split into a number of equally
+/// sized windows. Each window shows one bank of the underlying data, where a
+/// bank is a `window_size` sized chunk of the data. Addresses outside the
+/// windows are not mapped and [`get`] returns [`None`] for them.
+///
+/// Processor writes through [`write`] are ignored unless the memory has been
+/// made writable with [`set_writable`], while [`set`] always modifies the
+/// underlying data so that ROM can be patched. RAM created with [`ram`]
+/// starts out writable and can be write-protected the same way.
+///
+/// [`get`]: Memory::get
+/// [`set`]: Memory::set
+/// [`write`]: Memory::write
+/// [`set_writable`]: BankedMemory::set_writable
+/// [`ram`]: BankedMemory::ram
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BankedMemory {
+    data: Vec<u8>,
+    base: u16,
+    window_size: usize,
+    banks: Vec<usize>,
+    writable: bool,
+    ram: bool,
+}
+
+impl BankedMemory {
+    /// Creates a new read-only banked memory mapping `windows` windows of
+    /// `window_size` bytes each, starting at address `base`. All windows
+    /// initially show bank 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window_size` is not a power of two, if the data is empty or
+    /// not a multiple of `window_size` in length, or if the windows do not fit
+    /// inside the address space.
+    pub fn new(data: Vec<u8>, base: u16, window_size: usize, windows: usize) -> BankedMemory {
+        assert!(
+            window_size.is_power_of_two(),
+            "window size should be a power of two"
+        );
+        assert!(
+            !data.is_empty() && data.len().is_multiple_of(window_size),
+            "data length should be a non-zero multiple of the window size"
+        );
+        assert!(
+            usize::from(base) + window_size * windows <= 0x10000,
+            "windows should fit inside the address space"
+        );
+
+        BankedMemory {
+            data,
+            base,
+            window_size,
+            banks: vec![0; windows],
+            writable: false,
+            ram: false,
+        }
+    }
+
+    /// Creates a new writable banked memory like [`new`], for RAM. Unlike
+    /// ROM made writable, the data of RAM is saved with the
+    /// [`MemoryState`] even while it is write-protected.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`new`].
+    ///
+    /// [`new`]: BankedMemory::new
+    pub fn ram(data: Vec<u8>, base: u16, window_size: usize, windows: usize) -> BankedMemory {
+        BankedMemory {
+            writable: true,
+            ram: true,
+            ..BankedMemory::new(data, base, window_size, windows)
+        }
+    }
+
+    /// Returns the size in bytes of each window and bank.
+    #[inline]
+    pub fn window_size(&self) -> usize {
+        self.window_size
+    }
+
+    /// Returns the number of windows.
+    #[inline]
+    pub fn windows(&self) -> usize {
+        self.banks.len()
+    }
+
+    /// Returns the number of banks available in the underlying data.
+    #[inline]
+    pub fn bank_count(&self) -> usize {
+        self.data.len() / self.window_size
+    }
+
+    /// Returns the bank currently shown in `window`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is out of range.
+    #[inline]
+    pub fn bank(&self, window: usize) -> usize {
+        self.banks[window]
+    }
+
+    /// Shows `bank` in `window`. Like on real hardware any bank number
+    /// larger than the available number of banks wraps around.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `window` is out of range.
+    #[inline]
+    pub fn select(&mut self, window: usize, bank: usize) {
+        self.banks[window] = bank % self.bank_count();
+    }
+
+    /// Returns whether processor writes modify the underlying data.
+    #[inline]
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    /// Returns whether this is RAM, i.e. was created with
+    /// [`ram`](BankedMemory::ram).
+    #[inline]
+    pub fn is_ram(&self) -> bool {
+        self.ram
+    }
+
+    /// Sets whether processor writes modify the underlying data, making ROM
+    /// writable or write-protecting RAM.
+    #[inline]
+    pub fn set_writable(&mut self, writable: bool) {
+        self.writable = writable;
+    }
+
+    /// Returns the complete underlying data.
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the complete underlying data.
+    #[inline]
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Consumes the banked memory returning the underlying data.
+    #[inline]
+    pub fn into_inner(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Appends the selected banks, and the data if RAM or writable, to `out`.
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(self.writable));
+        for &bank in &self.banks {
+            out.extend_from_slice(&(bank as u32).to_le_bytes());
+        }
+        if self.ram || self.writable {
+            out.extend_from_slice(&self.data);
+        }
+    }
+
+    /// Restores the state appended by [`save`](BankedMemory::save),
+    /// advancing `state` past it.
+    fn restore(&mut self, state: &mut &[u8]) {
+        self.writable = take(state, 1)[0] != 0;
+        for bank in &mut self.banks {
+            let bytes = take(state, 4).try_into().unwrap();
+            *bank = u32::from_le_bytes(bytes) as usize;
+        }
+        if self.ram || self.writable {
+            let len = self.data.len();
+            self.data.copy_from_slice(take(state, len));
+        }
+    }
+
+    /// Translates an address into an offset into the underlying data.
+    #[inline]
+    fn offset(&self, addr: u16) -> Option<usize> {
+        let rel = usize::from(addr.checked_sub(self.base)?);
+        let bank = self.banks.get(rel / self.window_size)?;
+        Some(bank * self.window_size + rel % self.window_size)
+    }
+}
+
+impl Memory for BankedMemory {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.offset(addr).map(|i| self.data[i])
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        if let Some(i) = self.offset(addr) {
+            self.data[i] = value;
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if self.writable {
+            self.set(addr, value);
+        }
+    }
+}
+
+impl MemoryState for BankedMemory {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.save(out);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.restore(&mut state);
+    }
+}
+
+/// Splits the first `len` bytes off `state`.
+fn take<'a>(state: &mut &'a [u8], len: usize) -> &'a [u8] {
+    let (head, rest) = state.split_at(len);
+    *state = rest;
+    head
+}
+
+/// Bank-switching scheme used by an [`AtariCart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AtariScheme {
+    /// 8 KiB Atari standard. Accessing `$1FF8`-`$1FF9` selects one of two
+    /// 4 KiB banks.
+    F8,
+    /// 16 KiB Atari standard. Accessing `$1FF6`-`$1FF9` selects one of four
+    /// 4 KiB banks.
+    F6,
+    /// 32 KiB Atari standard. Accessing `$1FF4`-`$1FFB` selects one of eight
+    /// 4 KiB banks.
+    F4,
+    /// 8 KiB Parker Brothers scheme. The cartridge space is split in four
+    /// 1 KiB windows where the first three are selected by accessing
+    /// `$1FE0`-`$1FE7`, `$1FE8`-`$1FEF` and `$1FF0`-`$1FF7` respectively.
+    /// The last window always shows the last bank.
+    E0,
+    /// Tigervision scheme. The cartridge space is split in two 2 KiB windows
+    /// where the first is selected by writing the bank number to
+    /// `$0000`-`$003F`. The last window always shows the last bank.
+    ///
+    /// Because the hotspots overlap with the TIA registers, writes to those
+    /// addresses need to be forwarded to the cartridge as well.
+    Tigervision3F,
+}
+
+/// An Atari 2600 cartridge using one of the common bank-switching schemes.
+///
+/// The 2600 only exposes 13 address lines so the cartridge is mapped wherever
+/// address line 12 is set, i.e. at `$1000`-`$1FFF` and every mirror of it.
+/// Reads from outside the cartridge space return [`None`].
+///
+/// Bank switching happens on any processor access to a hotspot, both reads
+/// and writes. [`get`] and [`set`] never switch banks.
+///
+/// [`get`]: Memory::get
+/// [`set`]: Memory::set
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AtariCart {
+    scheme: AtariScheme,
+    rom: BankedMemory,
+}
+
+impl AtariCart {
+    /// Creates a new cartridge from the ROM image. The cartridge starts in the
+    /// bank used by the real hardware at power on.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ROM image does not have the size expected by the scheme.
+    /// For [`AtariScheme::Tigervision3F`] any multiple of 2 KiB is accepted.
+    pub fn new(scheme: AtariScheme, rom: Vec<u8>) -> AtariCart {
+        let (size, window_size) = match scheme {
+            AtariScheme::F8 => (0x2000, 0x1000),
+            AtariScheme::F6 => (0x4000, 0x1000),
+            AtariScheme::F4 => (0x8000, 0x1000),
+            AtariScheme::E0 => (0x2000, 0x0400),
+            AtariScheme::Tigervision3F => (rom.len(), 0x0800),
+        };
+        assert_eq!(rom.len(), size, "ROM image has wrong size for scheme");
+
+        let windows = 0x1000 / window_size;
+        let mut rom = BankedMemory::new(rom, 0x1000, window_size, windows);
+        let last = rom.bank_count() - 1;
+        match scheme {
+            // The standard Atari schemes start in the last bank.
+            AtariScheme::F8 | AtariScheme::F6 | AtariScheme::F4 => rom.select(0, last),
+            AtariScheme::E0 => {
+                rom.select(0, 4);
+                rom.select(1, 5);
+                rom.select(2, 6);
+                rom.select(3, last);
+            }
+            AtariScheme::Tigervision3F => rom.select(1, last),
+        }
+
+        AtariCart { scheme, rom }
+    }
+
+    /// Returns the bank-switching scheme of the cartridge.
+    #[inline]
+    pub fn scheme(&self) -> AtariScheme {
+        self.scheme
+    }
+
+    /// Returns the underlying banked ROM.
+    #[inline]
+    pub fn rom(&self) -> &BankedMemory {
+        &self.rom
+    }
+
+    /// Returns the underlying banked ROM.
+    #[inline]
+    pub fn rom_mut(&mut self) -> &mut BankedMemory {
+        &mut self.rom
+    }
+
+    /// Handles a processor access to `addr`, switching banks if it is a
+    /// hotspot.
+    fn access(&mut self, addr: u16, value: Option<u8>) {
+        let addr = addr & 0x1fff;
+        match self.scheme {
+            AtariScheme::F8 => {
+                if let 0x1ff8..=0x1ff9 = addr {
+                    self.rom.select(0, usize::from(addr - 0x1ff8));
+                }
+            }
+            AtariScheme::F6 => {
+                if let 0x1ff6..=0x1ff9 = addr {
+                    self.rom.select(0, usize::from(addr - 0x1ff6));
+                }
+            }
+            AtariScheme::F4 => {
+                if let 0x1ff4..=0x1ffb = addr {
+                    self.rom.select(0, usize::from(addr - 0x1ff4));
+                }
+            }
+            AtariScheme::E0 => {
+                if let 0x1fe0..=0x1ff7 = addr {
+                    let window = usize::from((addr >> 3) & 0x03);
+                    self.rom.select(window, usize::from(addr & 0x07));
+                }
+            }
+            AtariScheme::Tigervision3F => {
+                if let (0x0000..=0x003f, Some(value)) = (addr, value) {
+                    self.rom.select(0, usize::from(value));
+                }
+            }
+        }
+    }
+}
+
+impl Memory for AtariCart {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        if addr & 0x1000 != 0 {
+            self.rom.get(addr & 0x1fff)
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        if addr & 0x1000 != 0 {
+            self.rom.set(addr & 0x1fff, value);
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        // The value is read before the bank switch takes effect.
+        let value = self.get(addr).unwrap_or(0);
+        self.access(addr, None);
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.access(addr, Some(value));
+    }
+}
+
+impl MemoryState for AtariCart {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.rom.save(out);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.rom.restore(&mut state);
+    }
+}
+
+/// Nametable mirroring selected by a NES mapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Mirroring {
+    /// Both nametables show the first page of VRAM.
+    SingleScreenLower,
+    /// Both nametables show the second page of VRAM.
+    SingleScreenUpper,
+    /// Vertical mirroring. `$2000` and `$2800` show the first page and
+    /// `$2400` and `$2C00` the second, so the nametables are side by side.
+    Vertical,
+    /// Horizontal mirroring. `$2000` and `$2400` show the first page and
+    /// `$2800` and `$2C00` the second, so the nametables are stacked.
+    Horizontal,
+}
+
+/// Creates the CHR memory of a NES cartridge, which is mapped at
+/// `$0000`-`$1FFF` in the PPU address space. An empty image means the
+/// cartridge has 8 KiB of CHR RAM instead.
+fn nes_chr(chr: Vec<u8>, window_size: usize) -> BankedMemory {
+    if chr.is_empty() {
+        BankedMemory::ram(vec![0; 0x2000], 0x0000, window_size, 0x2000 / window_size)
+    } else {
+        BankedMemory::new(chr, 0x0000, window_size, 0x2000 / window_size)
+    }
+}
+
+/// NES mapper 0. Up to 32 KiB of fixed PRG ROM at `$8000`-`$FFFF` with a
+/// 16 KiB image mirrored into both halves, and 8 KiB of fixed CHR.
+///
+/// The [`Memory`] implementation covers the CPU address space, the CHR
+/// memory for the PPU address space is available through [`chr`].
+///
+/// [`chr`]: Nrom::chr
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Nrom {
+    prg: BankedMemory,
+    chr: BankedMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    /// Creates a new cartridge from the PRG and CHR images. An empty CHR
+    /// image gives the cartridge 8 KiB of CHR RAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the PRG image is not 16 or 32 KiB, or if the CHR image is not
+    /// empty or 8 KiB.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Nrom {
+        assert!(
+            prg.len() == 0x4000 || prg.len() == 0x8000,
+            "PRG ROM should be 16 or 32 KiB"
+        );
+        assert!(
+            chr.is_empty() || chr.len() == 0x2000,
+            "CHR ROM should be 8 KiB"
+        );
+
+        let mut prg = BankedMemory::new(prg, 0x8000, 0x4000, 2);
+        prg.select(1, 1);
+
+        Nrom {
+            prg,
+            chr: nes_chr(chr, 0x2000),
+            mirroring,
+        }
+    }
+
+    /// Returns the nametable mirroring.
+    #[inline]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the banked PRG ROM.
+    #[inline]
+    pub fn prg(&self) -> &BankedMemory {
+        &self.prg
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr(&self) -> &BankedMemory {
+        &self.chr
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr_mut(&mut self) -> &mut BankedMemory {
+        &mut self.chr
+    }
+}
+
+impl Memory for Nrom {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.prg.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.prg.set(addr, value);
+    }
+
+    #[inline]
+    fn write(&mut self, _addr: u16, _value: u8) {
+        // Do nothing since there are no registers.
+    }
+}
+
+impl MemoryState for Nrom {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.prg.save(out);
+        self.chr.save(out);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.prg.restore(&mut state);
+        self.chr.restore(&mut state);
+    }
+}
+
+/// NES mapper 2. A switchable 16 KiB PRG ROM bank at `$8000`-`$BFFF` and the
+/// last bank fixed at `$C000`-`$FFFF`. Writing to `$8000`-`$FFFF` selects the
+/// switchable bank. CHR is 8 KiB of fixed memory, usually RAM.
+///
+/// The [`Memory`] implementation covers the CPU address space, the CHR
+/// memory for the PPU address space is available through [`chr`].
+///
+/// [`chr`]: Uxrom::chr
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Uxrom {
+    prg: BankedMemory,
+    chr: BankedMemory,
+    mirroring: Mirroring,
+}
+
+impl Uxrom {
+    /// Creates a new cartridge from the PRG and CHR images. An empty CHR
+    /// image gives the cartridge 8 KiB of CHR RAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the PRG image is not a non-zero multiple of 16 KiB, or if the
+    /// CHR image is not empty or 8 KiB.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Uxrom {
+        assert!(
+            chr.is_empty() || chr.len() == 0x2000,
+            "CHR ROM should be 8 KiB"
+        );
+
+        let mut prg = BankedMemory::new(prg, 0x8000, 0x4000, 2);
+        prg.select(1, prg.bank_count() - 1);
+
+        Uxrom {
+            prg,
+            chr: nes_chr(chr, 0x2000),
+            mirroring,
+        }
+    }
+
+    /// Returns the nametable mirroring.
+    #[inline]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the banked PRG ROM.
+    #[inline]
+    pub fn prg(&self) -> &BankedMemory {
+        &self.prg
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr(&self) -> &BankedMemory {
+        &self.chr
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr_mut(&mut self) -> &mut BankedMemory {
+        &mut self.chr
+    }
+}
+
+impl Memory for Uxrom {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.prg.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.prg.set(addr, value);
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.prg.select(0, usize::from(value));
+        }
+    }
+}
+
+impl MemoryState for Uxrom {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.prg.save(out);
+        self.chr.save(out);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.prg.restore(&mut state);
+        self.chr.restore(&mut state);
+    }
+}
+
+/// NES mapper 3. Up to 32 KiB of fixed PRG ROM like [`Nrom`] and a switchable
+/// 8 KiB CHR ROM bank. Writing to `$8000`-`$FFFF` selects the CHR bank.
+///
+/// The [`Memory`] implementation covers the CPU address space, the CHR
+/// memory for the PPU address space is available through [`chr`].
+///
+/// [`chr`]: Cnrom::chr
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cnrom {
+    prg: BankedMemory,
+    chr: BankedMemory,
+    mirroring: Mirroring,
+}
+
+impl Cnrom {
+    /// Creates a new cartridge from the PRG and CHR images.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the PRG image is not 16 or 32 KiB, or if the CHR image is not
+    /// a non-zero multiple of 8 KiB.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mirroring: Mirroring) -> Cnrom {
+        assert!(
+            prg.len() == 0x4000 || prg.len() == 0x8000,
+            "PRG ROM should be 16 or 32 KiB"
+        );
+        assert!(!chr.is_empty(), "CHR ROM should not be empty");
+
+        let mut prg = BankedMemory::new(prg, 0x8000, 0x4000, 2);
+        prg.select(1, 1);
+
+        Cnrom {
+            prg,
+            chr: nes_chr(chr, 0x2000),
+            mirroring,
+        }
+    }
+
+    /// Returns the nametable mirroring.
+    #[inline]
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    /// Returns the banked PRG ROM.
+    #[inline]
+    pub fn prg(&self) -> &BankedMemory {
+        &self.prg
+    }
+
+    /// Returns the banked CHR ROM, mapped in the PPU address space.
+    #[inline]
+    pub fn chr(&self) -> &BankedMemory {
+        &self.chr
+    }
+
+    /// Returns the banked CHR ROM, mapped in the PPU address space.
+    #[inline]
+    pub fn chr_mut(&mut self) -> &mut BankedMemory {
+        &mut self.chr
+    }
+}
+
+impl Memory for Cnrom {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.prg.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.prg.set(addr, value);
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            self.chr.select(0, usize::from(value));
+        }
+    }
+}
+
+impl MemoryState for Cnrom {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.prg.save(out);
+        self.chr.save(out);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.prg.restore(&mut state);
+        self.chr.restore(&mut state);
+    }
+}
+
+/// NES mapper 1, the Nintendo MMC1.
+///
+/// Registers are loaded serially one bit at a time by writing to
+/// `$8000`-`$FFFF`, where the fifth write stores the value in the register
+/// selected by the address of that write. Writing a value with bit 7 set
+/// resets the shift register.
+///
+/// PRG ROM is switched in 16 or 32 KiB banks at `$8000`-`$FFFF` and CHR in 4
+/// or 8 KiB banks. 8 KiB of PRG RAM is mapped at `$6000`-`$7FFF`.
+///
+/// The [`Memory`] implementation covers the CPU address space, the CHR
+/// memory for the PPU address space is available through [`chr`].
+///
+/// [`chr`]: Mmc1::chr
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mmc1 {
+    prg: BankedMemory,
+    chr: BankedMemory,
+    prg_ram: BankedMemory,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    /// Creates a new cartridge from the PRG and CHR images. An empty CHR
+    /// image gives the cartridge 8 KiB of CHR RAM.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the PRG image is not a non-zero multiple of 16 KiB, or if the
+    /// CHR image is not a multiple of 4 KiB.
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>) -> Mmc1 {
+        let mut mmc1 = Mmc1 {
+            prg: BankedMemory::new(prg, 0x8000, 0x4000, 2),
+            chr: nes_chr(chr, 0x1000),
+            prg_ram: BankedMemory::ram(vec![0; 0x2000], 0x6000, 0x2000, 1),
+            shift: 0,
+            shift_count: 0,
+            // Power on with the last PRG bank fixed at $C000.
+            control: 0x0c,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        };
+        mmc1.update_banks();
+        mmc1
+    }
+
+    /// Returns the nametable mirroring currently selected.
+    pub fn mirroring(&self) -> Mirroring {
+        match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    /// Returns the banked PRG ROM.
+    #[inline]
+    pub fn prg(&self) -> &BankedMemory {
+        &self.prg
+    }
+
+    /// Returns the PRG RAM.
+    #[inline]
+    pub fn prg_ram(&self) -> &BankedMemory {
+        &self.prg_ram
+    }
+
+    /// Returns the PRG RAM.
+    #[inline]
+    pub fn prg_ram_mut(&mut self) -> &mut BankedMemory {
+        &mut self.prg_ram
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr(&self) -> &BankedMemory {
+        &self.chr
+    }
+
+    /// Returns the banked CHR memory, mapped in the PPU address space.
+    #[inline]
+    pub fn chr_mut(&mut self) -> &mut BankedMemory {
+        &mut self.chr
+    }
+
+    /// Points the PRG and CHR windows at the banks selected by the registers.
+    fn update_banks(&mut self) {
+        let prg_bank = usize::from(self.prg_bank & 0x0f);
+        match (self.control >> 2) & 0x03 {
+            0 | 1 => {
+                self.prg.select(0, prg_bank & !1);
+                self.prg.select(1, prg_bank | 1);
+            }
+            2 => {
+                self.prg.select(0, 0);
+                self.prg.select(1, prg_bank);
+            }
+            _ => {
+                self.prg.select(0, prg_bank);
+                self.prg.select(1, self.prg.bank_count() - 1);
+            }
+        }
+
+        if self.control & 0x10 == 0 {
+            let bank = usize::from(self.chr_bank0 & !1);
+            self.chr.select(0, bank);
+            self.chr.select(1, bank | 1);
+        } else {
+            self.chr.select(0, usize::from(self.chr_bank0));
+            self.chr.select(1, usize::from(self.chr_bank1));
+        }
+
+        self.prg_ram.set_writable(self.prg_bank & 0x10 == 0);
+    }
+}
+
+impl Memory for Mmc1 {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram.get(addr),
+            _ => self.prg.get(addr),
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram.set(addr, value),
+            _ => self.prg.set(addr, value),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x6000..=0x7fff => self.prg_ram.write(addr, value),
+            0x8000..=0xffff if value & 0x80 != 0 => {
+                self.shift = 0;
+                self.shift_count = 0;
+                self.control |= 0x0c;
+                self.update_banks();
+            }
+            0x8000..=0xffff => {
+                self.shift |= (value & 0x01) << self.shift_count;
+                self.shift_count += 1;
+                if self.shift_count == 5 {
+                    match addr {
+                        0x8000..=0x9fff => self.control = self.shift,
+                        0xa000..=0xbfff => self.chr_bank0 = self.shift,
+                        0xc000..=0xdfff => self.chr_bank1 = self.shift,
+                        _ => self.prg_bank = self.shift,
+                    }
+                    self.shift = 0;
+                    self.shift_count = 0;
+                    self.update_banks();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MemoryState for Mmc1 {
+    fn save_state(&self, out: &mut Vec<u8>) {
+        self.prg.save(out);
+        self.chr.save(out);
+        self.prg_ram.save(out);
+        out.extend_from_slice(&[
+            self.shift,
+            self.shift_count,
+            self.control,
+            self.chr_bank0,
+            self.chr_bank1,
+            self.prg_bank,
+        ]);
+    }
+
+    fn restore_state(&mut self, mut state: &[u8]) {
+        self.prg.restore(&mut state);
+        self.chr.restore(&mut state);
+        self.prg_ram.restore(&mut state);
+        [
+            self.shift,
+            self.shift_count,
+            self.control,
+            self.chr_bank0,
+            self.chr_bank1,
+            self.prg_bank,
+        ] = take(&mut state, 6).try_into().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns `count` banks of `size` bytes, each filled with its number.
+    fn banks(count: usize, size: usize) -> Vec<u8> {
+        (0..count).flat_map(|bank| vec![bank as u8; size]).collect()
+    }
+
+    /// Loads `value` into an MMC1 register by writing it one bit at a time.
+    fn mmc1_load(mmc1: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mmc1.write(addr, (value >> bit) & 0x01);
+        }
+    }
+
+    #[test]
+    fn banked_memory() {
+        let mut mem = BankedMemory::new(banks(4, 0x100), 0x1000, 0x100, 2);
+        assert_eq!(mem.bank_count(), 4);
+        assert_eq!(mem.get(0x0fff), None);
+        assert_eq!(mem.get(0x1200), None);
+        assert_eq!((mem.get(0x1000), mem.get(0x11ff)), (Some(0), Some(0)));
+
+        mem.select(1, 3);
+        assert_eq!(mem.get(0x1100), Some(3));
+        mem.select(0, 6);
+        assert_eq!(mem.bank(0), 2);
+        assert_eq!(mem.get(0x10ff), Some(2));
+
+        mem.write(0x1000, 0xaa);
+        assert_eq!(mem.get(0x1000), Some(2));
+        mem.set(0x1000, 0xaa);
+        assert_eq!(mem.data()[0x200], 0xaa);
+        mem.set_writable(true);
+        mem.write(0x1101, 0xbb);
+        assert_eq!(mem.data()[0x301], 0xbb);
+    }
+
+    /// Checks that accessing each hotspot of `scheme` selects the matching
+    /// 4 KiB bank.
+    fn check_atari(scheme: AtariScheme, count: usize, first_hotspot: u16) {
+        let mut cart = AtariCart::new(scheme, banks(count, 0x1000));
+        assert_eq!(cart.get(0x1000), Some(count as u8 - 1));
+
+        for bank in 0..count {
+            let hotspot = first_hotspot + bank as u16;
+            cart.read(hotspot);
+            assert_eq!(cart.get(0x1000), Some(bank as u8), "{scheme:?} read");
+            // Mirrors of the cartridge space and writes switch too.
+            cart.read(0x1000);
+            cart.write(hotspot | 0xe000, 0);
+            assert_eq!(cart.get(0xf000), Some(bank as u8), "{scheme:?} write");
+        }
+
+        // Only processor accesses switch banks.
+        cart.get(first_hotspot);
+        cart.set(first_hotspot, 0);
+        assert_eq!(cart.rom().bank(0), count - 1);
+        // Outside the cartridge space nothing is mapped or switched.
+        assert_eq!(cart.read(first_hotspot & 0x0fff), 0);
+        assert_eq!(cart.get(first_hotspot & 0x0fff), None);
+        assert_eq!(cart.rom().bank(0), count - 1);
+    }
+
+    #[test]
+    fn atari_f8_f6_f4() {
+        check_atari(AtariScheme::F8, 2, 0x1ff8);
+        check_atari(AtariScheme::F6, 4, 0x1ff6);
+        check_atari(AtariScheme::F4, 8, 0x1ff4);
+    }
+
+    #[test]
+    fn atari_read_before_switch() {
+        let mut rom = banks(2, 0x1000);
+        rom[0x1ff8] = 0x42;
+        let mut cart = AtariCart::new(AtariScheme::F8, rom);
+        assert_eq!(cart.read(0x1ff8), 0x42);
+        assert_eq!(cart.get(0x1ff8), Some(0));
+    }
+
+    #[test]
+    fn atari_e0() {
+        let mut cart = AtariCart::new(AtariScheme::E0, banks(8, 0x0400));
+        let windows = |cart: &AtariCart| [0x1000, 0x1400, 0x1800, 0x1c00].map(|a| cart.get(a));
+        assert_eq!(windows(&cart), [4, 5, 6, 7].map(Some));
+
+        cart.read(0x1fe2);
+        cart.read(0x1feb);
+        cart.write(0x1ff5, 0);
+        assert_eq!(windows(&cart), [2, 3, 5, 7].map(Some));
+
+        // The last window is fixed.
+        cart.read(0x1ff8);
+        assert_eq!(windows(&cart), [2, 3, 5, 7].map(Some));
+    }
+
+    #[test]
+    fn atari_tigervision() {
+        let mut cart = AtariCart::new(AtariScheme::Tigervision3F, banks(4, 0x0800));
+        assert_eq!((cart.get(0x1000), cart.get(0x1800)), (Some(0), Some(3)));
+
+        cart.write(0x003f, 2);
+        assert_eq!((cart.get(0x1000), cart.get(0x1800)), (Some(2), Some(3)));
+        // Reads and writes outside the hotspots do not switch.
+        cart.read(0x0000);
+        cart.write(0x0040, 1);
+        assert_eq!(cart.get(0x17ff), Some(2));
+        // Bank numbers wrap around.
+        cart.write(0x0000, 5);
+        assert_eq!(cart.get(0x1000), Some(1));
+    }
+
+    #[test]
+    fn nrom_mirrors_16k() {
+        let mut prg = banks(1, 0x4000);
+        prg[0x3ffc] = 0x12;
+        let nrom = Nrom::new(prg, Vec::new(), Mirroring::Vertical);
+        assert_eq!(nrom.get(0xfffc), Some(0x12));
+        assert_eq!(nrom.get(0xbffc), Some(0x12));
+        assert_eq!(nrom.get(0x7fff), None);
+        assert!(nrom.chr().writable());
+    }
+
+    #[test]
+    fn uxrom() {
+        let mut cart = Uxrom::new(banks(4, 0x4000), Vec::new(), Mirroring::Horizontal);
+        assert_eq!((cart.get(0x8000), cart.get(0xc000)), (Some(0), Some(3)));
+
+        cart.write(0x8000, 2);
+        assert_eq!((cart.get(0x8000), cart.get(0xc000)), (Some(2), Some(3)));
+        cart.write(0xffff, 5);
+        assert_eq!((cart.get(0xbfff), cart.get(0xffff)), (Some(1), Some(3)));
+        // Writes below the ROM do not switch.
+        cart.write(0x6000, 0);
+        assert_eq!(cart.get(0x8000), Some(1));
+        assert_eq!(cart.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn cnrom() {
+        let mut cart = Cnrom::new(banks(2, 0x4000), banks(4, 0x2000), Mirroring::Vertical);
+        assert_eq!((cart.get(0x8000), cart.get(0xc000)), (Some(0), Some(1)));
+        assert_eq!(cart.chr().get(0x0000), Some(0));
+        assert!(!cart.chr().writable());
+
+        cart.write(0x8000, 3);
+        assert_eq!(cart.chr().get(0x1fff), Some(3));
+        cart.write(0xc000, 6);
+        assert_eq!(cart.chr().get(0x0000), Some(2));
+        // PRG is fixed.
+        assert_eq!((cart.get(0x8000), cart.get(0xc000)), (Some(0), Some(1)));
+    }
+
+    #[test]
+    fn mmc1_prg_modes() {
+        let mut mmc1 = Mmc1::new(banks(8, 0x4000), banks(8, 0x1000));
+        let prg = |mmc1: &Mmc1| (mmc1.get(0x8000), mmc1.get(0xc000));
+        assert_eq!(prg(&mmc1), (Some(0), Some(7)));
+
+        // Mode 3: switch $8000, fix the last bank at $C000.
+        mmc1_load(&mut mmc1, 0xe000, 3);
+        assert_eq!(prg(&mmc1), (Some(3), Some(7)));
+
+        // Mode 2: fix the first bank at $8000, switch $C000.
+        mmc1_load(&mut mmc1, 0x8000, 0x08);
+        assert_eq!(prg(&mmc1), (Some(0), Some(3)));
+
+        // Modes 0 and 1: switch 32 KiB ignoring the low bit.
+        mmc1_load(&mut mmc1, 0x9fff, 0x04);
+        assert_eq!(prg(&mmc1), (Some(2), Some(3)));
+        mmc1_load(&mut mmc1, 0xffff, 4);
+        assert_eq!(prg(&mmc1), (Some(4), Some(5)));
+    }
+
+    #[test]
+    fn mmc1_chr_modes() {
+        let mut mmc1 = Mmc1::new(banks(2, 0x4000), banks(8, 0x1000));
+        let chr = |mmc1: &Mmc1| (mmc1.chr().get(0x0000), mmc1.chr().get(0x1000));
+
+        // 8 KiB mode ignores the low bit and the second register.
+        mmc1_load(&mut mmc1, 0xa000, 5);
+        mmc1_load(&mut mmc1, 0xc000, 1);
+        assert_eq!(chr(&mmc1), (Some(4), Some(5)));
+
+        // 4 KiB mode.
+        mmc1_load(&mut mmc1, 0x8000, 0x1c);
+        assert_eq!(chr(&mmc1), (Some(5), Some(1)));
+    }
+
+    #[test]
+    fn mmc1_shift_register() {
+        let mut mmc1 = Mmc1::new(banks(8, 0x4000), Vec::new());
+
+        // The register is selected by the address of the fifth write.
+        for value in [1, 1, 0, 0] {
+            mmc1.write(0x8000, value);
+        }
+        mmc1.write(0xe000, 0);
+        assert_eq!((mmc1.get(0x8000), mmc1.get(0xc000)), (Some(3), Some(7)));
+
+        // Setting bit 7 clears the partially loaded value and sets PRG mode 3.
+        mmc1_load(&mut mmc1, 0x8000, 0x00);
+        assert_eq!((mmc1.get(0x8000), mmc1.get(0xc000)), (Some(2), Some(3)));
+        mmc1.write(0xe000, 1);
+        mmc1.write(0xe000, 1);
+        mmc1.write(0x8000, 0x80);
+        assert_eq!((mmc1.get(0x8000), mmc1.get(0xc000)), (Some(3), Some(7)));
+        mmc1_load(&mut mmc1, 0xe000, 2);
+        assert_eq!((mmc1.get(0x8000), mmc1.get(0xc000)), (Some(2), Some(7)));
+
+        // Only bit 0 of each write is shifted in.
+        for value in [0x7f, 0x02, 0x7e, 0x02, 0x02] {
+            mmc1.write(0xe000, value);
+        }
+        assert_eq!(mmc1.get(0x8000), Some(1));
+    }
+
+    #[test]
+    fn mmc1_mirroring_and_prg_ram() {
+        let mut mmc1 = Mmc1::new(banks(2, 0x4000), Vec::new());
+        let modes = [
+            Mirroring::SingleScreenLower,
+            Mirroring::SingleScreenUpper,
+            Mirroring::Vertical,
+            Mirroring::Horizontal,
+        ];
+        for (control, mirroring) in modes.into_iter().enumerate() {
+            mmc1_load(&mut mmc1, 0x8000, 0x0c | control as u8);
+            assert_eq!(mmc1.mirroring(), mirroring);
+        }
+
+        mmc1.write(0x6000, 0x55);
+        assert_eq!(mmc1.get(0x6000), Some(0x55));
+        // Bit 4 of the PRG register disables the RAM.
+        mmc1_load(&mut mmc1, 0xe000, 0x10);
+        mmc1.write(0x6000, 0xaa);
+        assert_eq!(mmc1.get(0x6000), Some(0x55));
+    }
+
+    #[test]
+    fn state() {
+        let mut mmc1 = Mmc1::new(banks(4, 0x4000), Vec::new());
+        let mut saved = Vec::new();
+        mmc1.save_state(&mut saved);
+
+        mmc1.write(0x6000, 0x12);
+        mmc1.chr_mut().write(0x0000, 0x34);
+        mmc1_load(&mut mmc1, 0xe000, 0x12);
+        mmc1.write(0x8000, 1);
+        assert_eq!(
+            (mmc1.get(0x8000), mmc1.prg_ram().writable()),
+            (Some(2), false)
+        );
+
+        mmc1.restore_state(&saved);
+        assert_eq!((mmc1.get(0x8000), mmc1.get(0x6000)), (Some(0), Some(0)));
+        assert_eq!(mmc1.chr().get(0x0000), Some(0));
+        // The partly loaded shift register was restored as well.
+        mmc1_load(&mut mmc1, 0xe000, 0x03);
+        assert_eq!(mmc1.get(0x8000), Some(3));
+
+        // The data of RAM is saved while write-protected, including the
+        // banks that aren't mapped.
+        let mut ram = BankedMemory::ram(vec![0; 0x4000], 0x6000, 0x2000, 1);
+        ram.write(0x6000, 0x56);
+        ram.set_writable(false);
+        saved.clear();
+        ram.save_state(&mut saved);
+        ram.data_mut()[0x2000] = 0x78;
+        ram.select(0, 1);
+        ram.set_writable(true);
+        ram.write(0x6001, 0x9a);
+        ram.restore_state(&saved);
+        assert_eq!((ram.bank(0), ram.writable()), (0, false));
+        assert_eq!(ram.data()[..2], [0x56, 0]);
+        assert_eq!(ram.data()[0x2000..0x2002], [0, 0]);
+
+        // Writable ROM is saved as long as it is writable.
+        let mut rom = BankedMemory::new(vec![0; 0x1000], 0xf000, 0x1000, 1);
+        rom.set_writable(true);
+        saved.clear();
+        rom.save_state(&mut saved);
+        rom.write(0xf000, 0x12);
+        rom.restore_state(&saved);
+        assert_eq!((rom.get(0xf000), rom.is_ram()), (Some(0), false));
+
+        let mut cart = AtariCart::new(AtariScheme::E0, banks(8, 0x0400));
+        saved.clear();
+        cart.save_state(&mut saved);
+        cart.read(0x1fe0);
+        cart.restore_state(&saved);
+        assert_eq!(cart.get(0x1000), Some(4));
+    }
+}
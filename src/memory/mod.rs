@@ -1,5 +1,11 @@
 #[cfg(any(feature = "alloc", doc))]
 mod alloc;
+#[cfg(any(feature = "alloc", doc))]
+pub mod bank;
+#[cfg(any(feature = "alloc", doc))]
+pub mod overlay;
+pub mod trace;
+pub mod watch;
 
 use core::mem;
 
@@ -8,6 +14,18 @@ const _: () = if mem::size_of::<u16>() > mem::size_of::<usize>() {
     panic!("usize needs to be at least as big as u16 in size");
 };
 
+/// The kind of bus access made by the processor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Access {
+    /// A read, see [`Memory::read`].
+    Read,
+    /// A write, see [`Memory::write`].
+    Write,
+    /// An opcode fetch, see [`Memory::fetch`].
+    Execute,
+}
+
 /// A data structure where bytes can be read/written at specific addresses.
 ///
 /// There are two methods you need to implement [`get`] and [`set`]. You are
@@ -52,6 +70,16 @@ pub trait Memory {
         self.set(addr, value);
     }
 
+    /// Perform an opcode fetch from the processor. This is a regular [`read`]
+    /// made while the processor signals that the byte is the first byte of an
+    /// instruction, which can be used to tell code and data accesses apart.
+    ///
+    /// [`read`]: Memory::read
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
+
     /// Same as [`get`], but for slices.
     ///
     /// [`get`]: Memory::get
@@ -119,6 +147,21 @@ impl<M: Memory + ?Sized> Memory for &mut M {
     fn set(&mut self, addr: u16, value: u8) {
         Memory::set(*self, addr, value)
     }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        Memory::read(*self, addr)
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        Memory::write(*self, addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        Memory::fetch(*self, addr)
+    }
 }
 
 impl Memory for &[u8] {
@@ -146,3 +189,61 @@ impl Memory for &mut [u8] {
         }
     }
 }
+
+/// Arrays of exactly 65536 bytes, or larger, cover the whole address space.
+/// For those every address is in bounds, so accesses never fail and the
+/// bounds check is optimised away.
+impl<const N: usize> Memory for [u8; N] {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        if N >= 0x10000 {
+            Some(self[usize::from(addr)])
+        } else {
+            <[u8]>::get(self, usize::from(addr)).copied()
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        if N >= 0x10000 {
+            self[usize::from(addr)] = value;
+        } else if let Some(v) = <[u8]>::get_mut(self, usize::from(addr)) {
+            *v = value
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_array() {
+        let mut mem = [0u8; 0x100];
+        mem.set(0x00ff, 0x12);
+        mem.write(0x0010, 0x34);
+        assert_eq!((mem.get(0x00ff), mem.read(0x0010)), (Some(0x12), 0x34));
+
+        // Past the end nothing is mapped and changes are dropped.
+        mem.set(0x0100, 0x56);
+        mem.write(0xffff, 0x78);
+        assert_eq!((mem.get(0x0100), mem.get(0xffff)), (None, None));
+        assert_eq!((mem.read(0x0100), mem.fetch(0xffff)), (0, 0));
+        assert_eq!(mem.iter().filter(|&&b| b != 0).count(), 2);
+    }
+
+    #[test]
+    fn full_array() {
+        let mut mem = [0u8; 0x10000];
+        mem.set(0xffff, 0x12);
+        mem.write(0x0000, 0x34);
+        assert_eq!((mem.get(0xffff), mem.read(0x0000)), (Some(0x12), 0x34));
+        assert_eq!(mem.fetch(0xffff), 0x12);
+        assert_eq!((mem[0xffff], mem[0x0000]), (0x12, 0x34));
+
+        // Anything past the address space is never touched.
+        let mut mem = [0u8; 0x10001];
+        mem.set(0xffff, 0x56);
+        assert_eq!((mem.get(0xffff), mem[0x10000]), (Some(0x56), 0));
+    }
+}
@@ -0,0 +1,149 @@
+//! Copy-on-write overlay memory.
+//!
+//! [`OverlayMemory`] records every write in a sparse delta on top of an
+//! underlying memory which itself is never modified. This allows running code
+//! speculatively, e.g. to evaluate a subroutine for a debugger, and afterwards
+//! either discard or commit the changes.
+//!
+//! Requires feature `alloc`.
+
+use alloc::collections::BTreeMap;
+
+use super::Memory;
+
+extern crate alloc;
+
+/// A [`Memory`] overlay recording writes in a sparse delta on top of an
+/// underlying memory.
+///
+/// The underlying memory is only ever accessed through [`get`], so reads
+/// through the overlay never trigger any read related side effects in it.
+/// Pass a shared reference to make sure the underlying memory is left
+/// untouched, or an owned or mutable one to be able to [`commit`] the delta.
+///
+/// [`get`]: Memory::get
+/// [`commit`]: OverlayMemory::commit
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OverlayMemory<M> {
+    base: M,
+    delta: BTreeMap<u16, u8>,
+}
+
+impl<M: Memory> OverlayMemory<M> {
+    /// Returns a new overlay on top of `base` with an empty delta.
+    pub fn new(base: M) -> OverlayMemory<M> {
+        OverlayMemory {
+            base,
+            delta: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the underlying memory.
+    #[inline]
+    pub fn base(&self) -> &M {
+        &self.base
+    }
+
+    /// Returns the values written so far, by address.
+    #[inline]
+    pub fn delta(&self) -> &BTreeMap<u16, u8> {
+        &self.delta
+    }
+
+    /// Returns whether anything has been written to the overlay.
+    #[inline]
+    pub fn is_dirty(&self) -> bool {
+        !self.delta.is_empty()
+    }
+
+    /// Throws away every value written so far.
+    #[inline]
+    pub fn discard(&mut self) {
+        self.delta.clear();
+    }
+
+    /// Applies every value written so far to the underlying memory using
+    /// [`set`], leaving the delta empty.
+    ///
+    /// [`set`]: Memory::set
+    pub fn commit(&mut self) {
+        for (addr, value) in core::mem::take(&mut self.delta) {
+            self.base.set(addr, value);
+        }
+    }
+
+    /// Applies every value written so far to `target` using [`set`], leaving
+    /// the delta empty. Useful when the underlying memory is a shared
+    /// reference to `target`.
+    ///
+    /// [`set`]: Memory::set
+    pub fn commit_to<T: Memory + ?Sized>(&mut self, target: &mut T) {
+        for (addr, value) in core::mem::take(&mut self.delta) {
+            target.set(addr, value);
+        }
+    }
+
+    /// Consumes the overlay returning the underlying memory and the delta.
+    #[inline]
+    pub fn into_parts(self) -> (M, BTreeMap<u16, u8>) {
+        (self.base, self.delta)
+    }
+}
+
+impl<M: Memory> Memory for OverlayMemory<M> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        match self.delta.get(&addr) {
+            Some(&value) => Some(value),
+            None => self.base.get(addr),
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.delta.insert(addr, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_stay_in_delta() {
+        let base = [1u8; 0x100];
+        let mut overlay = OverlayMemory::new(&base);
+        assert!(!overlay.is_dirty());
+
+        overlay.write(0x10, 2);
+        overlay.set(0x11, 3);
+        assert_eq!(overlay.read(0x10), 2);
+        assert_eq!((overlay.get(0x11), overlay.get(0x12)), (Some(3), Some(1)));
+        assert_eq!(overlay.get(0x100), None);
+        assert_eq!(base[0x10], 1);
+        assert!(overlay.is_dirty());
+
+        overlay.discard();
+        assert_eq!(overlay.get(0x10), Some(1));
+
+        overlay.write(0x20, 4);
+        let mut target = [0u8; 0x100];
+        overlay.commit_to(&mut target);
+        assert_eq!((target[0x20], target[0x21]), (4, 0));
+        assert!(!overlay.is_dirty());
+    }
+
+    #[test]
+    fn commit() {
+        let mut overlay = OverlayMemory::new([0u8; 0x100]);
+        overlay.write(0xff, 5);
+        assert_eq!(overlay.base()[0xff], 0);
+        assert_eq!(overlay.delta().get(&0xff), Some(&5));
+
+        overlay.commit();
+        assert!(overlay.delta().is_empty());
+        let (base, delta) = overlay.into_parts();
+        assert_eq!((base[0xff], delta.len()), (5, 0));
+    }
+}
@@ -0,0 +1,334 @@
+//! Bus access tracing.
+//!
+//! [`TraceMemory`] wraps any other [`Memory`] and logs every processor access
+//! as a [`BusEvent`] into a [`TraceBuffer`]. A fixed-size [`RingBuffer`] is
+//! provided for `no_std` use, keeping only the latest events, while with
+//! feature `alloc` a `Vec<BusEvent>` can be used to keep every event.
+
+use super::{Access, Memory};
+
+/// A single processor access on the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BusEvent {
+    /// The cycle number the access happened on.
+    pub cycle: u64,
+    /// The address accessed.
+    pub addr: u16,
+    /// The value read or written.
+    pub value: u8,
+    /// The direction of the access.
+    pub access: Access,
+}
+
+impl BusEvent {
+    const EMPTY: BusEvent = BusEvent {
+        cycle: 0,
+        addr: 0,
+        value: 0,
+        access: Access::Read,
+    };
+}
+
+/// Storage for the events logged by a [`TraceMemory`].
+pub trait TraceBuffer {
+    /// Logs a new event.
+    fn push(&mut self, event: BusEvent);
+}
+
+impl<B: TraceBuffer + ?Sized> TraceBuffer for &mut B {
+    #[inline]
+    fn push(&mut self, event: BusEvent) {
+        B::push(self, event)
+    }
+}
+
+/// A fixed-size buffer keeping the latest `N` events, overwriting the oldest
+/// event once full.
+#[derive(Debug, Clone)]
+pub struct RingBuffer<const N: usize> {
+    events: [BusEvent; N],
+    start: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    /// Returns a new empty ring buffer.
+    pub const fn new() -> RingBuffer<N> {
+        RingBuffer {
+            events: [BusEvent::EMPTY; N],
+            start: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns the maximum number of events kept.
+    #[inline]
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of events currently kept.
+    #[inline]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether there are no events.
+    #[inline]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Removes all events.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Returns the event at `index`, counting from the oldest event kept.
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&BusEvent> {
+        if index < self.len {
+            Some(&self.events[(self.start + index) % N])
+        } else {
+            None
+        }
+    }
+
+    /// Returns the most recent event.
+    #[inline]
+    pub fn latest(&self) -> Option<&BusEvent> {
+        self.len.checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    /// Returns an iterator over the events, from the oldest to the most
+    /// recent.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &BusEvent> + ExactSizeIterator + '_ {
+        (0..self.len).map(move |i| &self.events[(self.start + i) % N])
+    }
+}
+
+impl<const N: usize> Default for RingBuffer<N> {
+    fn default() -> Self {
+        RingBuffer::new()
+    }
+}
+
+impl<const N: usize> TraceBuffer for RingBuffer<N> {
+    #[inline]
+    fn push(&mut self, event: BusEvent) {
+        if N == 0 {
+            return;
+        }
+
+        if self.len < N {
+            self.events[(self.start + self.len) % N] = event;
+            self.len += 1;
+        } else {
+            self.events[self.start] = event;
+            self.start = (self.start + 1) % N;
+        }
+    }
+}
+
+/// A [`Memory`] wrapper logging every processor access into a
+/// [`TraceBuffer`].
+///
+/// Only [`read`], [`write`] and [`fetch`] are logged since [`get`] and [`set`]
+/// do not come from the processor.
+///
+/// The 6502 accesses the bus exactly once every clock cycle, so the wrapper
+/// counts cycles by counting accesses. The counter starts at zero and can be
+/// adjusted with [`set_cycle`] to line up with an external clock.
+///
+/// [`get`]: Memory::get
+/// [`set`]: Memory::set
+/// [`read`]: Memory::read
+/// [`write`]: Memory::write
+/// [`fetch`]: Memory::fetch
+/// [`set_cycle`]: TraceMemory::set_cycle
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceMemory<M, B> {
+    inner: M,
+    buffer: B,
+    cycle: u64,
+}
+
+impl<M: Memory, B: TraceBuffer> TraceMemory<M, B> {
+    /// Returns a new wrapper around `inner` logging into `buffer`.
+    pub fn new(inner: M, buffer: B) -> TraceMemory<M, B> {
+        TraceMemory {
+            inner,
+            buffer,
+            cycle: 0,
+        }
+    }
+
+    /// Returns the wrapped memory.
+    #[inline]
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Returns the wrapped memory.
+    #[inline]
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Returns the trace buffer.
+    #[inline]
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+
+    /// Returns the trace buffer.
+    #[inline]
+    pub fn buffer_mut(&mut self) -> &mut B {
+        &mut self.buffer
+    }
+
+    /// Consumes the wrapper returning the wrapped memory and the trace buffer.
+    #[inline]
+    pub fn into_parts(self) -> (M, B) {
+        (self.inner, self.buffer)
+    }
+
+    /// Returns the cycle number the next access will be logged with.
+    #[inline]
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Sets the cycle number the next access will be logged with.
+    #[inline]
+    pub fn set_cycle(&mut self, cycle: u64) {
+        self.cycle = cycle;
+    }
+
+    #[inline]
+    fn log(&mut self, addr: u16, value: u8, access: Access) {
+        self.buffer.push(BusEvent {
+            cycle: self.cycle,
+            addr,
+            value,
+            access,
+        });
+        self.cycle += 1;
+    }
+}
+
+impl<M: Memory, B: TraceBuffer> Memory for TraceMemory<M, B> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.inner.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.inner.set(addr, value)
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        self.log(addr, value, Access::Read);
+        value
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.log(addr, value, Access::Write);
+        self.inner.write(addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        let value = self.inner.fetch(addr);
+        self.log(addr, value, Access::Execute);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(cycle: u64) -> BusEvent {
+        BusEvent {
+            cycle,
+            ..BusEvent::EMPTY
+        }
+    }
+
+    fn cycles<const N: usize>(buffer: &RingBuffer<N>) -> [u64; N] {
+        let mut cycles = [u64::MAX; N];
+        for (cycle, event) in cycles.iter_mut().zip(buffer.iter()) {
+            *cycle = event.cycle;
+        }
+        cycles
+    }
+
+    #[test]
+    fn ring_buffer() {
+        let mut buffer = RingBuffer::<3>::new();
+        assert!(buffer.is_empty());
+        assert_eq!((buffer.capacity(), buffer.latest()), (3, None));
+
+        buffer.push(event(0));
+        buffer.push(event(1));
+        assert_eq!(cycles(&buffer), [0, 1, u64::MAX]);
+        assert_eq!(buffer.latest(), Some(&event(1)));
+
+        // Once full the oldest events are overwritten.
+        for cycle in 2..5 {
+            buffer.push(event(cycle));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(cycles(&buffer), [2, 3, 4]);
+        assert_eq!(
+            (buffer.get(0), buffer.get(2), buffer.get(3)),
+            (Some(&event(2)), Some(&event(4)), None)
+        );
+        assert_eq!(buffer.iter().next_back(), Some(&event(4)));
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        buffer.push(event(5));
+        assert_eq!(cycles(&buffer), [5, u64::MAX, u64::MAX]);
+
+        // A zero sized buffer keeps nothing.
+        let mut buffer = RingBuffer::<0>::new();
+        buffer.push(event(0));
+        assert_eq!(buffer.latest(), None);
+    }
+
+    #[test]
+    fn logs_accesses() {
+        let mut mem = TraceMemory::new([0u8; 0x100], RingBuffer::<4>::new());
+        mem.set(0x10, 0x42);
+        assert_eq!(mem.get(0x10), Some(0x42));
+        assert!(mem.buffer().is_empty());
+
+        mem.set_cycle(100);
+        assert_eq!(mem.fetch(0x00), 0x00);
+        assert_eq!(mem.read(0x10), 0x42);
+        mem.write(0x11, 0x43);
+        assert_eq!(mem.cycle(), 103);
+
+        let events: [(u64, u16, u8, Access); 3] = [
+            (100, 0x00, 0x00, Access::Execute),
+            (101, 0x10, 0x42, Access::Read),
+            (102, 0x11, 0x43, Access::Write),
+        ];
+        let (inner, buffer) = mem.into_parts();
+        assert!(buffer
+            .iter()
+            .map(|e| (e.cycle, e.addr, e.value, e.access))
+            .eq(events));
+        assert_eq!(inner[0x11], 0x43);
+    }
+}
@@ -0,0 +1,312 @@
+//! Memory access watchpoints.
+//!
+//! [`WatchMemory`] wraps any other [`Memory`] and checks every processor
+//! access against a set of [`Watchpoint`]s, recording a [`WatchHit`] when one
+//! matches. The wrapped memory is accessed exactly as it would have been
+//! without the wrapper.
+
+use core::ops::RangeInclusive;
+
+use super::{Access, Memory};
+
+/// An address range together with the kinds of accesses to watch for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Watchpoint {
+    /// First address of the watched range.
+    pub start: u16,
+    /// Last address of the watched range, inclusive.
+    pub end: u16,
+    /// Whether to watch for reads, see [`Access::Read`].
+    pub read: bool,
+    /// Whether to watch for writes, see [`Access::Write`].
+    pub write: bool,
+    /// Whether to watch for opcode fetches, see [`Access::Execute`].
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    /// Returns a watchpoint matching reads from `range`.
+    pub const fn read(range: RangeInclusive<u16>) -> Watchpoint {
+        Watchpoint {
+            start: *range.start(),
+            end: *range.end(),
+            read: true,
+            write: false,
+            execute: false,
+        }
+    }
+
+    /// Returns a watchpoint matching writes to `range`.
+    pub const fn write(range: RangeInclusive<u16>) -> Watchpoint {
+        Watchpoint {
+            start: *range.start(),
+            end: *range.end(),
+            read: false,
+            write: true,
+            execute: false,
+        }
+    }
+
+    /// Returns a watchpoint matching opcode fetches from `range`.
+    pub const fn execute(range: RangeInclusive<u16>) -> Watchpoint {
+        Watchpoint {
+            start: *range.start(),
+            end: *range.end(),
+            read: false,
+            write: false,
+            execute: true,
+        }
+    }
+
+    /// Returns a watchpoint matching any access to `range`.
+    pub const fn access(range: RangeInclusive<u16>) -> Watchpoint {
+        Watchpoint {
+            start: *range.start(),
+            end: *range.end(),
+            read: true,
+            write: true,
+            execute: true,
+        }
+    }
+
+    /// Returns whether the watchpoint matches an access of kind `access` to
+    /// `addr`.
+    #[inline]
+    pub const fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        kind && self.start <= addr && addr <= self.end
+    }
+}
+
+/// A processor access that matched a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchHit {
+    /// Index of the matching watchpoint.
+    pub index: usize,
+    /// The address accessed.
+    pub addr: u16,
+    /// The value read or written.
+    pub value: u8,
+    /// The kind of access.
+    pub access: Access,
+}
+
+/// A [`Memory`] wrapper checking processor accesses against watchpoints.
+///
+/// The watchpoints can be stored in anything that can be viewed as a slice of
+/// [`Watchpoint`]s, e.g. an array, a slice or a `Vec`. Only [`read`],
+/// [`write`] and [`fetch`] are checked since [`get`] and [`set`] do not come
+/// from the processor.
+///
+/// The first hit is kept until it is taken with [`take_hit`], while
+/// [`hit_count`] counts every hit.
+///
+/// [`get`]: Memory::get
+/// [`set`]: Memory::set
+/// [`read`]: Memory::read
+/// [`write`]: Memory::write
+/// [`fetch`]: Memory::fetch
+/// [`take_hit`]: WatchMemory::take_hit
+/// [`hit_count`]: WatchMemory::hit_count
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WatchMemory<M, W> {
+    inner: M,
+    watchpoints: W,
+    hit: Option<WatchHit>,
+    hit_count: usize,
+}
+
+impl<M: Memory, W: AsRef<[Watchpoint]>> WatchMemory<M, W> {
+    /// Returns a new wrapper around `inner` watching for `watchpoints`.
+    pub fn new(inner: M, watchpoints: W) -> WatchMemory<M, W> {
+        WatchMemory {
+            inner,
+            watchpoints,
+            hit: None,
+            hit_count: 0,
+        }
+    }
+
+    /// Returns the wrapped memory.
+    #[inline]
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    /// Returns the wrapped memory.
+    #[inline]
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.inner
+    }
+
+    /// Consumes the wrapper returning the wrapped memory.
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+
+    /// Returns the watchpoints.
+    #[inline]
+    pub fn watchpoints(&self) -> &W {
+        &self.watchpoints
+    }
+
+    /// Returns the watchpoints, allowing them to be changed.
+    #[inline]
+    pub fn watchpoints_mut(&mut self) -> &mut W {
+        &mut self.watchpoints
+    }
+
+    /// Returns the first hit not yet taken, if any.
+    #[inline]
+    pub fn hit(&self) -> Option<&WatchHit> {
+        self.hit.as_ref()
+    }
+
+    /// Takes the first hit not yet taken, if any, making room to record the
+    /// next one.
+    #[inline]
+    pub fn take_hit(&mut self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    /// Returns the total number of hits since creation or the last call to
+    /// [`reset_hit_count`].
+    ///
+    /// [`reset_hit_count`]: WatchMemory::reset_hit_count
+    #[inline]
+    pub fn hit_count(&self) -> usize {
+        self.hit_count
+    }
+
+    /// Resets the number of hits to zero.
+    #[inline]
+    pub fn reset_hit_count(&mut self) {
+        self.hit_count = 0;
+    }
+
+    /// Checks an access against the watchpoints, recording a hit if one
+    /// matches.
+    #[inline]
+    fn check(&mut self, addr: u16, value: u8, access: Access) {
+        let index = self
+            .watchpoints
+            .as_ref()
+            .iter()
+            .position(|w| w.matches(addr, access));
+
+        if let Some(index) = index {
+            self.hit_count += 1;
+            self.hit.get_or_insert(WatchHit {
+                index,
+                addr,
+                value,
+                access,
+            });
+        }
+    }
+}
+
+impl<M: Memory, W: AsRef<[Watchpoint]>> Memory for WatchMemory<M, W> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.inner.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.inner.set(addr, value)
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.read(addr);
+        self.check(addr, value, Access::Read);
+        value
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.check(addr, value, Access::Write);
+        self.inner.write(addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        let value = self.inner.fetch(addr);
+        self.check(addr, value, Access::Execute);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches() {
+        let watch = Watchpoint::read(0x10..=0x1f);
+        assert!(watch.matches(0x10, Access::Read));
+        assert!(watch.matches(0x1f, Access::Read));
+        assert!(!watch.matches(0x20, Access::Read));
+        assert!(!watch.matches(0x0f, Access::Read));
+        assert!(!watch.matches(0x10, Access::Write));
+        assert!(!watch.matches(0x10, Access::Execute));
+
+        assert!(Watchpoint::write(0..=0).matches(0, Access::Write));
+        assert!(Watchpoint::execute(0..=0).matches(0, Access::Execute));
+        for access in [Access::Read, Access::Write, Access::Execute] {
+            assert!(Watchpoint::access(0x100..=0xffff).matches(0xffff, access));
+        }
+    }
+
+    #[test]
+    fn hits() {
+        let watchpoints = [Watchpoint::write(0x10..=0x10), Watchpoint::access(0..=0xff)];
+        let mut mem = WatchMemory::new([0u8; 0x100], watchpoints);
+
+        // Side effect free accesses are not checked.
+        mem.set(0x10, 0x42);
+        assert_eq!(mem.get(0x10), Some(0x42));
+        assert_eq!((mem.hit(), mem.hit_count()), (None, 0));
+
+        // The first hit is kept while every hit is counted.
+        assert_eq!(mem.read(0x10), 0x42);
+        mem.write(0x10, 0x43);
+        mem.fetch(0x20);
+        assert_eq!(mem.hit_count(), 3);
+        let first = WatchHit {
+            index: 1,
+            addr: 0x10,
+            value: 0x42,
+            access: Access::Read,
+        };
+        assert_eq!(mem.take_hit(), Some(first));
+        assert_eq!(mem.take_hit(), None);
+
+        // The first matching watchpoint is reported.
+        mem.write(0x10, 0x44);
+        assert_eq!(
+            mem.take_hit(),
+            Some(WatchHit {
+                index: 0,
+                addr: 0x10,
+                value: 0x44,
+                access: Access::Write,
+            })
+        );
+        assert_eq!(mem.inner()[0x10], 0x44);
+
+        mem.reset_hit_count();
+        mem.watchpoints_mut()[1] = Watchpoint::execute(0..=0xff);
+        mem.read(0x20);
+        assert_eq!((mem.hit(), mem.hit_count()), (None, 0));
+    }
+}
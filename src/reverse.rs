@@ -0,0 +1,362 @@
+//! Reverse execution.
+//!
+//! [`History`] owns the memory and records an undo log while running the
+//! processor forward: a [`snapshot`] of the processor before every
+//! instruction and the old value of every byte written by it. This allows
+//! stepping back one instruction at a time with [`History::step_back`], or
+//! running backwards to the previous breakpoint with
+//! [`History::reverse_continue`].
+//!
+//! Unlike [`Rewind`] this is exact to the instruction and cheap for short
+//! distances, but the log grows with every instruction so only the last
+//! `capacity` instructions are kept. Memory is restored with [`Memory::set`],
+//! so devices are not rewound.
+//!
+//! Requires feature `alloc`.
+//!
+//! [`snapshot`]: CycleTicker::snapshot
+//! [`Rewind`]: crate::rewind::Rewind
+
+use alloc::collections::VecDeque;
+
+use crate::{
+    debug::{Breakpoint, Debugger, StopReason},
+    memory::Memory,
+    CycleTicker,
+};
+
+extern crate alloc;
+
+/// What is needed to undo a single instruction.
+#[derive(Debug, Clone)]
+struct Undo {
+    cpu: [u8; CycleTicker::SNAPSHOT_LEN],
+    cycle: u64,
+    writes: usize,
+}
+
+/// Runs a processor while recording enough to run it backwards.
+#[derive(Debug, Clone)]
+pub struct History<M> {
+    mem: M,
+    capacity: usize,
+    undo: VecDeque<Undo>,
+    writes: VecDeque<(u16, u8)>,
+}
+
+impl<M: Memory> History<M> {
+    /// Returns a new history for `mem` keeping at most `capacity`
+    /// instructions.
+    pub fn new(mem: M, capacity: usize) -> History<M> {
+        History {
+            mem,
+            capacity,
+            undo: VecDeque::new(),
+            writes: VecDeque::new(),
+        }
+    }
+
+    /// Returns the memory.
+    #[inline]
+    pub fn mem(&self) -> &M {
+        &self.mem
+    }
+
+    /// Returns the memory. Changes made through it are not recorded, so they
+    /// are not undone when stepping back.
+    #[inline]
+    pub fn mem_mut(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Consumes the history returning the memory.
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.mem
+    }
+
+    /// Returns the number of instructions that can be stepped back.
+    #[inline]
+    pub fn depth(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Throws away the recorded history.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.writes.clear();
+    }
+
+    /// Runs the next instruction, see [`CycleTicker::step`], recording how
+    /// to undo it. Returns the number of cycles run.
+    pub fn step(&mut self, cpu: &mut CycleTicker) -> u32 {
+        if self.capacity == 0 {
+            return cpu.step(&mut self.mem);
+        }
+        if self.undo.len() == self.capacity {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.writes.drain(..oldest.writes);
+            }
+        }
+
+        let cpu_snapshot = cpu.snapshot();
+        let cycle = cpu.cycles();
+        let before = self.writes.len();
+        let mut mem = Recorder {
+            mem: &mut self.mem,
+            writes: &mut self.writes,
+        };
+        let cycles = cpu.step(&mut mem);
+        self.undo.push_back(Undo {
+            cpu: cpu_snapshot,
+            cycle,
+            writes: self.writes.len() - before,
+        });
+        cycles
+    }
+
+    /// Returns the processor and memory to the state before the last
+    /// recorded instruction. Returns `false` if there is nothing to undo.
+    pub fn step_back(&mut self, cpu: &mut CycleTicker) -> bool {
+        let Some(undo) = self.undo.pop_back() else {
+            return false;
+        };
+        for _ in 0..undo.writes {
+            if let Some((addr, old)) = self.writes.pop_back() {
+                self.mem.set(addr, old);
+            }
+        }
+        // Snapshots made by this version of the format always restore.
+        if let Ok(restored) = CycleTicker::from_snapshot(&undo.cpu) {
+            *cpu = restored;
+        }
+        true
+    }
+
+    /// Runs instructions forward like [`Debugger::run`], recording each one.
+    pub fn run<B: AsRef<[Breakpoint]>>(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<B>,
+        max_cycles: u64,
+    ) -> StopReason {
+        let start = cpu.cycles();
+        let check = !debugger.breakpoints().as_ref().is_empty();
+        let mut prev_cycles = start;
+        let mut first = true;
+
+        loop {
+            if cpu.is_jammed() {
+                return StopReason::Jammed;
+            }
+            if check && !first {
+                if let Some(index) = debugger.check(cpu, &self.mem, prev_cycles) {
+                    return StopReason::Breakpoint(index);
+                }
+            }
+            if cpu.cycles() - start >= max_cycles {
+                return StopReason::Limit;
+            }
+
+            first = false;
+            prev_cycles = cpu.cycles();
+            let pc = debugger.trap_pc(cpu);
+            self.step(cpu);
+            if let Some(reason) = debugger.check_trap(cpu, pc) {
+                return reason;
+            }
+        }
+    }
+
+    /// Steps back until a breakpoint of `debugger` matches, stopping where a
+    /// forward run would have stopped. Returns
+    /// [`StopReason::HistoryExhausted`] if the oldest recorded instruction is
+    /// reached first.
+    pub fn reverse_continue<B: AsRef<[Breakpoint]>>(
+        &mut self,
+        cpu: &mut CycleTicker,
+        debugger: &Debugger<B>,
+    ) -> StopReason {
+        loop {
+            if !self.step_back(cpu) {
+                return StopReason::HistoryExhausted;
+            }
+            let prev_cycles = self.undo.back().map_or(cpu.cycles(), |u| u.cycle);
+            if let Some(index) = debugger.check(cpu, &self.mem, prev_cycles) {
+                return StopReason::Breakpoint(index);
+            }
+        }
+    }
+}
+
+/// Memory logging the old value of every byte written.
+struct Recorder<'a, M> {
+    mem: &'a mut M,
+    writes: &'a mut VecDeque<(u16, u8)>,
+}
+
+impl<M: Memory> Memory for Recorder<'_, M> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.mem.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.mem.set(addr, value)
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        self.mem.read(addr)
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if let Some(old) = self.mem.get(addr) {
+            self.writes.push_back((addr, old));
+        }
+        self.mem.write(addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.mem.fetch(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::M6502;
+
+    /// Pushes and stores the numbers 1 to 5, counting at `$10`, then traps.
+    const PROGRAM: &[u8] = &[
+        0xa2, 0x00, // $0200 LDX #0
+        0xe8, // $0202 INX
+        0x8a, // $0203 TXA
+        0x48, // $0204 PHA
+        0x9d, 0x00, 0x03, // $0205 STA $0300,X
+        0xe6, 0x10, // $0208 INC $10
+        0xe0, 0x05, // $020A CPX #5
+        0xd0, 0xf4, // $020C BNE $0202
+        0x4c, 0x0e, 0x02, // $020E JMP *
+    ];
+
+    fn setup() -> (CycleTicker, History<[u8; 0x10000]>) {
+        let mut mem = [0; 0x10000];
+        mem[0x0200..0x0200 + PROGRAM.len()].copy_from_slice(PROGRAM);
+        let cpu = CycleTicker::new(M6502 {
+            pc: 0x0200,
+            sp: 0xfd,
+            ..M6502::new()
+        });
+        (cpu, History::new(mem, 100))
+    }
+
+    #[test]
+    fn step_back() {
+        let (mut cpu, mut history) = setup();
+        let mut states = Vec::new();
+        for _ in 0..40 {
+            states.push((cpu.snapshot(), *history.mem()));
+            history.step(&mut cpu);
+        }
+        assert_eq!(history.mem()[0x10], 5);
+        assert_eq!(history.depth(), 40);
+
+        while let Some((snapshot, mem)) = states.pop() {
+            assert!(history.step_back(&mut cpu));
+            assert_eq!(cpu.snapshot(), snapshot, "{} steps", states.len());
+            assert!(*history.mem() == mem, "{} steps", states.len());
+        }
+        assert!(!history.step_back(&mut cpu));
+        assert_eq!((cpu.state().pc, cpu.cycles()), (0x0200, 0));
+    }
+
+    #[test]
+    fn capacity() {
+        let (mut cpu, mut history) = setup();
+        history.capacity = 3;
+        for _ in 0..10 {
+            history.step(&mut cpu);
+        }
+        assert_eq!(history.depth(), 3);
+        let cycles = cpu.cycles();
+        assert!(history.step_back(&mut cpu));
+        assert!(history.step_back(&mut cpu));
+        assert!(history.step_back(&mut cpu));
+        assert!(!history.step_back(&mut cpu));
+        // BNE, INX and TXA.
+        assert_eq!(cpu.cycles(), cycles - 7);
+
+        history.step(&mut cpu);
+        history.clear();
+        assert_eq!(history.depth(), 0);
+
+        history.capacity = 0;
+        history.step(&mut cpu);
+        assert_eq!(history.depth(), 0);
+    }
+
+    #[test]
+    fn run_matches_debugger() {
+        let mut debugger = Debugger::new([Breakpoint::Pc(0x0208)]);
+        debugger.set_detect_traps(true);
+
+        // Runs in chunks of every size, stopping where a debugger run does.
+        for chunk in 1..=8 {
+            let (mut cpu, mut history) = setup();
+            let (mut expected_cpu, mut mem) = setup();
+            loop {
+                let expected = loop {
+                    match debugger.run(&mut expected_cpu, &mut mem.mem, 1000) {
+                        StopReason::Limit => {}
+                        reason => break reason,
+                    }
+                };
+                let reason = loop {
+                    match history.run(&mut cpu, &debugger, chunk) {
+                        StopReason::Limit => {}
+                        reason => break reason,
+                    }
+                };
+                assert_eq!(reason, expected, "chunk of {chunk} cycles");
+                assert_eq!(cpu.snapshot(), expected_cpu.snapshot());
+                if reason == StopReason::Trap(0x020e) {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_continue() {
+        let mut debugger = Debugger::new([Breakpoint::Pc(0x0208)]);
+        debugger.set_detect_traps(true);
+        let (mut cpu, mut history) = setup();
+        while history.run(&mut cpu, &debugger, 1000) != StopReason::Trap(0x020e) {}
+        assert_eq!(history.mem()[0x10], 5);
+
+        // Stops before each INC, newest first.
+        for count in (0..5).rev() {
+            assert_eq!(
+                history.reverse_continue(&mut cpu, &debugger),
+                StopReason::Breakpoint(0)
+            );
+            assert_eq!(cpu.state().pc, 0x0208);
+            assert_eq!(history.mem()[0x10], count);
+            assert_eq!(history.mem()[0x0301 + usize::from(count)], count + 1);
+            assert_eq!(history.mem()[0x0302 + usize::from(count)], 0);
+        }
+        assert_eq!(
+            history.reverse_continue(&mut cpu, &debugger),
+            StopReason::HistoryExhausted
+        );
+        assert_eq!((cpu.state().pc, cpu.cycles()), (0x0200, 0));
+        assert!(history.mem()[0x01f0..0x0200].iter().all(|&b| b == 0));
+        assert!(history.mem()[0x0300..0x0310].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,729 @@
+//! Rewinding to earlier cycles.
+//!
+//! [`Rewind`] owns the memory and drives a [`CycleTicker`], saving a
+//! [`snapshot`] of the processor together with an image of memory every N
+//! cycles into a ring buffer. Everything coming from outside the processor
+//! and memory is logged as an [`Input`]: changes to the interrupt lines and
+//! reads from memory mapped devices. Rewinding restores the nearest snapshot
+//! at or before the requested cycle and replays the inputs from there, which
+//! gives exactly the same result as the original run.
+//!
+//! Devices are marked with [`Rewind::add_device`]. Their addresses are left
+//! out of the memory images, their reads are served from the log while
+//! replaying, and writes to them are dropped while replaying so devices see
+//! every write only once. Devices are not rewound themselves.
+//!
+//! Memory images are taken with [`Memory::get`] and restored with
+//! [`Memory::set`], which is only enough for memory whose mapping never
+//! changes. Memory with state of its own that `get` does not show, e.g. the
+//! bank registers of the mappers in [`bank`], implements [`MemoryState`] and
+//! is rewound with [`Rewind::with_state`], which saves that state with every
+//! snapshot and restores it before the memory image.
+//!
+//! Requires feature `alloc`.
+//!
+//! [`snapshot`]: CycleTicker::snapshot
+//! [`bank`]: crate::memory::bank
+
+use alloc::{boxed::Box, collections::VecDeque, vec, vec::Vec};
+use core::ops::RangeInclusive;
+
+use crate::{memory::Memory, CycleTicker};
+
+extern crate alloc;
+
+/// Something coming from outside the processor and its memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Input {
+    /// The IRQ line was changed, see [`CycleTicker::set_irq`].
+    Irq(bool),
+    /// The NMI line was changed, see [`CycleTicker::set_nmi`].
+    Nmi(bool),
+    /// The reset line was pulled, see [`CycleTicker::reset`].
+    Reset,
+    /// A value was read from a device.
+    Read {
+        /// The address read.
+        addr: u16,
+        /// The value read.
+        value: u8,
+    },
+}
+
+/// An [`Input`] together with the cycle count of the processor when it
+/// happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InputEvent {
+    /// The cycle count before the input, see [`CycleTicker::cycles`].
+    pub cycle: u64,
+    /// The input.
+    pub input: Input,
+}
+
+/// State of a memory that is not visible through [`Memory::get`], such as
+/// the bank registers of a mapper or banks of RAM that are not mapped, see
+/// [`Rewind::with_state`].
+pub trait MemoryState {
+    /// Appends the state to `out`.
+    fn save_state(&self, out: &mut Vec<u8>);
+
+    /// Restores the state appended by [`save_state`].
+    ///
+    /// # Panics
+    ///
+    /// May panic if `state` was not saved by a memory of the same layout.
+    ///
+    /// [`save_state`]: MemoryState::save_state
+    fn restore_state(&mut self, state: &[u8]);
+}
+
+/// The processor and memory state at a cycle.
+#[derive(Debug, Clone)]
+struct Checkpoint {
+    cpu: [u8; CycleTicker::SNAPSHOT_LEN],
+    cycle: u64,
+    image: Box<[u8]>,
+    mapped: Box<[u64]>,
+    state: Vec<u8>,
+}
+
+/// Runs a processor while keeping enough history to rewind it.
+///
+/// Snapshots are taken every `interval` cycles and the oldest ones are thrown
+/// away once there are `capacity` of them, together with the inputs that are
+/// no longer needed.
+#[derive(Debug, Clone)]
+pub struct Rewind<M> {
+    mem: M,
+    interval: u64,
+    capacity: usize,
+    next_snapshot: u64,
+    checkpoints: VecDeque<Checkpoint>,
+    inputs: VecDeque<InputEvent>,
+    devices: Vec<RangeInclusive<u16>>,
+    save_state: Option<fn(&M, &mut Vec<u8>)>,
+    restore_state: Option<fn(&mut M, &[u8])>,
+}
+
+impl<M: Memory + MemoryState> Rewind<M> {
+    /// Like [`new`], but also saves the [`MemoryState`] of `mem` with every
+    /// snapshot.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` or `capacity` is zero.
+    ///
+    /// [`new`]: Rewind::new
+    pub fn with_state(mem: M, interval: u64, capacity: usize) -> Rewind<M> {
+        Rewind {
+            save_state: Some(M::save_state),
+            restore_state: Some(M::restore_state),
+            ..Rewind::new(mem, interval, capacity)
+        }
+    }
+}
+
+impl<M: Memory> Rewind<M> {
+    /// Returns a new rewind buffer for `mem`, taking a snapshot every
+    /// `interval` cycles and keeping at most `capacity` snapshots.
+    ///
+    /// Only the memory visible through [`Memory::get`] is saved, use
+    /// [`with_state`] for memory with a mapping that can change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` or `capacity` is zero.
+    ///
+    /// [`with_state`]: Rewind::with_state
+    pub fn new(mem: M, interval: u64, capacity: usize) -> Rewind<M> {
+        assert!(interval > 0, "snapshot interval must not be zero");
+        assert!(capacity > 0, "snapshot capacity must not be zero");
+        Rewind {
+            mem,
+            interval,
+            capacity,
+            next_snapshot: 0,
+            checkpoints: VecDeque::new(),
+            inputs: VecDeque::new(),
+            devices: Vec::new(),
+            save_state: None,
+            restore_state: None,
+        }
+    }
+
+    /// Returns the memory.
+    #[inline]
+    pub fn mem(&self) -> &M {
+        &self.mem
+    }
+
+    /// Returns the memory. Changes made through it are not logged, so they
+    /// are lost when rewinding past them.
+    #[inline]
+    pub fn mem_mut(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    /// Consumes the rewind buffer returning the memory.
+    #[inline]
+    pub fn into_inner(self) -> M {
+        self.mem
+    }
+
+    /// Marks `range` as a memory mapped device.
+    pub fn add_device(&mut self, range: RangeInclusive<u16>) {
+        self.devices.push(range);
+    }
+
+    /// Returns the address ranges of the devices.
+    #[inline]
+    pub fn devices(&self) -> &[RangeInclusive<u16>] {
+        &self.devices
+    }
+
+    /// Returns the logged inputs, oldest first.
+    #[inline]
+    pub fn inputs(&self) -> &VecDeque<InputEvent> {
+        &self.inputs
+    }
+
+    /// Returns the earliest cycle that can be rewound to, if any.
+    #[inline]
+    pub fn earliest(&self) -> Option<u64> {
+        self.checkpoints.front().map(|c| c.cycle)
+    }
+
+    /// Throws away every snapshot and input.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.inputs.clear();
+        self.next_snapshot = 0;
+    }
+
+    /// Sets the IRQ line of `cpu`, logging the change.
+    pub fn set_irq(&mut self, cpu: &mut CycleTicker, asserted: bool) {
+        self.snapshot_if_due(cpu);
+        self.log(cpu.cycles(), Input::Irq(asserted));
+        cpu.set_irq(asserted);
+    }
+
+    /// Sets the NMI line of `cpu`, logging the change.
+    pub fn set_nmi(&mut self, cpu: &mut CycleTicker, asserted: bool) {
+        self.snapshot_if_due(cpu);
+        self.log(cpu.cycles(), Input::Nmi(asserted));
+        cpu.set_nmi(asserted);
+    }
+
+    /// Pulls the reset line of `cpu`, logging it.
+    pub fn reset(&mut self, cpu: &mut CycleTicker) {
+        self.snapshot_if_due(cpu);
+        self.log(cpu.cycles(), Input::Reset);
+        cpu.reset();
+    }
+
+    /// Runs a single clock cycle, taking a snapshot first if one is due.
+    pub fn tick(&mut self, cpu: &mut CycleTicker) {
+        self.snapshot_if_due(cpu);
+        let cycle = cpu.cycles();
+        let mut mem = Recorder {
+            mem: &mut self.mem,
+            devices: &self.devices,
+            inputs: &mut self.inputs,
+            cycle,
+        };
+        cpu.tick(&mut mem);
+    }
+
+    /// Runs cycles until the current instruction has finished, see
+    /// [`CycleTicker::step`]. Returns the number of cycles run.
+    pub fn step(&mut self, cpu: &mut CycleTicker) -> u32 {
+        let start = cpu.cycles();
+        loop {
+            self.tick(cpu);
+            if cpu.at_instruction_boundary() || cpu.is_jammed() {
+                break;
+            }
+        }
+        (cpu.cycles() - start) as u32
+    }
+
+    /// Rewinds `cpu` and memory to the state they were in at `cycle`, before
+    /// any input logged at `cycle`. Any history from there on is thrown away
+    /// since running on may take a different path.
+    ///
+    /// Returns `false`, leaving everything untouched, if `cycle` is before
+    /// the earliest snapshot or after the current cycle count.
+    pub fn rewind_to(&mut self, cpu: &mut CycleTicker, cycle: u64) -> bool {
+        if cycle > cpu.cycles() {
+            return false;
+        }
+        let Some(index) = self.checkpoints.iter().rposition(|c| c.cycle <= cycle) else {
+            return false;
+        };
+        self.checkpoints.truncate(index + 1);
+        let checkpoint = &self.checkpoints[index];
+
+        let Ok(restored) = CycleTicker::from_snapshot(&checkpoint.cpu) else {
+            return false;
+        };
+        *cpu = restored;
+        if let Some(restore) = self.restore_state {
+            restore(&mut self.mem, &checkpoint.state);
+        }
+        for addr in 0..=u16::MAX {
+            let i = usize::from(addr);
+            if checkpoint.mapped[i / 64] & (1 << (i % 64)) != 0 {
+                self.mem.set(addr, checkpoint.image[i]);
+            }
+        }
+
+        let start = self.inputs.partition_point(|e| e.cycle < checkpoint.cycle);
+        let mut next = start;
+        while cpu.cycles() < cycle {
+            while let Some(event) = self.inputs.get(next) {
+                match event.input {
+                    _ if event.cycle > cpu.cycles() => break,
+                    // A read that was not made again, which only happens if
+                    // memory was changed behind the log's back.
+                    Input::Read { .. } if event.cycle < cpu.cycles() => {}
+                    Input::Read { .. } => break,
+                    Input::Irq(asserted) => cpu.set_irq(asserted),
+                    Input::Nmi(asserted) => cpu.set_nmi(asserted),
+                    Input::Reset => cpu.reset(),
+                }
+                next += 1;
+            }
+            let mut mem = Replay {
+                mem: &mut self.mem,
+                devices: &self.devices,
+                inputs: &self.inputs,
+                next: &mut next,
+                cycle: cpu.cycles(),
+            };
+            cpu.tick(&mut mem);
+        }
+
+        let end = self.inputs.partition_point(|e| e.cycle < cycle);
+        self.inputs.truncate(end);
+        self.next_snapshot = self.checkpoints[index].cycle + self.interval;
+        true
+    }
+
+    fn log(&mut self, cycle: u64, input: Input) {
+        self.inputs.push_back(InputEvent { cycle, input });
+    }
+
+    fn is_device(&self, addr: u16) -> bool {
+        is_device(&self.devices, addr)
+    }
+
+    /// Takes a snapshot if one is due. Called before any input is applied,
+    /// so a snapshot never includes the inputs of its own cycle.
+    fn snapshot_if_due(&mut self, cpu: &CycleTicker) {
+        if cpu.cycles() >= self.next_snapshot {
+            self.checkpoint(cpu);
+        }
+    }
+
+    fn checkpoint(&mut self, cpu: &CycleTicker) {
+        if self.checkpoints.len() >= self.capacity {
+            // Reuse the buffers of the oldest snapshot.
+            let mut oldest = self.checkpoints.pop_front().unwrap();
+            if let Some(front) = self.checkpoints.front() {
+                let keep = self.inputs.partition_point(|e| e.cycle < front.cycle);
+                self.inputs.drain(..keep);
+            }
+            self.fill_checkpoint(&mut oldest, cpu);
+            self.checkpoints.push_back(oldest);
+        } else {
+            let mut checkpoint = Checkpoint {
+                cpu: [0; CycleTicker::SNAPSHOT_LEN],
+                cycle: 0,
+                image: vec![0; 0x10000].into_boxed_slice(),
+                mapped: vec![0; 0x10000 / 64].into_boxed_slice(),
+                state: Vec::new(),
+            };
+            self.fill_checkpoint(&mut checkpoint, cpu);
+            self.checkpoints.push_back(checkpoint);
+        }
+        self.next_snapshot = cpu.cycles() + self.interval;
+    }
+
+    fn fill_checkpoint(&self, checkpoint: &mut Checkpoint, cpu: &CycleTicker) {
+        checkpoint.cpu = cpu.snapshot();
+        checkpoint.cycle = cpu.cycles();
+        checkpoint.mapped.fill(0);
+        checkpoint.state.clear();
+        if let Some(save) = self.save_state {
+            save(&self.mem, &mut checkpoint.state);
+        }
+        for addr in 0..=u16::MAX {
+            if self.is_device(addr) {
+                continue;
+            }
+            if let Some(value) = self.mem.get(addr) {
+                let i = usize::from(addr);
+                checkpoint.image[i] = value;
+                checkpoint.mapped[i / 64] |= 1 << (i % 64);
+            }
+        }
+    }
+}
+
+fn is_device(devices: &[RangeInclusive<u16>], addr: u16) -> bool {
+    devices.iter().any(|r| r.contains(&addr))
+}
+
+/// Memory logging every read from a device.
+struct Recorder<'a, M> {
+    mem: &'a mut M,
+    devices: &'a [RangeInclusive<u16>],
+    inputs: &'a mut VecDeque<InputEvent>,
+    cycle: u64,
+}
+
+impl<M: Memory> Memory for Recorder<'_, M> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.mem.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.mem.set(addr, value)
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = self.mem.read(addr);
+        if is_device(self.devices, addr) {
+            self.inputs.push_back(InputEvent {
+                cycle: self.cycle,
+                input: Input::Read { addr, value },
+            });
+        }
+        value
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        self.mem.write(addr, value)
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        let value = self.mem.fetch(addr);
+        if is_device(self.devices, addr) {
+            self.inputs.push_back(InputEvent {
+                cycle: self.cycle,
+                input: Input::Read { addr, value },
+            });
+        }
+        value
+    }
+}
+
+/// Memory serving reads from devices from the log.
+struct Replay<'a, M> {
+    mem: &'a mut M,
+    devices: &'a [RangeInclusive<u16>],
+    inputs: &'a VecDeque<InputEvent>,
+    next: &'a mut usize,
+    cycle: u64,
+}
+
+impl<M: Memory> Replay<'_, M> {
+    /// Returns the value logged for a read of `addr` in the current cycle.
+    fn logged(&mut self, addr: u16) -> u8 {
+        match self.inputs.get(*self.next) {
+            Some(&InputEvent {
+                cycle,
+                input:
+                    Input::Read {
+                        addr: logged,
+                        value,
+                    },
+            }) if cycle == self.cycle && logged == addr => {
+                *self.next += 1;
+                value
+            }
+            // Only happens if memory was changed behind the log's back.
+            _ => self.mem.get(addr).unwrap_or(0),
+        }
+    }
+}
+
+impl<M: Memory> Memory for Replay<'_, M> {
+    #[inline]
+    fn get(&self, addr: u16) -> Option<u8> {
+        self.mem.get(addr)
+    }
+
+    #[inline]
+    fn set(&mut self, addr: u16, value: u8) {
+        self.mem.set(addr, value)
+    }
+
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        if is_device(self.devices, addr) {
+            self.logged(addr)
+        } else {
+            self.mem.read(addr)
+        }
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        if !is_device(self.devices, addr) {
+            self.mem.write(addr, value)
+        }
+    }
+
+    #[inline]
+    fn fetch(&mut self, addr: u16) -> u8 {
+        if is_device(self.devices, addr) {
+            self.logged(addr)
+        } else {
+            self.mem.fetch(addr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{memory::bank::Mmc1, M6502};
+
+    /// RAM with a device at `$D000`-`$D0FF` returning a new value on every
+    /// read.
+    #[derive(Clone)]
+    struct Bus {
+        ram: [u8; 0x10000],
+        next: u8,
+        /// Whether `$0200`-`$02FF` is hidden from `get`, so changes made to
+        /// it survive rewinding as if made behind the log's back.
+        hide_program: bool,
+    }
+
+    impl Bus {
+        fn new(program: &[u8], irq: &[u8], nmi: &[u8]) -> Bus {
+            let mut ram = [0; 0x10000];
+            ram[0x0200..0x0200 + program.len()].copy_from_slice(program);
+            ram[0x0300..0x0300 + irq.len()].copy_from_slice(irq);
+            ram[0x0310..0x0310 + nmi.len()].copy_from_slice(nmi);
+            ram[0xfffa..0xfffc].copy_from_slice(&[0x10, 0x03]);
+            ram[0xfffe..].copy_from_slice(&[0x00, 0x03]);
+            Bus {
+                ram,
+                next: 1,
+                hide_program: false,
+            }
+        }
+    }
+
+    impl Memory for Bus {
+        fn get(&self, addr: u16) -> Option<u8> {
+            match addr {
+                0x0200..=0x02ff if self.hide_program => None,
+                0xd000..=0xd0ff => None,
+                _ => Some(self.ram[usize::from(addr)]),
+            }
+        }
+
+        fn set(&mut self, addr: u16, value: u8) {
+            self.ram[usize::from(addr)] = value;
+        }
+
+        fn read(&mut self, addr: u16) -> u8 {
+            match addr {
+                0xd000..=0xd0ff => {
+                    self.next = self.next.wrapping_add(1);
+                    self.next
+                }
+                _ => self.ram[usize::from(addr)],
+            }
+        }
+    }
+
+    fn cpu() -> CycleTicker {
+        CycleTicker::new(M6502 {
+            pc: 0x0200,
+            sp: 0xfd,
+            ..M6502::new()
+        })
+    }
+
+    /// Changes the interrupt lines for `rewind_and_replay`, at 160 on a
+    /// snapshot.
+    fn inputs(rewind: &mut Rewind<Bus>, cpu: &mut CycleTicker) {
+        match cpu.cycles() {
+            50 => rewind.set_irq(cpu, true),
+            90 => rewind.set_irq(cpu, false),
+            120 => rewind.set_nmi(cpu, true),
+            160 => rewind.set_nmi(cpu, false),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn rewind_and_replay() {
+        // Copies the device into RAM, counting interrupts at $10 and $11.
+        let program = [
+            0xa2, 0x00, // LDX #0
+            0xad, 0x00, 0xd0, // LDA $D000
+            0x9d, 0x00, 0x04, // STA $0400,X
+            0xe8, // INX
+            0x4c, 0x02, 0x02, // JMP $0202
+        ];
+        let irq = [0xe6, 0x10, 0x40]; // INC $10, RTI
+        let nmi = [0xe6, 0x11, 0x40]; // INC $11, RTI
+        let mut rewind = Rewind::new(Bus::new(&program, &irq, &nmi), 16, 100);
+        rewind.add_device(0xd000..=0xd0ff);
+        let mut cpu = cpu();
+
+        // The state at the start of every cycle, before its inputs.
+        let mut history = Vec::new();
+        for _ in 0..300 {
+            history.push((cpu.snapshot(), rewind.mem().ram[..0x500].to_vec()));
+            inputs(&mut rewind, &mut cpu);
+            rewind.tick(&mut cpu);
+        }
+        assert!(rewind.mem().ram[0x10] > 0 && rewind.mem().ram[0x11] == 1);
+        let end = (cpu.snapshot(), rewind.mem().ram[..0x500].to_vec());
+        let next = rewind.mem().next;
+
+        assert!(!rewind.rewind_to(&mut cpu, 301));
+        // Rewind to the inputs, the snapshots around them and the snapshot
+        // with an input, then run again with the same inputs.
+        for cycle in [50, 90, 120, 160, 48, 96, 112, 128, 144, 176, 0, 299] {
+            assert!(rewind.rewind_to(&mut cpu, cycle), "cycle {cycle}");
+            let (snapshot, ram) = &history[cycle as usize];
+            assert_eq!(cpu.snapshot(), *snapshot, "cycle {cycle}");
+            assert!(rewind.mem().ram[..0x500] == ram[..], "cycle {cycle}");
+            assert!(rewind.inputs().iter().all(|e| e.cycle < cycle));
+
+            // The device isn't rewound, so restart it where it was.
+            let reads = rewind.inputs().iter();
+            let reads = reads.filter(|e| matches!(e.input, Input::Read { .. }));
+            rewind.mem_mut().next = 1u8.wrapping_add(reads.count() as u8);
+            while cpu.cycles() < 300 {
+                inputs(&mut rewind, &mut cpu);
+                rewind.tick(&mut cpu);
+            }
+            assert_eq!(cpu.snapshot(), end.0, "cycle {cycle}");
+            assert!(rewind.mem().ram[..0x500] == end.1[..], "cycle {cycle}");
+            assert_eq!(rewind.mem().next, next);
+        }
+
+        for cycle in (0..300).rev().step_by(7) {
+            assert!(rewind.rewind_to(&mut cpu, cycle), "cycle {cycle}");
+            let (snapshot, ram) = &history[cycle as usize];
+            assert_eq!(cpu.snapshot(), *snapshot, "cycle {cycle}");
+            assert!(rewind.mem().ram[..0x500] == ram[..], "cycle {cycle}");
+        }
+        // Only the inputs before the cycle rewound to are kept.
+        assert!(rewind.inputs().iter().all(|e| e.cycle < 5));
+    }
+
+    #[test]
+    fn capacity() {
+        let mut rewind = Rewind::new([0xea; 0x10000], 16, 4);
+        let mut cpu = cpu();
+        assert_eq!(rewind.earliest(), None);
+        while cpu.cycles() < 200 {
+            rewind.step(&mut cpu);
+        }
+        assert_eq!(rewind.earliest(), Some(144));
+        assert!(!rewind.rewind_to(&mut cpu, 143));
+        assert!(rewind.rewind_to(&mut cpu, 150));
+        assert_eq!((cpu.cycles(), cpu.state().pc), (150, 0x0200 + 75));
+
+        rewind.clear();
+        assert_eq!(rewind.earliest(), None);
+        assert!(!rewind.rewind_to(&mut cpu, 150));
+    }
+
+    #[test]
+    fn mapper_state() {
+        // Counts in PRG RAM while loading bit 0 of the count into the MMC1
+        // PRG bank register, from the fixed bank at $C000.
+        let program = [
+            0xa2, 0x00, // LDX #0
+            0xe8, // INX
+            0x8e, 0x00, 0x60, // STX $6000
+            0x8a, // TXA
+            0x8d, 0x00, 0xe0, // STA $E000
+            0x4c, 0x02, 0xc0, // JMP $C002
+        ];
+        let mut prg: Vec<u8> = (0..4).flat_map(|bank| vec![bank; 0x4000]).collect();
+        prg[0xc000..0xc000 + program.len()].copy_from_slice(&program);
+        let mut rewind = Rewind::with_state(Mmc1::new(prg.clone(), Vec::new()), 10, 100);
+        let mut cpu = CycleTicker::new(M6502 {
+            pc: 0xc000,
+            ..M6502::new()
+        });
+
+        let mut history = Vec::new();
+        while cpu.cycles() < 400 {
+            let mem = rewind.mem();
+            history.push((
+                cpu.snapshot(),
+                mem.prg().bank(0),
+                mem.get(0x6000),
+                mem.get(0x8000),
+            ));
+            rewind.tick(&mut cpu);
+        }
+        let end = (cpu.snapshot(), rewind.mem().prg().bank(0));
+
+        for cycle in (0..400).rev().step_by(13) {
+            assert!(rewind.rewind_to(&mut cpu, cycle), "cycle {cycle}");
+            let mem = rewind.mem();
+            let state = (
+                cpu.snapshot(),
+                mem.prg().bank(0),
+                mem.get(0x6000),
+                mem.get(0x8000),
+            );
+            assert_eq!(state, history[cycle as usize], "cycle {cycle}");
+            assert!(mem.prg().data() == prg, "cycle {cycle}: ROM changed");
+        }
+
+        // The shift register is restored too, so running on gives the same
+        // result.
+        while cpu.cycles() < 400 {
+            rewind.tick(&mut cpu);
+        }
+        assert_eq!((cpu.snapshot(), rewind.mem().prg().bank(0)), end);
+    }
+
+    #[test]
+    fn replay_mismatch() {
+        let program = [
+            0xad, 0x00, 0xd0, // LDA $D000
+            0x4c, 0x00, 0x02, // JMP $0200
+        ];
+        let irq = [0xa2, 0x01, 0x4c, 0x02, 0x03]; // LDX #1, JMP *
+        let mut rewind = Rewind::new(Bus::new(&program, &irq, &[]), 1000, 10);
+        rewind.add_device(0xd000..=0xd0ff);
+        rewind.mem_mut().hide_program = true;
+        let mut cpu = cpu();
+        for cycle in 0..60 {
+            if cycle == 20 {
+                rewind.set_irq(&mut cpu, true);
+            }
+            rewind.tick(&mut cpu);
+        }
+        assert_eq!((cpu.state().a, cpu.state().x), (4, 1));
+
+        // Reading $D001 instead, the reads logged for $D000 are not handed
+        // out and do not hold up the IRQ logged after them.
+        rewind.mem_mut().set(0x0201, 0x01);
+        assert!(rewind.rewind_to(&mut cpu, 60));
+        assert_eq!((cpu.state().a, cpu.state().x), (0, 1));
+        assert_eq!(cpu.state().pc, 0x0302);
+    }
+}
@@ -0,0 +1,329 @@
+//! Symbol tables mapping labels to addresses.
+//!
+//! A [`SymbolTable`] can be loaded from the debug files written by ld65 of
+//! the cc65 suite, from VICE label files and from plain `label = $addr` maps.
+//! It implements [`Symbols`] so labels can be shown in place of addresses in
+//! disassembly and traces.
+//!
+//! Requires feature `alloc`.
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+};
+use core::fmt;
+
+use crate::disasm::Symbols;
+
+extern crate alloc;
+
+/// An error found while loading symbols.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    /// The line the error was found on, starting at 1.
+    pub line: usize,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: invalid symbol definition", self.line)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Labels by address and addresses by label.
+///
+/// An address can have several labels, in which case the first one added is
+/// the one shown in place of the address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SymbolTable {
+    by_addr: BTreeMap<u16, String>,
+    by_label: BTreeMap<String, u16>,
+}
+
+impl SymbolTable {
+    /// Returns a new empty symbol table.
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Adds a label for `addr`, replacing any earlier address of the label.
+    /// If the label was the one shown for its earlier address, another label
+    /// of that address is shown instead.
+    pub fn insert(&mut self, label: &str, addr: u16) {
+        if let Some(old) = self.by_label.insert(label.to_string(), addr) {
+            if old != addr && self.by_addr.get(&old).is_some_and(|l| l == label) {
+                self.by_addr.remove(&old);
+                if let Some((other, _)) = self.by_label.iter().find(|(_, &a)| a == old) {
+                    self.by_addr.insert(old, other.clone());
+                }
+            }
+        }
+        self.by_addr
+            .entry(addr)
+            .or_insert_with(|| label.to_string());
+    }
+
+    /// Returns the label shown for `addr`, if any.
+    #[inline]
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.by_addr.get(&addr).map(String::as_str)
+    }
+
+    /// Returns the address of `label`, if defined.
+    #[inline]
+    pub fn addr(&self, label: &str) -> Option<u16> {
+        self.by_label.get(label).copied()
+    }
+
+    /// Returns the number of labels.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.by_label.len()
+    }
+
+    /// Returns whether there are no labels.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.by_label.is_empty()
+    }
+
+    /// Returns every label and its address, ordered by label.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, u16)> {
+        self.by_label.iter().map(|(l, a)| (l.as_str(), *a))
+    }
+
+    /// Adds the labels of a ca65/ld65 debug file, as written by `ld65
+    /// --dbgfile`. Only symbols of type `lab` are added, since equates are
+    /// usually constants rather than addresses. Other lines are skipped.
+    pub fn load_ca65_dbg(&mut self, src: &str) -> Result<(), Error> {
+        for (n, line) in src.lines().enumerate() {
+            let Some(fields) = line.strip_prefix("sym\t") else {
+                continue;
+            };
+            let mut name = None;
+            let mut val = None;
+            let mut lab = false;
+            for field in split_fields(fields) {
+                match field.split_once('=') {
+                    Some(("name", v)) => {
+                        name = v.strip_prefix('"').and_then(|v| v.strip_suffix('"'))
+                    }
+                    Some(("val", v)) => val = Some(v),
+                    Some(("type", v)) => lab = v == "lab",
+                    _ => {}
+                }
+            }
+            if !lab {
+                continue;
+            }
+            let error = Error { line: n + 1 };
+            let (Some(name), Some(val)) = (name, val) else {
+                return Err(error);
+            };
+            let addr = val
+                .strip_prefix("0x")
+                .and_then(|v| u16::from_str_radix(v, 16).ok())
+                .ok_or(error)?;
+            self.insert(name, addr);
+        }
+        Ok(())
+    }
+
+    /// Adds the labels of a VICE label file, i.e. lines like `al C:c000
+    /// .reset`. The leading `.` of each label is dropped. Other lines are
+    /// skipped.
+    pub fn load_vice(&mut self, src: &str) -> Result<(), Error> {
+        for (n, line) in src.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("al") {
+                continue;
+            }
+            let error = Error { line: n + 1 };
+            let (Some(addr), Some(label)) = (words.next(), words.next()) else {
+                return Err(error);
+            };
+            let addr = addr.split_once(':').map_or(addr, |(_, a)| a);
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error.clone())?;
+            self.insert(label.strip_prefix('.').unwrap_or(label), addr);
+        }
+        Ok(())
+    }
+
+    /// Adds the labels of a plain map with one `label = addr` per line, where
+    /// the address is given as `$hex`, `0xhex` or decimal. Empty lines and
+    /// comments starting with `;` or `#` are skipped.
+    pub fn load_map(&mut self, src: &str) -> Result<(), Error> {
+        for (n, line) in src.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = Error { line: n + 1 };
+            let (label, value) = line.split_once('=').ok_or(error.clone())?;
+            let (label, value) = (label.trim(), value.trim());
+            let addr = if let Some(hex) = value.strip_prefix('$') {
+                u16::from_str_radix(hex, 16)
+            } else if let Some(hex) = value.strip_prefix("0x") {
+                u16::from_str_radix(hex, 16)
+            } else {
+                value.parse()
+            };
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                return Err(error);
+            }
+            self.insert(label, addr.map_err(|_| error)?);
+        }
+        Ok(())
+    }
+}
+
+impl Symbols for SymbolTable {
+    #[inline]
+    fn label(&self, addr: u16) -> Option<&str> {
+        SymbolTable::label(self, addr)
+    }
+}
+
+/// Splits the comma separated fields of a debug file line, keeping commas
+/// inside quotes.
+fn split_fields(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    core::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let mut quoted = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c == ',' && !quoted
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let field = &rest[..end];
+        rest = rest.get(end + 1..).unwrap_or_default();
+        Some(field)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut symbols = SymbolTable::new();
+        assert!(symbols.is_empty());
+        symbols.insert("reset", 0x8000);
+        symbols.insert("start", 0x8000);
+        symbols.insert("nmi", 0x9000);
+        assert_eq!(symbols.label(0x8000), Some("reset"));
+        assert_eq!(symbols.addr("start"), Some(0x8000));
+        assert_eq!(symbols.len(), 3);
+
+        // Inserting a label again at the same address changes nothing.
+        symbols.insert("reset", 0x8000);
+        assert_eq!(symbols.label(0x8000), Some("reset"));
+
+        // Moving the shown label shows another one of the old address.
+        symbols.insert("reset", 0xa000);
+        assert_eq!(symbols.label(0x8000), Some("start"));
+        assert_eq!(symbols.label(0xa000), Some("reset"));
+        assert_eq!(symbols.len(), 3);
+
+        // Moving a label that isn't shown leaves the old address alone.
+        symbols.insert("other", 0x9000);
+        symbols.insert("other", 0xb000);
+        assert_eq!(symbols.label(0x9000), Some("nmi"));
+        assert_eq!(symbols.label(0xb000), Some("other"));
+
+        // An address without labels left has none shown.
+        symbols.insert("nmi", 0xc000);
+        assert_eq!(symbols.label(0x9000), None);
+
+        let all: Vec<_> = symbols.iter().collect();
+        assert_eq!(
+            all,
+            [
+                ("nmi", 0xc000),
+                ("other", 0xb000),
+                ("reset", 0xa000),
+                ("start", 0x8000)
+            ]
+        );
+    }
+
+    #[test]
+    fn ca65_dbg() {
+        let src = "\
+version\tmajor=2,minor=0
+sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,ref=5,val=0x8000,seg=0,type=lab
+sym\tid=1,name=\"count\",addrsize=zeropage,scope=0,def=2,val=0x10,type=equ
+sym\tid=2,name=\"a,b\",addrsize=zeropage,scope=0,def=3,val=0x20,type=lab
+sym\tid=3,name=\"ext\",addrsize=absolute,scope=0,def=4,type=imp";
+        let mut symbols = SymbolTable::new();
+        symbols.load_ca65_dbg(src).unwrap();
+        assert_eq!(symbols.addr("reset"), Some(0x8000));
+        assert_eq!(symbols.addr("a,b"), Some(0x20));
+        assert_eq!(symbols.addr("count"), None);
+        assert_eq!(symbols.len(), 2);
+
+        for src in [
+            "sym\tid=0,val=0x8000,type=lab",
+            "sym\tid=0,name=\"x\",type=lab",
+            "sym\tid=0,name=\"x\",val=8000,type=lab",
+            "sym\tid=0,name=\"x\",val=0x10000,type=lab",
+        ] {
+            let src = alloc::format!("version\tmajor=2\n{src}");
+            assert_eq!(
+                SymbolTable::new().load_ca65_dbg(&src),
+                Err(Error { line: 2 }),
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn vice() {
+        let src = "al C:c000 .reset\nbreak c000\nal 00ff .zp\nal C:0400 screen\n";
+        let mut symbols = SymbolTable::new();
+        symbols.load_vice(src).unwrap();
+        assert_eq!(symbols.addr("reset"), Some(0xc000));
+        assert_eq!(symbols.addr("zp"), Some(0x00ff));
+        assert_eq!(symbols.addr("screen"), Some(0x0400));
+        assert_eq!(symbols.len(), 3);
+
+        for src in ["al C:c000", "al C:g000 .x", "al 10000 .x"] {
+            let src = alloc::format!("\n{src}");
+            assert_eq!(
+                SymbolTable::new().load_vice(&src),
+                Err(Error { line: 2 }),
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn map() {
+        let src = "; comment\nreset = $8000\n\n  nmi=0x9000 # comment\nirq = 40960\n";
+        let mut symbols = SymbolTable::new();
+        symbols.load_map(src).unwrap();
+        assert_eq!(symbols.addr("reset"), Some(0x8000));
+        assert_eq!(symbols.addr("nmi"), Some(0x9000));
+        assert_eq!(symbols.addr("irq"), Some(0xa000));
+
+        for src in ["reset $8000", "= $8000", "a b = 1", "x = $10000", "x = y"] {
+            let src = alloc::format!("a = 1\n{src}");
+            let error = SymbolTable::new().load_map(&src).unwrap_err();
+            assert_eq!(error, Error { line: 2 }, "{src}");
+            assert_eq!(error.to_string(), "line 2: invalid symbol definition");
+        }
+    }
+}
@@ -0,0 +1,399 @@
+//! Instruction trace logging.
+//!
+//! A [`Tracer`] writes one line per instruction, just before it is executed,
+//! to anything implementing [`core::fmt::Write`]. The line holds the program
+//! counter, the raw instruction bytes, the disassembly, the registers and the
+//! cycle count, laid out like `nestest.log` so traces can be compared line by
+//! line with other emulators:
+//!
+//! ```text
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+//! ```
+//!
+//! The PPU columns of `nestest.log` are left out since they are not part of
+//! the processor. Memory is only accessed with [`Memory::get`], so tracing
+//! never triggers any read related side effects.
+//!
+//! To make traces easier to read addresses can be replaced by labels, see
+//! [`Tracer::with_symbols`].
+
+use core::fmt::{self, Write};
+
+use crate::{
+    disasm::{Instruction, Operand, Symbols},
+    memory::Memory,
+    AddressingMode, CycleTicker, Mnemonic,
+};
+
+/// Layout of the lines written by a [`Tracer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TraceFormat {
+    /// The layout of `nestest.log`, including the effective addresses and
+    /// memory values shown after the operand, e.g. `LDA ($80,X) @ 80 = 0200
+    /// = 5A`. Illegal opcodes are marked with a `*`.
+    #[default]
+    Nestest,
+    /// A layout close to the default trace of Mesen, with the bytes prefixed
+    /// by `$` and the status flags shown as letters, uppercase when set.
+    ///
+    /// ```text
+    /// C000  $4C $F5 $C5  JMP $C5F5        A:00 X:00 Y:00 S:FD P:nv--dIzc Cycle:7
+    /// ```
+    Mesen,
+}
+
+/// Writes a trace line for every instruction.
+///
+/// Either call [`trace`] before stepping the processor yourself or let
+/// [`step`] do both.
+///
+/// [`trace`]: Tracer::trace
+/// [`step`]: Tracer::step
+#[derive(Debug, Clone)]
+pub struct Tracer<W, S = ()> {
+    out: W,
+    format: TraceFormat,
+    symbols: S,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Returns a new tracer writing lines in `format` to `out`.
+    pub fn new(out: W, format: TraceFormat) -> Tracer<W> {
+        Tracer::with_symbols(out, format, ())
+    }
+}
+
+impl<W: Write, S: Symbols> Tracer<W, S> {
+    /// Returns a new tracer writing lines in `format` to `out`, showing
+    /// labels from `symbols` in place of addresses.
+    pub fn with_symbols(out: W, format: TraceFormat, symbols: S) -> Tracer<W, S> {
+        Tracer {
+            out,
+            format,
+            symbols,
+        }
+    }
+
+    /// Returns the symbols.
+    #[inline]
+    pub fn symbols(&self) -> &S {
+        &self.symbols
+    }
+
+    /// Returns the format of the lines.
+    #[inline]
+    pub fn format(&self) -> TraceFormat {
+        self.format
+    }
+
+    /// Sets the format of the lines.
+    #[inline]
+    pub fn set_format(&mut self, format: TraceFormat) {
+        self.format = format;
+    }
+
+    /// Returns the writer.
+    #[inline]
+    pub fn writer(&self) -> &W {
+        &self.out
+    }
+
+    /// Returns the writer.
+    #[inline]
+    pub fn writer_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    /// Consumes the tracer returning the writer.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Writes the line for the instruction about to be executed, followed by
+    /// a newline.
+    pub fn trace<M: Memory + ?Sized>(&mut self, cpu: &CycleTicker, mem: &M) -> fmt::Result {
+        let instr = Instruction::decode(mem, cpu.state().pc);
+        match self.format {
+            TraceFormat::Nestest => write_nestest(&mut self.out, &instr, cpu, mem, &self.symbols)?,
+            TraceFormat::Mesen => write_mesen(&mut self.out, &instr, cpu, &self.symbols)?,
+        }
+        self.out.write_char('\n')
+    }
+
+    /// Writes the line for the next instruction and then runs it, returning
+    /// the number of cycles run.
+    pub fn step<M: Memory>(
+        &mut self,
+        cpu: &mut CycleTicker,
+        mem: &mut M,
+    ) -> Result<u32, fmt::Error> {
+        self.trace(cpu, mem)?;
+        Ok(cpu.step(mem))
+    }
+}
+
+/// A writer counting the characters written through it.
+struct Counter<W> {
+    out: W,
+    chars: usize,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.chars += s.chars().count();
+        self.out.write_str(s)
+    }
+}
+
+/// Writes whatever `f` writes, padded with spaces to `width` characters.
+fn write_padded<W: Write>(
+    out: &mut W,
+    width: usize,
+    f: impl FnOnce(&mut Counter<&mut W>) -> fmt::Result,
+) -> fmt::Result {
+    let mut counter = Counter { out, chars: 0 };
+    f(&mut counter)?;
+    for _ in counter.chars..width {
+        counter.out.write_char(' ')?;
+    }
+    Ok(())
+}
+
+fn write_bytes(out: &mut impl Write, instr: &Instruction, prefix: &str) -> fmt::Result {
+    for (i, byte) in instr.bytes()[..usize::from(instr.len)].iter().enumerate() {
+        if i > 0 {
+            out.write_char(' ')?;
+        }
+        write!(out, "{prefix}{byte:02X}")?;
+    }
+    Ok(())
+}
+
+fn write_nestest<M: Memory + ?Sized>(
+    out: &mut impl Write,
+    instr: &Instruction,
+    cpu: &CycleTicker,
+    mem: &M,
+    symbols: &impl Symbols,
+) -> fmt::Result {
+    let s = cpu.state();
+    write!(out, "{:04X}  ", instr.addr)?;
+    write_padded(out, 8, |out| write_bytes(out, instr, ""))?;
+    out.write_char(' ')?;
+    out.write_char(if instr.illegal { '*' } else { ' ' })?;
+    write_padded(out, 31, |out| {
+        write_nestest_disasm(out, instr, cpu, mem, symbols)
+    })?;
+    write!(
+        out,
+        " A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        s.a,
+        s.x,
+        s.y,
+        s.flags | 0x20,
+        s.sp,
+        cpu.cycles()
+    )
+}
+
+/// Writes the disassembly the way `nestest.log` does, showing the effective
+/// address and the value in memory there.
+fn write_nestest_disasm<M: Memory + ?Sized>(
+    out: &mut impl Write,
+    instr: &Instruction,
+    cpu: &CycleTicker,
+    mem: &M,
+    symbols: &impl Symbols,
+) -> fmt::Result {
+    let s = cpu.state();
+    let get = |addr: u16| mem.get(addr).unwrap_or(0);
+    let get_zp16 =
+        |addr: u8| u16::from_le_bytes([get(u16::from(addr)), get(u16::from(addr.wrapping_add(1)))]);
+
+    let mnemonic = match instr.mnemonic {
+        Mnemonic::ISC => "ISB",
+        m => m.as_str(),
+    };
+    let op = instr.operand;
+    let zp = Operand::new(op, 2, symbols);
+    let abs = Operand::new(op, 4, symbols);
+    match instr.mode {
+        AddressingMode::Implied => write!(out, "{mnemonic}"),
+        AddressingMode::Accumulator => write!(out, "{mnemonic} A"),
+        AddressingMode::Immediate => write!(out, "{mnemonic} #${op:02X}"),
+        AddressingMode::Relative => write!(
+            out,
+            "{mnemonic} {}",
+            Operand::new(instr.target().unwrap_or(0), 4, symbols)
+        ),
+        AddressingMode::ZeroPage => write!(out, "{mnemonic} {zp} = {:02X}", get(op)),
+        AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+            let (reg, index) = match instr.mode {
+                AddressingMode::ZeroPageX => ('X', s.x),
+                _ => ('Y', s.y),
+            };
+            let addr = (op as u8).wrapping_add(index);
+            write!(
+                out,
+                "{mnemonic} {zp},{reg} @ {addr:02X} = {:02X}",
+                get(u16::from(addr))
+            )
+        }
+        AddressingMode::Absolute => match instr.mnemonic {
+            Mnemonic::JMP | Mnemonic::JSR => write!(out, "{mnemonic} {abs}"),
+            _ => write!(out, "{mnemonic} {abs} = {:02X}", get(op)),
+        },
+        AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+            let (reg, index) = match instr.mode {
+                AddressingMode::AbsoluteX => ('X', s.x),
+                _ => ('Y', s.y),
+            };
+            let addr = op.wrapping_add(u16::from(index));
+            write!(
+                out,
+                "{mnemonic} {abs},{reg} @ {addr:04X} = {:02X}",
+                get(addr)
+            )
+        }
+        AddressingMode::Indirect => {
+            let hi_addr = (op & 0xff00) | (op.wrapping_add(1) & 0x00ff);
+            let target = u16::from_le_bytes([get(op), get(hi_addr)]);
+            write!(out, "{mnemonic} ({abs}) = {target:04X}")
+        }
+        AddressingMode::IndirectX => {
+            let ptr = (op as u8).wrapping_add(s.x);
+            let addr = get_zp16(ptr);
+            write!(
+                out,
+                "{mnemonic} ({zp},X) @ {ptr:02X} = {addr:04X} = {:02X}",
+                get(addr)
+            )
+        }
+        AddressingMode::IndirectY => {
+            let base = get_zp16(op as u8);
+            let addr = base.wrapping_add(u16::from(s.y));
+            write!(
+                out,
+                "{mnemonic} ({zp}),Y = {base:04X} @ {addr:04X} = {:02X}",
+                get(addr)
+            )
+        }
+    }
+}
+
+fn write_mesen(
+    out: &mut impl Write,
+    instr: &Instruction,
+    cpu: &CycleTicker,
+    symbols: &impl Symbols,
+) -> fmt::Result {
+    let s = cpu.state();
+    write!(out, "{:04X}  ", instr.addr)?;
+    write_padded(out, 11, |out| write_bytes(out, instr, "$"))?;
+    out.write_str("  ")?;
+    write_padded(out, 15, |out| {
+        write!(out, "{}", instr.with_symbols(symbols))
+    })?;
+    write!(
+        out,
+        "  A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:",
+        s.a, s.x, s.y, s.sp
+    )?;
+    for (i, c) in "nv--dizc".chars().enumerate() {
+        let set = s.flags & (0x80 >> i) != 0;
+        out.write_char(if set { c.to_ascii_uppercase() } else { c })?;
+    }
+    write!(out, " Cycle:{}", cpu.cycles())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec::Vec};
+
+    use super::*;
+    use crate::M6502;
+
+    extern crate alloc;
+
+    /// A single label.
+    struct Label(u16, &'static str);
+
+    impl Symbols for Label {
+        fn label(&self, addr: u16) -> Option<&str> {
+            (addr == self.0).then_some(self.1)
+        }
+    }
+
+    /// Returns a processor at `$C000` with `bytes` there, as left by the
+    /// reset sequence of `nestest`.
+    fn setup(bytes: &[u8]) -> (CycleTicker, [u8; 0x10000]) {
+        let mut mem = [0; 0x10000];
+        mem[0xc000..0xc000 + bytes.len()].copy_from_slice(bytes);
+        let mut cpu = CycleTicker::new(M6502 {
+            pc: 0xc000,
+            sp: 0xfd,
+            flags: 0x04,
+            ..M6502::new()
+        });
+        cpu.set_cycles(7);
+        (cpu, mem)
+    }
+
+    fn trace(bytes: &[u8], format: TraceFormat, symbols: impl Symbols) -> String {
+        let (cpu, mem) = setup(bytes);
+        let mut tracer = Tracer::with_symbols(String::new(), format, symbols);
+        tracer.trace(&cpu, &mem).unwrap();
+        tracer.into_inner()
+    }
+
+    #[test]
+    fn nestest() {
+        assert_eq!(
+            trace(&[0x4c, 0xf5, 0xc5], TraceFormat::Nestest, ()),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7\n"
+        );
+        assert_eq!(
+            trace(&[0xa1, 0x80], TraceFormat::Nestest, ()),
+            "C000  A1 80     LDA ($80,X) @ 80 = 0000 = 00    A:00 X:00 Y:00 P:24 SP:FD CYC:7\n"
+        );
+        assert_eq!(
+            trace(&[0xc7, 0x10], TraceFormat::Nestest, ()),
+            "C000  C7 10    *DCP $10 = 00                    A:00 X:00 Y:00 P:24 SP:FD CYC:7\n"
+        );
+    }
+
+    #[test]
+    fn mesen() {
+        assert_eq!(
+            trace(&[0xad, 0x00, 0x02], TraceFormat::Mesen, ()),
+            "C000  $AD $00 $02  LDA $0200        A:00 X:00 Y:00 S:FD P:nv--dIzc Cycle:7\n"
+        );
+    }
+
+    #[test]
+    fn long_labels() {
+        let label = "a_label_much_longer_than_any_of_the_columns_it_ends_up_in_at_all";
+        for format in [TraceFormat::Nestest, TraceFormat::Mesen] {
+            let line = trace(&[0x20, 0x00, 0x80], format, Label(0x8000, label));
+            assert!(line.contains(label), "{line}");
+            assert!(line.ends_with("CYC:7\n") || line.ends_with("Cycle:7\n"));
+        }
+    }
+
+    #[test]
+    fn step() {
+        let (mut cpu, mut mem) = setup(&[0xea, 0xe8]);
+        let mut tracer = Tracer::new(String::new(), TraceFormat::Mesen);
+        assert_eq!(tracer.step(&mut cpu, &mut mem), Ok(2));
+        tracer.set_format(TraceFormat::Nestest);
+        assert_eq!(tracer.step(&mut cpu, &mut mem), Ok(2));
+        let out = tracer.into_inner();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("C000  $EA          NOP"));
+        assert!(lines[1].starts_with("C001  E8        INX"));
+        assert_eq!(cpu.state().x, 1);
+    }
+}
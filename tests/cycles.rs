@@ -0,0 +1,148 @@
+//! Checks the number of cycles taken by every opcode against the published
+//! NMOS 6502 timing tables, including the undocumented opcodes.
+//!
+//! Each opcode is run with and without its indexing crossing a page, and
+//! branches both not taken, taken within the page and taken to another page.
+//! The `JAM` opcodes, shown as 0 in the tables, halt the processor and are
+//! skipped.
+
+use fast6502::{AddressingMode, CycleTicker, Mnemonic, Opcode, M6502};
+
+/// Base cycles of every opcode.
+#[rustfmt::skip]
+const CYCLES: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    7, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 4, 4, 6, 6, // 0
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 1
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 4, 4, 6, 6, // 2
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 3
+    6, 6, 0, 8, 3, 3, 5, 5, 3, 2, 2, 2, 3, 4, 6, 6, // 4
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 5
+    6, 6, 0, 8, 3, 3, 5, 5, 4, 2, 2, 2, 5, 4, 6, 6, // 6
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // 7
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // 8
+    2, 6, 0, 6, 4, 4, 4, 4, 2, 5, 2, 5, 5, 5, 5, 5, // 9
+    2, 6, 2, 6, 3, 3, 3, 3, 2, 2, 2, 2, 4, 4, 4, 4, // A
+    2, 5, 0, 5, 4, 4, 4, 4, 2, 4, 2, 4, 4, 4, 4, 4, // B
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // C
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // D
+    2, 6, 2, 8, 3, 3, 5, 5, 2, 2, 2, 2, 4, 4, 6, 6, // E
+    2, 5, 0, 8, 4, 4, 6, 6, 2, 4, 2, 7, 4, 4, 7, 7, // F
+];
+
+/// Extra cycles taken when indexing crosses a page, or when a taken branch
+/// goes to another page.
+#[rustfmt::skip]
+const PAGE_CROSS: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 0
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // 1
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 2
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // 3
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 4
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // 5
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 6
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // 7
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 8
+    1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // 9
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // A
+    1, 1, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 1, 1, 1, // B
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // C
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // D
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // E
+    1, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 1, 0, 0, // F
+];
+
+/// Index register value used for indexed addressing.
+const INDEX: u8 = 0x10;
+
+/// Runs `op` once, returning the number of cycles taken.
+///
+/// Indexed operands point at `$1000`, or at `$10F8` when `cross` is set so
+/// adding [`INDEX`] crosses into the next page. Branches are taken when
+/// `taken` is set, going forward to the same page or, when `cross` is set,
+/// to the next one.
+fn cycles(op: Opcode, cross: bool, taken: bool) -> u32 {
+    let mut mem = vec![0u8; 0x10000];
+    let base: u16 = if cross { 0x10f8 } else { 0x1000 };
+    let [lo, hi] = base.to_le_bytes();
+
+    let mut state = M6502 {
+        x: INDEX,
+        y: INDEX,
+        sp: 0xfd,
+        ..M6502::new()
+    };
+    let (pc, operand) = match op.mode() {
+        AddressingMode::Relative => {
+            // BPL, BVC, BCC and BNE branch on a clear flag, the others on a
+            // set one.
+            let on_set = op.to_byte() & 0x20 != 0;
+            state.flags = if on_set == taken { 0xc3 } else { 0x00 };
+            (if cross { 0x02f0 } else { 0x0200 }, [0x10, 0x00])
+        }
+        AddressingMode::IndirectX => {
+            mem[0x30] = lo;
+            mem[0x31] = hi;
+            (0x0200, [0x20, 0x00])
+        }
+        AddressingMode::IndirectY => {
+            mem[0x20] = lo;
+            mem[0x21] = hi;
+            (0x0200, [0x20, 0x00])
+        }
+        AddressingMode::ZeroPage
+        | AddressingMode::ZeroPageX
+        | AddressingMode::ZeroPageY
+        | AddressingMode::Immediate => (0x0200, [0x20, 0x00]),
+        _ => (0x0200, [lo, hi]),
+    };
+    mem[usize::from(pc)] = op.to_byte();
+    mem[usize::from(pc) + 1..usize::from(pc) + 3].copy_from_slice(&operand);
+
+    state.pc = pc;
+    let mut cpu = CycleTicker::new(state);
+    cpu.step(&mut mem)
+}
+
+#[test]
+fn cycle_counts() {
+    for op in Opcode::iter().filter(|op| op.mnemonic() != Mnemonic::JAM) {
+        let byte = op.to_byte();
+        let base = u32::from(CYCLES[usize::from(byte)]);
+        let extra = u32::from(PAGE_CROSS[usize::from(byte)]);
+        let name = format!("{byte:02X} {}", op.mnemonic().as_str());
+
+        if op.mode() == AddressingMode::Relative {
+            assert_eq!(cycles(op, false, false), base, "{name} not taken");
+            assert_eq!(cycles(op, true, false), base, "{name} not taken");
+            assert_eq!(cycles(op, false, true), base + 1, "{name} taken");
+            assert_eq!(
+                cycles(op, true, true),
+                base + 1 + extra,
+                "{name} taken to another page"
+            );
+        } else {
+            assert_eq!(cycles(op, false, false), base, "{name}");
+            assert_eq!(
+                cycles(op, true, false),
+                base + extra,
+                "{name} crossing a page"
+            );
+        }
+    }
+}
+
+#[test]
+fn opcode_info_matches_tables() {
+    for op in Opcode::iter().filter(|op| op.mnemonic() != Mnemonic::JAM) {
+        let byte = usize::from(op.to_byte());
+        let info = op.info();
+        assert_eq!(
+            (info.cycles, u8::from(info.page_cross)),
+            (CYCLES[byte], PAGE_CROSS[byte]),
+            "{byte:02X} {}: (cycles, page cross)",
+            op.mnemonic().as_str()
+        );
+    }
+}
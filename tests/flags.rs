@@ -0,0 +1,268 @@
+//! Exhaustive tests of the flags set by the arithmetic and logic
+//! instructions, checked against expectations derived from the arithmetic
+//! for every operand and carry in and in both binary and decimal mode.
+//!
+//! The NMOS 6502 sets N, V and Z oddly in decimal mode. Those expectations
+//! follow appendix A of Bruce Clark's "Decimal Mode" tutorial, which also
+//! covers operands that are not valid BCD.
+
+use fast6502::{CycleTicker, M6502};
+
+/// Zero page address of the memory operand.
+const OPERAND: u8 = 0x10;
+
+/// Runs the instruction `bytes` at `$0200` with `state`, and with `value`
+/// stored at the zero page address [`OPERAND`]. Returns the state after and
+/// the value at [`OPERAND`].
+fn run(bytes: &[u8], state: M6502, value: u8) -> (M6502, u8) {
+    let mut mem = [0; 0x300];
+    mem[usize::from(OPERAND)] = value;
+    mem[0x200..0x200 + bytes.len()].copy_from_slice(bytes);
+    let mut cpu = CycleTicker::new(M6502 { pc: 0x200, ..state });
+    cpu.step(&mut mem);
+    (cpu.into_state(), mem[usize::from(OPERAND)])
+}
+
+/// Returns a state with the accumulator, carry and decimal flags set, and
+/// the overflow flag set to `v` so tests can tell if it is left alone.
+fn state(a: u8, c: bool, d: bool, v: bool) -> M6502 {
+    let mut state = M6502 { a, ..M6502::new() };
+    state.set_flag_c(c);
+    state.set_flag_d(d);
+    state.set_flag_v(v);
+    state
+}
+
+/// Calls `f` for every accumulator, operand and carry in.
+fn for_all(mut f: impl FnMut(u8, u8, bool)) {
+    for a in 0..=255 {
+        for m in 0..=255 {
+            f(a, m, false);
+            f(a, m, true);
+        }
+    }
+}
+
+/// Returns the value of a BCD byte, or `None` if it is not valid BCD.
+fn from_bcd(value: u8) -> Option<u8> {
+    let (hi, lo) = (value >> 4, value & 0x0f);
+    (hi < 10 && lo < 10).then_some(hi * 10 + lo)
+}
+
+fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+/// Checks the flags and accumulator after an instruction.
+#[track_caller]
+fn check(name: &str, after: &M6502, a: u8, n: bool, v: bool, z: bool, c: bool) {
+    let actual = (
+        after.a,
+        after.flag_n(),
+        after.flag_v(),
+        after.flag_z(),
+        after.flag_c(),
+    );
+    assert_eq!(actual, (a, n, v, z, c), "{name}: (a, n, v, z, c)");
+}
+
+#[test]
+fn adc_binary() {
+    for_all(|a, m, c| {
+        let (after, _) = run(&[0x69, m], state(a, c, false, false), 0);
+        let sum = u16::from(a) + u16::from(m) + u16::from(c);
+        let signed = i16::from(a as i8) + i16::from(m as i8) + i16::from(c);
+        let result = sum as u8;
+        check(
+            &format!("{a:02X} + {m:02X} + {}", u8::from(c)),
+            &after,
+            result,
+            result & 0x80 != 0,
+            !(-128..=127).contains(&signed),
+            result == 0,
+            sum > 0xff,
+        );
+    });
+}
+
+#[test]
+fn sbc_binary() {
+    for_all(|a, m, c| {
+        let (after, _) = run(&[0xe9, m], state(a, c, false, false), 0);
+        let borrow = i16::from(!c);
+        let diff = i16::from(a) - i16::from(m) - borrow;
+        let signed = i16::from(a as i8) - i16::from(m as i8) - borrow;
+        let result = diff as u8;
+        check(
+            &format!("{a:02X} - {m:02X} - {borrow}"),
+            &after,
+            result,
+            result & 0x80 != 0,
+            !(-128..=127).contains(&signed),
+            result == 0,
+            diff >= 0,
+        );
+    });
+}
+
+#[test]
+fn adc_decimal() {
+    for_all(|a, m, c| {
+        let (after, _) = run(&[0x69, m], state(a, c, true, false), 0);
+        let c = i16::from(c);
+
+        let mut lo = i16::from(a & 0x0f) + i16::from(m & 0x0f) + c;
+        if lo >= 0x0a {
+            lo = ((lo + 0x06) & 0x0f) + 0x10;
+        }
+        let mut sum = i16::from(a & 0xf0) + i16::from(m & 0xf0) + lo;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        // N and V come from the sum before the high digit is adjusted, and
+        // Z from the binary sum.
+        let signed = i16::from((a & 0xf0) as i8) + i16::from((m & 0xf0) as i8) + lo;
+        let binary = i16::from(a) + i16::from(m) + c;
+
+        check(
+            &format!("{a:02X} + {m:02X} + {c}"),
+            &after,
+            sum as u8,
+            signed & 0x80 != 0,
+            !(-128..=127).contains(&signed),
+            binary & 0xff == 0,
+            sum >= 0x100,
+        );
+
+        if let (Some(x), Some(y)) = (from_bcd(a), from_bcd(m)) {
+            let total = x + y + c as u8;
+            assert_eq!(
+                (after.a, after.flag_c()),
+                (to_bcd(total % 100), total >= 100),
+                "{a:02X} + {m:02X} + {c}: BCD sum"
+            );
+        }
+    });
+}
+
+#[test]
+fn sbc_decimal() {
+    for_all(|a, m, c| {
+        let (after, _) = run(&[0xe9, m], state(a, c, true, false), 0);
+        let borrow = i16::from(!c);
+
+        let mut lo = i16::from(a & 0x0f) - i16::from(m & 0x0f) - borrow;
+        if lo < 0 {
+            lo = ((lo - 0x06) & 0x0f) - 0x10;
+        }
+        let mut diff = i16::from(a & 0xf0) - i16::from(m & 0xf0) + lo;
+        if diff < 0 {
+            diff -= 0x60;
+        }
+        // The flags are those of the binary subtraction.
+        let binary = i16::from(a) - i16::from(m) - borrow;
+        let signed = i16::from(a as i8) - i16::from(m as i8) - borrow;
+
+        check(
+            &format!("{a:02X} - {m:02X} - {borrow}"),
+            &after,
+            diff as u8,
+            binary & 0x80 != 0,
+            !(-128..=127).contains(&signed),
+            binary & 0xff == 0,
+            binary >= 0,
+        );
+
+        if let (Some(x), Some(y)) = (from_bcd(a), from_bcd(m)) {
+            let total = i16::from(x) - i16::from(y) - borrow;
+            assert_eq!(
+                (after.a, after.flag_c()),
+                (to_bcd(total.rem_euclid(100) as u8), total >= 0),
+                "{a:02X} - {m:02X} - {borrow}: BCD difference"
+            );
+        }
+    });
+}
+
+#[test]
+fn compare() {
+    // CMP, CPX and CPY, immediate and zero page.
+    let ops = [(0xc9, 0xc5), (0xe0, 0xe4), (0xc0, 0xc4)];
+    for (reg, (imm, zp)) in ops.into_iter().enumerate() {
+        for d in [false, true] {
+            for_all(|r, m, v| {
+                let mut before = state(0x5a, false, d, v);
+                match reg {
+                    0 => before.a = r,
+                    1 => before.x = r,
+                    _ => before.y = r,
+                }
+                let name = format!("{imm:02X} {r:02X} {m:02X}, D = {d}");
+                let diff = r.wrapping_sub(m);
+                for (after, _) in [run(&[imm, m], before, 0), run(&[zp, OPERAND], before, m)] {
+                    assert_eq!(
+                        (
+                            after.flag_n(),
+                            after.flag_v(),
+                            after.flag_z(),
+                            after.flag_c()
+                        ),
+                        (diff & 0x80 != 0, v, r == m, r >= m),
+                        "{name}: (n, v, z, c)"
+                    );
+                    assert_eq!((after.a, after.x, after.y), (before.a, before.x, before.y));
+                }
+            });
+        }
+    }
+}
+
+#[test]
+fn bit() {
+    for d in [false, true] {
+        for_all(|a, m, c| {
+            for bytes in [&[0x24, OPERAND][..], &[0x2c, OPERAND, 0x00]] {
+                let (after, _) = run(bytes, state(a, c, d, false), m);
+                check(
+                    &format!("BIT {a:02X} & {m:02X}, D = {d}"),
+                    &after,
+                    a,
+                    m & 0x80 != 0,
+                    m & 0x40 != 0,
+                    a & m == 0,
+                    c,
+                );
+            }
+        });
+    }
+}
+
+#[test]
+fn shifts_and_rotates() {
+    // ASL, ROL, LSR and ROR: accumulator and zero page opcodes.
+    let ops = [(0x0a, 0x06), (0x2a, 0x26), (0x4a, 0x46), (0x6a, 0x66)];
+    for (kind, (acc, zp)) in ops.into_iter().enumerate() {
+        for d in [false, true] {
+            for value in 0..=255u8 {
+                for (c, v) in [(false, false), (false, true), (true, false), (true, true)] {
+                    let carry = u8::from(c);
+                    let (result, carry_out) = match kind {
+                        0 => (value << 1, value & 0x80 != 0),
+                        1 => (value << 1 | carry, value & 0x80 != 0),
+                        2 => (value >> 1, value & 0x01 != 0),
+                        _ => (value >> 1 | carry << 7, value & 0x01 != 0),
+                    };
+                    let name = format!("{acc:02X} {value:02X}, C = {c}, D = {d}");
+                    let n = result & 0x80 != 0;
+
+                    let (after, _) = run(&[acc], state(value, c, d, v), 0);
+                    check(&name, &after, result, n, v, result == 0, carry_out);
+
+                    let (after, mem) = run(&[zp, OPERAND], state(0x5a, c, d, v), value);
+                    check(&name, &after, 0x5a, n, v, result == 0, carry_out);
+                    assert_eq!(mem, result, "{name}: memory");
+                }
+            }
+        }
+    }
+}
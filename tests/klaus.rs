@@ -0,0 +1,168 @@
+//! Runs Klaus Dormann's 6502 test suites, see
+//! <https://github.com/Klaus2m5/6502_65C02_functional_tests>.
+//!
+//! The binaries are not part of the repository, so the tests are ignored by
+//! default. Point the `KLAUS_TESTS` environment variable at a directory
+//! holding `6502_functional_test.bin`, `6502_decimal_test.bin` and
+//! `6502_interrupt_test.bin`, each a flat image loaded at `$0000` and built
+//! with the default configuration of its source, and run them with
+//! `cargo test --release --test klaus -- --ignored`. A suite fails if
+//! `KLAUS_TESTS` is not set or its binary is missing.
+//!
+//! Only the functional test is shipped prebuilt, in `bin_files`. The other
+//! two have to be assembled from their sources with `as65 -l -m -w -h0`.
+//! Decimal mode is also checked exhaustively by `tests/flags.rs`, which
+//! needs no binaries.
+//!
+//! All suites report their result by trapping, i.e. jumping or branching to
+//! themselves. The address of the success trap depends on how a suite was
+//! assembled, so it can be overridden with `KLAUS_FUNCTIONAL_SUCCESS`,
+//! `KLAUS_DECIMAL_SUCCESS` and `KLAUS_INTERRUPT_SUCCESS`, given in hex. The
+//! functional test defaults to the address of the prebuilt binary and the
+//! decimal test to checking its error byte, while the interrupt test needs
+//! the address to be given, e.g. from the `jmp *` marked "test passed" in
+//! its listing.
+//!
+//! `65C02_extended_opcodes_test` is not run since only the NMOS 6502 is
+//! emulated.
+
+use std::{env, fs, path::PathBuf};
+
+use fast6502::{
+    debug::{Debugger, StopReason},
+    memory::Memory,
+    CycleTicker, M6502,
+};
+
+/// Cycles after which a suite is considered stuck. The functional test,
+/// the longest one, takes a little under 100 million.
+const MAX_CYCLES: u64 = 200_000_000;
+
+/// Feedback port of the interrupt test. Bit 0 drives the IRQ line and bit 1
+/// the NMI line, a set bit asserting it.
+const INTERRUPT_PORT: u16 = 0xbffc;
+
+/// How a suite tells that it passed.
+#[derive(Debug, Clone, Copy)]
+enum Success {
+    /// Trapped at this address.
+    Pc(u16),
+    /// Trapped with zero in the error byte at this address.
+    Zero(u16),
+}
+
+struct Suite {
+    file: &'static str,
+    var: &'static str,
+    start: u16,
+    /// How the suite passes unless overridden by `var`.
+    success: Option<Success>,
+}
+
+/// Memory with the interrupt feedback port of the interrupt test.
+struct Ram {
+    data: Vec<u8>,
+    port: bool,
+}
+
+impl Memory for Ram {
+    fn get(&self, addr: u16) -> Option<u8> {
+        Some(self.data[usize::from(addr)])
+    }
+
+    fn set(&mut self, addr: u16, value: u8) {
+        self.data[usize::from(addr)] = value;
+    }
+}
+
+/// Runs `suite` returning the address of the trap it stopped at.
+fn run(suite: &Suite) -> (u16, Ram) {
+    let dir = env::var_os("KLAUS_TESTS").expect("KLAUS_TESTS is not set");
+    let path = PathBuf::from(dir).join(suite.file);
+    let image = fs::read(&path).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+    assert!(image.len() <= 0x10000, "{} is too large", suite.file);
+
+    let mut data = vec![0; 0x10000];
+    data[..image.len()].copy_from_slice(&image);
+    let mut mem = Ram {
+        data,
+        port: suite.file == "6502_interrupt_test.bin",
+    };
+    if mem.port {
+        mem.set(INTERRUPT_PORT, 0);
+    }
+
+    let mut cpu = CycleTicker::new(M6502 {
+        pc: suite.start,
+        ..M6502::new()
+    });
+    let mut debugger = Debugger::new([]);
+    debugger.set_detect_traps(true);
+    loop {
+        // The interrupt lines are only polled before the last cycle of an
+        // instruction, so updating them between instructions is enough.
+        let max_cycles = if mem.port { 1 } else { MAX_CYCLES };
+        match debugger.run(&mut cpu, &mut mem, max_cycles) {
+            StopReason::Trap(pc) => return (pc, mem),
+            StopReason::Limit if cpu.cycles() < MAX_CYCLES => {
+                let port = mem.get(INTERRUPT_PORT).unwrap_or(0);
+                cpu.set_irq(port & 0x01 != 0);
+                cpu.set_nmi(port & 0x02 != 0);
+            }
+            reason => panic!(
+                "{} stopped at ${:04X} after {} cycles: {reason:?}",
+                suite.file,
+                cpu.state().pc,
+                cpu.cycles()
+            ),
+        }
+    }
+}
+
+fn check(suite: Suite) {
+    let success = match env::var(suite.var) {
+        Ok(addr) => Success::Pc(u16::from_str_radix(&addr, 16).expect("invalid success address")),
+        Err(_) => suite
+            .success
+            .unwrap_or_else(|| panic!("{} needs the success address in {}", suite.file, suite.var)),
+    };
+    let (pc, mem) = run(&suite);
+    let passed = match success {
+        Success::Pc(addr) => pc == addr,
+        Success::Zero(addr) => mem.get(addr) == Some(0),
+    };
+    assert!(passed, "{} failed, trapped at ${pc:04X}", suite.file);
+}
+
+#[test]
+#[ignore = "needs the binaries in KLAUS_TESTS"]
+fn functional() {
+    check(Suite {
+        file: "6502_functional_test.bin",
+        var: "KLAUS_FUNCTIONAL_SUCCESS",
+        start: 0x0400,
+        success: Some(Success::Pc(0x3469)),
+    });
+}
+
+#[test]
+#[ignore = "needs the binaries in KLAUS_TESTS"]
+fn decimal() {
+    check(Suite {
+        file: "6502_decimal_test.bin",
+        var: "KLAUS_DECIMAL_SUCCESS",
+        start: 0x0200,
+        success: Some(Success::Zero(0x000b)),
+    });
+}
+
+#[test]
+#[ignore = "needs the binaries in KLAUS_TESTS"]
+fn interrupt() {
+    check(Suite {
+        file: "6502_interrupt_test.bin",
+        var: "KLAUS_INTERRUPT_SUCCESS",
+        start: 0x0400,
+        success: None,
+    });
+}
@@ -0,0 +1,664 @@
+//! A simple instruction-level 6502 interpreter used as the reference model
+//! when differential testing the cycle-level core, see `tests/differential.rs`
+//! and the `differential` fuzz target.
+//!
+//! The model is written independently of the core: instructions are decoded
+//! from the bit fields of the opcode, executed in one go and their cycle
+//! count is derived from the addressing mode. Decimal mode follows Bruce
+//! Clark's "Decimal Mode" tutorial. `JAM` and the unstable illegal opcodes
+//! `ANE`, `LXA`, `SHA`, `SHX`, `SHY` and `TAS` are not modelled, and neither
+//! are interrupts.
+
+use fast6502::{CycleTicker, M6502};
+
+const C: u8 = 0x01;
+const Z: u8 = 0x02;
+const I: u8 = 0x04;
+const D: u8 = 0x08;
+const V: u8 = 0x40;
+const N: u8 = 0x80;
+
+/// Bits of the status register only seen on the stack.
+const STACK_ONLY: u8 = 0x30;
+
+/// Returns whether the reference model implements `op`.
+pub fn is_supported(op: u8) -> bool {
+    !matches!(
+        op,
+        0x02 | 0x12
+            | 0x22
+            | 0x32
+            | 0x42
+            | 0x52
+            | 0x62
+            | 0x72
+            | 0x92
+            | 0xb2
+            | 0xd2
+            | 0xf2
+            | 0x8b
+            | 0xab
+            | 0x93
+            | 0x9f
+            | 0x9e
+            | 0x9c
+            | 0x9b
+    )
+}
+
+/// A SplitMix64 random number generator.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Read,
+    Write,
+    Modify,
+}
+
+/// Decodes the addressing mode from the `bbb` and `cc` fields of `aaabbbcc`.
+fn mode(op: u8) -> Mode {
+    let (aaa, bbb, cc) = (op >> 5, (op >> 2) & 7, op & 3);
+    // STX, LDX, SAX and LAX index with Y instead of X.
+    let y_indexed = cc >= 2 && (aaa == 4 || aaa == 5);
+    match (bbb, cc) {
+        (0, 1 | 3) => Mode::IndirectX,
+        (0, _) if op == 0x20 => Mode::Absolute,
+        (0, _) if op >= 0x80 => Mode::Immediate,
+        (0, _) => Mode::Implied,
+        (1, _) => Mode::ZeroPage,
+        (2, 1 | 3) => Mode::Immediate,
+        (2, 2) if op < 0x80 => Mode::Accumulator,
+        (2, _) => Mode::Implied,
+        (3, _) if op == 0x6c => Mode::Indirect,
+        (3, _) => Mode::Absolute,
+        (4, 0) => Mode::Relative,
+        (4, _) => Mode::IndirectY,
+        (5, _) if y_indexed => Mode::ZeroPageY,
+        (5, _) => Mode::ZeroPageX,
+        (6, 1 | 3) => Mode::AbsoluteY,
+        (6, _) => Mode::Implied,
+        (_, _) if y_indexed => Mode::AbsoluteY,
+        (_, _) => Mode::AbsoluteX,
+    }
+}
+
+/// Returns how an instruction accessing memory uses its operand.
+fn kind(op: u8) -> Kind {
+    match (op >> 5, op & 3) {
+        (4, _) => Kind::Write,
+        (0..=3 | 6 | 7, 2 | 3) => Kind::Modify,
+        _ => Kind::Read,
+    }
+}
+
+/// Returns the cycles taken by an instruction that is neither a branch nor
+/// one of the jump, stack and interrupt instructions.
+fn cycles(mode: Mode, kind: Kind, crossed: bool) -> u32 {
+    let crossed = u32::from(crossed);
+    match (mode, kind) {
+        (Mode::Implied | Mode::Accumulator | Mode::Immediate, _) => 2,
+        (Mode::ZeroPage, Kind::Modify) => 5,
+        (Mode::ZeroPage, _) => 3,
+        (Mode::ZeroPageX | Mode::ZeroPageY, Kind::Modify) => 6,
+        (Mode::ZeroPageX | Mode::ZeroPageY, _) => 4,
+        (Mode::Absolute, Kind::Modify) => 6,
+        (Mode::Absolute, _) => 4,
+        (Mode::AbsoluteX | Mode::AbsoluteY, Kind::Modify) => 7,
+        (Mode::AbsoluteX | Mode::AbsoluteY, Kind::Write) => 5,
+        (Mode::AbsoluteX | Mode::AbsoluteY, Kind::Read) => 4 + crossed,
+        (Mode::IndirectX, Kind::Modify) => 8,
+        (Mode::IndirectX, _) => 6,
+        (Mode::IndirectY, Kind::Modify) => 8,
+        (Mode::IndirectY, Kind::Write) => 6,
+        (Mode::IndirectY, Kind::Read) => 5 + crossed,
+        (Mode::Indirect | Mode::Relative, _) => unreachable!("handled by the instruction"),
+    }
+}
+
+/// The reference processor, owning its memory.
+pub struct Reference {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub p: u8,
+    pub pc: u16,
+    pub mem: Vec<u8>,
+}
+
+impl Reference {
+    fn read(&self, addr: u16) -> u8 {
+        self.mem[usize::from(addr)]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.mem[usize::from(addr)] = value;
+    }
+
+    fn read16(&self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+    }
+
+    fn read16_zp(&self, addr: u8) -> u16 {
+        u16::from_le_bytes([
+            self.read(u16::from(addr)),
+            self.read(u16::from(addr.wrapping_add(1))),
+        ])
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x100 | u16::from(self.sp), value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(0x100 | u16::from(self.sp))
+    }
+
+    fn push16(&mut self, value: u16) {
+        let [lo, hi] = value.to_le_bytes();
+        self.push(hi);
+        self.push(lo);
+    }
+
+    fn pull16(&mut self) -> u16 {
+        let lo = self.pull();
+        u16::from_le_bytes([lo, self.pull()])
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(N, value & 0x80 != 0);
+        self.set_flag(Z, value == 0);
+    }
+
+    /// Returns the effective address of the operand and whether indexing
+    /// crossed a page, moving the program counter past the instruction.
+    fn operand(&mut self, mode: Mode) -> (u16, bool) {
+        let pc = self.pc.wrapping_add(1);
+        let byte = self.read(pc);
+        let word = self.read16(pc);
+        let indexed = |base: u16, index: u8| {
+            let addr = base.wrapping_add(u16::from(index));
+            (addr, addr & 0xff00 != base & 0xff00)
+        };
+        let (len, operand) = match mode {
+            Mode::Implied | Mode::Accumulator => (1, (0, false)),
+            Mode::Immediate => (2, (pc, false)),
+            Mode::ZeroPage => (2, (u16::from(byte), false)),
+            Mode::ZeroPageX => (2, (u16::from(byte.wrapping_add(self.x)), false)),
+            Mode::ZeroPageY => (2, (u16::from(byte.wrapping_add(self.y)), false)),
+            Mode::Absolute => (3, (word, false)),
+            Mode::AbsoluteX => (3, indexed(word, self.x)),
+            Mode::AbsoluteY => (3, indexed(word, self.y)),
+            Mode::Indirect => {
+                let hi = (word & 0xff00) | (word.wrapping_add(1) & 0x00ff);
+                let addr = u16::from_le_bytes([self.read(word), self.read(hi)]);
+                (3, (addr, false))
+            }
+            Mode::IndirectX => (2, (self.read16_zp(byte.wrapping_add(self.x)), false)),
+            Mode::IndirectY => (2, indexed(self.read16_zp(byte), self.y)),
+            Mode::Relative => {
+                let next = self.pc.wrapping_add(2);
+                let addr = next.wrapping_add(byte as i8 as u16);
+                (2, (addr, addr & 0xff00 != next & 0xff00))
+            }
+        };
+        self.pc = self.pc.wrapping_add(len);
+        operand
+    }
+
+    fn adc(&mut self, m: u8) {
+        let (a, c) = (self.a, self.p & C);
+        let binary = u16::from(a) + u16::from(m) + u16::from(c);
+        if self.p & D == 0 {
+            self.set_flag(C, binary > 0xff);
+            self.set_flag(V, !(a ^ m) & (a ^ binary as u8) & 0x80 != 0);
+            self.a = binary as u8;
+            self.set_nz(self.a);
+            return;
+        }
+
+        let mut al = i16::from(a & 0x0f) + i16::from(m & 0x0f) + i16::from(c);
+        if al >= 0x0a {
+            al = ((al + 0x06) & 0x0f) + 0x10;
+        }
+        let signed = i16::from((a & 0xf0) as i8) + i16::from((m & 0xf0) as i8) + al;
+        let mut sum = i16::from(a & 0xf0) + i16::from(m & 0xf0) + al;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.set_flag(N, signed & 0x80 != 0);
+        self.set_flag(V, !(-128..=127).contains(&signed));
+        self.set_flag(Z, binary & 0xff == 0);
+        self.set_flag(C, sum >= 0x100);
+        self.a = sum as u8;
+    }
+
+    fn sbc(&mut self, m: u8) {
+        let (a, c) = (self.a, self.p & C);
+        let decimal = self.p & D != 0;
+        // The flags are always those of the binary subtraction.
+        self.p &= !D;
+        self.adc(!m);
+        if decimal {
+            self.p |= D;
+            let mut al = i16::from(a & 0x0f) - i16::from(m & 0x0f) + i16::from(c) - 1;
+            if al < 0 {
+                al = ((al - 0x06) & 0x0f) - 0x10;
+            }
+            let mut diff = i16::from(a & 0xf0) - i16::from(m & 0xf0) + al;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            self.a = diff as u8;
+        }
+    }
+
+    fn compare(&mut self, reg: u8, m: u8) {
+        self.set_flag(C, reg >= m);
+        self.set_nz(reg.wrapping_sub(m));
+    }
+
+    /// Runs the shift or rotate selected by `aaa`: ASL, ROL, LSR or ROR.
+    fn shift(&mut self, aaa: u8, value: u8) -> u8 {
+        let carry = self.p & C;
+        let (result, out) = match aaa {
+            0 => (value << 1, value & 0x80),
+            1 => (value << 1 | carry, value & 0x80),
+            2 => (value >> 1, value & 0x01),
+            _ => (value >> 1 | carry << 7, value & 0x01),
+        };
+        self.set_flag(C, out != 0);
+        self.set_nz(result);
+        result
+    }
+
+    fn arr(&mut self, m: u8) {
+        let t = self.a & m;
+        let carry = self.p & C;
+        let mut a = t >> 1 | carry << 7;
+        if self.p & D == 0 {
+            self.set_nz(a);
+            self.set_flag(C, a & 0x40 != 0);
+            self.set_flag(V, (a >> 6 ^ a >> 5) & 1 != 0);
+        } else {
+            self.set_nz(a);
+            self.set_flag(V, (t ^ a) & 0x40 != 0);
+            if (t & 0x0f) + (t & 0x01) > 0x05 {
+                a = (a & 0xf0) | (a.wrapping_add(0x06) & 0x0f);
+            }
+            let high = u16::from(t & 0xf0) + u16::from(t & 0x10) > 0x50;
+            if high {
+                a = a.wrapping_add(0x60);
+            }
+            self.set_flag(C, high);
+        }
+        self.a = a;
+    }
+
+    /// Runs a single instruction, returning the number of cycles taken.
+    pub fn step(&mut self) -> u32 {
+        let op = self.read(self.pc);
+        let (aaa, bbb, cc) = (op >> 5, (op >> 2) & 7, op & 3);
+        let mode = mode(op);
+        let (addr, crossed) = self.operand(mode);
+        let m = self.read(addr);
+        let memory = !matches!(
+            mode,
+            Mode::Implied | Mode::Accumulator | Mode::Immediate | Mode::Relative
+        );
+        let default = || cycles(mode, kind(op), crossed);
+
+        match (cc, aaa, bbb) {
+            // Branches.
+            (0, _, 4) => {
+                let flag = [N, V, C, Z][usize::from(aaa >> 1)];
+                if (self.p & flag != 0) == (aaa & 1 != 0) {
+                    self.pc = addr;
+                    return 3 + u32::from(crossed);
+                }
+                2
+            }
+            // BRK, JSR, RTI, RTS.
+            (0, 0, 0) => {
+                self.push16(self.pc.wrapping_add(1));
+                self.push(self.p | STACK_ONLY);
+                self.p |= I;
+                self.pc = self.read16(0xfffe);
+                7
+            }
+            (0, 1, 0) => {
+                // The high byte of the target is read after pushing, so the
+                // push can overwrite it when the stack runs into the JSR.
+                let last = self.pc.wrapping_sub(1);
+                self.push16(last);
+                self.pc = u16::from_le_bytes([addr as u8, self.read(last)]);
+                6
+            }
+            (0, 2, 0) => {
+                self.p = self.pull() & !STACK_ONLY;
+                self.pc = self.pull16();
+                6
+            }
+            (0, 3, 0) => {
+                self.pc = self.pull16().wrapping_add(1);
+                6
+            }
+            // JMP.
+            (0, 2 | 3, 3) => {
+                self.pc = addr;
+                if mode == Mode::Indirect {
+                    5
+                } else {
+                    3
+                }
+            }
+            // PHP, PLP, PHA, PLA.
+            (0, 0, 2) => {
+                self.push(self.p | STACK_ONLY);
+                3
+            }
+            (0, 1, 2) => {
+                self.p = self.pull() & !STACK_ONLY;
+                4
+            }
+            (0, 2, 2) => {
+                self.push(self.a);
+                3
+            }
+            (0, 3, 2) => {
+                self.a = self.pull();
+                self.set_nz(self.a);
+                4
+            }
+            // Register transfers, increments and flag changes.
+            (0, _, 2) => {
+                match aaa {
+                    4 => self.y = self.y.wrapping_sub(1),
+                    5 => self.y = self.a,
+                    6 => self.y = self.y.wrapping_add(1),
+                    _ => self.x = self.x.wrapping_add(1),
+                }
+                let value = if aaa == 7 { self.x } else { self.y };
+                self.set_nz(value);
+                2
+            }
+            (0, _, 6) => {
+                match aaa {
+                    4 => {
+                        self.a = self.y;
+                        self.set_nz(self.a);
+                    }
+                    5 => self.p &= !V,
+                    _ => self.set_flag([C, I, V, D][usize::from(aaa >> 1)], aaa & 1 != 0),
+                }
+                2
+            }
+            (2, 4, 2) => {
+                self.a = self.x;
+                self.set_nz(self.a);
+                2
+            }
+            (2, 5, 2) => {
+                self.x = self.a;
+                self.set_nz(self.x);
+                2
+            }
+            (2, 6, 2) => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_nz(self.x);
+                2
+            }
+            (2, 4, 6) => {
+                self.sp = self.x;
+                2
+            }
+            (2, 5, 6) => {
+                self.x = self.sp;
+                self.set_nz(self.x);
+                2
+            }
+            // ASL, ROL, LSR and ROR of the accumulator.
+            (2, 0..=3, 2) => {
+                self.a = self.shift(aaa, self.a);
+                2
+            }
+            // Stores, including SAX, and the loads and compares of X and Y.
+            (0, 4, _) if memory => {
+                self.write(addr, self.y);
+                default()
+            }
+            (2, 4, _) if memory => {
+                self.write(addr, self.x);
+                default()
+            }
+            (3, 4, _) if memory => {
+                self.write(addr, self.a & self.x);
+                default()
+            }
+            (0, 5, _) => {
+                self.y = m;
+                self.set_nz(m);
+                default()
+            }
+            (2, 5, _) => {
+                self.x = m;
+                self.set_nz(m);
+                default()
+            }
+            (0, 6, 0 | 1 | 3) => {
+                self.compare(self.y, m);
+                default()
+            }
+            (0, 7, 0 | 1 | 3) => {
+                self.compare(self.x, m);
+                default()
+            }
+            (0, 1, 1 | 3) => {
+                self.set_flag(Z, self.a & m == 0);
+                self.set_flag(N, m & 0x80 != 0);
+                self.set_flag(V, m & 0x40 != 0);
+                default()
+            }
+            // The accumulator operations, with STA #imm being a NOP.
+            (1, 4, 2) => default(),
+            (1, _, _) => {
+                match aaa {
+                    0 => self.a |= m,
+                    1 => self.a &= m,
+                    2 => self.a ^= m,
+                    3 => self.adc(m),
+                    4 => self.write(addr, self.a),
+                    5 => self.a = m,
+                    6 => self.compare(self.a, m),
+                    _ => self.sbc(m),
+                }
+                if !matches!(aaa, 3 | 4 | 6 | 7) {
+                    self.set_nz(self.a);
+                }
+                default()
+            }
+            // ASL, ROL, LSR, ROR, DEC and INC of memory, and the illegal
+            // opcodes combining them with an accumulator operation.
+            (2 | 3, 0..=3 | 6 | 7, _) if memory => {
+                let value = match aaa {
+                    0..=3 => self.shift(aaa, m),
+                    6 => m.wrapping_sub(1),
+                    _ => m.wrapping_add(1),
+                };
+                self.write(addr, value);
+                if cc == 2 {
+                    if aaa >= 6 {
+                        self.set_nz(value);
+                    }
+                } else {
+                    match aaa {
+                        0 => self.a |= value,
+                        1 => self.a &= value,
+                        2 => self.a ^= value,
+                        3 => self.adc(value),
+                        6 => self.compare(self.a, value),
+                        _ => self.sbc(value),
+                    }
+                    if aaa <= 2 {
+                        self.set_nz(self.a);
+                    }
+                }
+                default()
+            }
+            // LAX and LAS.
+            (3, 5, 6) => {
+                let value = m & self.sp;
+                self.a = value;
+                self.x = value;
+                self.sp = value;
+                self.set_nz(value);
+                default()
+            }
+            (3, 5, _) => {
+                self.a = m;
+                self.x = m;
+                self.set_nz(m);
+                default()
+            }
+            // ANC, ALR, ARR, SBX and SBC #imm.
+            (3, 0 | 1, 2) => {
+                self.a &= m;
+                self.set_nz(self.a);
+                self.set_flag(C, self.a & 0x80 != 0);
+                2
+            }
+            (3, 2, 2) => {
+                self.a = self.shift(2, self.a & m);
+                2
+            }
+            (3, 3, 2) => {
+                self.arr(m);
+                2
+            }
+            (3, 6, 2) => {
+                let value = self.a & self.x;
+                self.compare(value, m);
+                self.x = value.wrapping_sub(m);
+                2
+            }
+            (3, 7, 2) => {
+                self.sbc(m);
+                2
+            }
+            // What is left are the many NOPs.
+            _ => default(),
+        }
+    }
+}
+
+/// Runs the instruction sequence described by `data` on both the cycle-level
+/// core and the reference model, returning a description of the first
+/// difference found.
+///
+/// The first 8 bytes seed the random contents of memory and the next 7 give
+/// the registers. Every 3 bytes after that select an opcode and its operand
+/// bytes, which are written at the program counter before each instruction.
+pub fn compare(data: &[u8]) -> Result<(), String> {
+    let mut bytes = data.iter().copied();
+    let mut next = || bytes.next().unwrap_or(0);
+
+    let mut rng = Rng(u64::from_le_bytes([(); 8].map(|_| next())));
+    let mem: Vec<u8> = (0..0x10000).map(|_| rng.next_u64() as u8).collect();
+    let mut reference = Reference {
+        a: next(),
+        x: next(),
+        y: next(),
+        sp: next(),
+        p: next() & !STACK_ONLY,
+        pc: u16::from_le_bytes([next(), next()]),
+        mem: mem.clone(),
+    };
+    let mut cpu = CycleTicker::new(M6502 {
+        a: reference.a,
+        x: reference.x,
+        y: reference.y,
+        sp: reference.sp,
+        pc: reference.pc,
+        flags: reference.p,
+    });
+    let mut mem = mem;
+
+    let supported: Vec<u8> = (0..=255).filter(|&op| is_supported(op)).collect();
+    let count = data.len().saturating_sub(15) / 3;
+    for _ in 0..count {
+        let pc = reference.pc;
+        let op = supported[usize::from(next()) % supported.len()];
+        let instr = [op, next(), next()];
+        for (addr, value) in (0..).map(|i| pc.wrapping_add(i)).zip(instr) {
+            reference.write(addr, value);
+            mem[usize::from(addr)] = value;
+        }
+
+        let start = cpu.cycles();
+        cpu.step(&mut mem);
+        let cycles = cpu.cycles() - start;
+        let expected_cycles = u64::from(reference.step());
+
+        let s = cpu.state();
+        let regs = (s.a, s.x, s.y, s.sp, s.flags, s.pc);
+        let r = &reference;
+        let expected_regs = (r.a, r.x, r.y, r.sp, r.p, r.pc);
+        let at = format!("{:02X?} at ${pc:04X}", instr);
+        if regs != expected_regs {
+            return Err(format!(
+                "{at}: registers (a, x, y, sp, p, pc) {regs:02X?}, expected {expected_regs:02X?}"
+            ));
+        }
+        if cycles != expected_cycles {
+            return Err(format!("{at}: {cycles} cycles, expected {expected_cycles}"));
+        }
+        if mem == reference.mem {
+            continue;
+        }
+        if let Some(addr) = (0..mem.len()).find(|&i| mem[i] != reference.mem[i]) {
+            return Err(format!(
+                "{at}: ${addr:04X} = {:02X}, expected {:02X}",
+                mem[addr], reference.mem[addr]
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+//! Runs the per-opcode JSON tests of the SingleStepTests/ProcessorTests 6502
+//! dataset through the cycle-level core.
+//!
+//! Point the `SINGLE_STEP_TESTS` environment variable at the directory with
+//! the `xx.json` files of the dataset, e.g. `65x02/6502/v1`, to run all of
+//! them. A file missing from it fails the test. Without it every file in
+//! `tests/data/single_step` is run instead, a few hand-checked cases in the
+//! same format. A file that cannot be read or parsed fails the test either
+//! way.
+//!
+//! Each test checks the registers, the RAM listed in the final state and
+//! every bus access made, cycle by cycle. The `JAM` opcodes are skipped since
+//! the bus activity of a halted processor is not modelled.
+
+use std::{env, fs, path::PathBuf};
+
+use fast6502::{
+    memory::{trace::BusEvent, trace::TraceMemory, Access},
+    CycleTicker, Mnemonic, Opcode, M6502,
+};
+use serde::Deserialize;
+
+/// Maximum number of failures reported.
+const MAX_REPORTED: usize = 20;
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+/// Returns the files to run: one per opcode from the dataset, or all files
+/// of the hand-checked cases.
+fn test_files() -> Vec<PathBuf> {
+    if let Some(dir) = env::var_os("SINGLE_STEP_TESTS") {
+        let dir = PathBuf::from(dir);
+        return Opcode::iter()
+            .filter(|op| op.mnemonic() != Mnemonic::JAM)
+            .map(|op| dir.join(format!("{:02x}.json", op.to_byte())))
+            .collect();
+    }
+
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data/single_step");
+    let entries = fs::read_dir(&dir).unwrap_or_else(|e| panic!("{}: {e}", dir.display()));
+    let mut files: Vec<PathBuf> = entries
+        .map(|entry| {
+            entry
+                .unwrap_or_else(|e| panic!("{}: {e}", dir.display()))
+                .path()
+        })
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Runs a single test, returning a description of the first mismatch.
+fn run(test: &Test) -> Result<(), String> {
+    let mut ram = vec![0u8; 0x10000];
+    for &(addr, value) in &test.initial.ram {
+        ram[usize::from(addr)] = value;
+    }
+
+    let init = &test.initial;
+    let mut state = M6502::new();
+    state.pc = init.pc;
+    state.sp = init.s;
+    state.a = init.a;
+    state.x = init.x;
+    state.y = init.y;
+    state.flags = init.p & !0x30;
+
+    let mut cpu = CycleTicker::new(state);
+    let mut mem = TraceMemory::new(ram, Vec::<BusEvent>::new());
+    cpu.step(&mut mem);
+    let (ram, bus) = mem.into_parts();
+
+    let bus: Vec<(u16, u8, &str)> = bus
+        .iter()
+        .map(|e| {
+            let kind = match e.access {
+                Access::Write => "write",
+                Access::Read | Access::Execute => "read",
+            };
+            (e.addr, e.value, kind)
+        })
+        .collect();
+    let expected_bus: Vec<(u16, u8, &str)> = test
+        .cycles
+        .iter()
+        .map(|(addr, value, kind)| (*addr, *value, kind.as_str()))
+        .collect();
+    if bus != expected_bus {
+        return Err(format!("bus {bus:?}, expected {expected_bus:?}"));
+    }
+
+    let s = cpu.state();
+    let exp = &test.expected;
+    let regs = (s.pc, s.sp, s.a, s.x, s.y, s.flags);
+    let expected_regs = (exp.pc, exp.s, exp.a, exp.x, exp.y, exp.p & !0x30);
+    if regs != expected_regs {
+        return Err(format!(
+            "registers (pc, s, a, x, y, p) {regs:02X?}, expected {expected_regs:02X?}"
+        ));
+    }
+
+    for &(addr, value) in &exp.ram {
+        let actual = ram[usize::from(addr)];
+        if actual != value {
+            return Err(format!(
+                "ram ${addr:04X} = {actual:02X}, expected {value:02X}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn single_step_tests() {
+    let mut failures = Vec::new();
+    let mut count = 0;
+
+    for path in test_files() {
+        let loaded = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<Vec<Test>>(&json).map_err(|e| e.to_string()));
+        let tests = match loaded {
+            Ok(tests) => tests,
+            Err(e) => {
+                failures.push(format!("{}: {e}", path.display()));
+                continue;
+            }
+        };
+
+        for test in &tests {
+            count += 1;
+            if let Err(msg) = run(test) {
+                failures.push(format!("{}: {msg}", test.name));
+            }
+        }
+    }
+
+    assert!(count > 0 || !failures.is_empty(), "no tests found");
+    assert!(
+        failures.is_empty(),
+        "{} failures with {count} tests run:\n{}",
+        failures.len(),
+        failures[..failures.len().min(MAX_REPORTED)].join("\n")
+    );
+}